@@ -0,0 +1,270 @@
+use super::rasterizer::Rasterizer;
+use super::Light;
+use nalgebra::{Matrix4, UnitQuaternion, Vector3};
+
+type Vector3f = Vector3<f32>;
+
+/// 关键帧之间的插值 向量用 lerp 旋转用 slerp
+pub trait Interpolate {
+    fn interpolate(&self, other: &Self, t: f32) -> Self;
+}
+
+impl Interpolate for f32 {
+    fn interpolate(&self, other: &Self, t: f32) -> Self {
+        self + (other - self) * t
+    }
+}
+
+impl Interpolate for Vector3f {
+    fn interpolate(&self, other: &Self, t: f32) -> Self {
+        self.lerp(other, t)
+    }
+}
+
+impl Interpolate for UnitQuaternion<f32> {
+    fn interpolate(&self, other: &Self, t: f32) -> Self {
+        self.slerp(other, t)
+    }
+}
+
+/// 平移 旋转 缩放 矩阵为 T * R * S
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+    pub translation: Vector3f,
+    pub rotation: UnitQuaternion<f32>,
+    pub scale: Vector3f,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            translation: nalgebra::zero(),
+            rotation: UnitQuaternion::identity(),
+            scale: Vector3f::from_element(1f32),
+        }
+    }
+}
+
+impl Transform {
+    pub fn new(translation: Vector3f, rotation: UnitQuaternion<f32>, scale: Vector3f) -> Self {
+        Self {
+            translation,
+            rotation,
+            scale,
+        }
+    }
+
+    pub fn from_translation(translation: Vector3f) -> Self {
+        Self {
+            translation,
+            ..Default::default()
+        }
+    }
+
+    pub fn matrix(&self) -> Matrix4<f32> {
+        Matrix4::new_translation(&self.translation)
+            * self.rotation.to_homogeneous()
+            * Matrix4::new_nonuniform_scaling(&self.scale)
+    }
+}
+
+impl Interpolate for Transform {
+    fn interpolate(&self, other: &Self, t: f32) -> Self {
+        Self {
+            translation: self.translation.interpolate(&other.translation, t),
+            rotation: self.rotation.interpolate(&other.rotation, t),
+            scale: self.scale.interpolate(&other.scale, t),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Keyframe<T> {
+    /// 单位为秒
+    pub time: f32,
+    pub value: T,
+}
+
+/// 按时间排序的关键帧 两端之外保持第一帧或最后一帧的值
+#[derive(Debug, Clone)]
+pub struct Track<T> {
+    keys: Vec<Keyframe<T>>,
+}
+
+impl<T: Interpolate + Clone> Track<T> {
+    pub fn new() -> Self {
+        Self { keys: vec![] }
+    }
+
+    pub fn constant(value: T) -> Self {
+        let mut ret = Self::new();
+        ret.insert(0f32, value);
+        ret
+    }
+
+    pub fn insert(&mut self, time: f32, value: T) -> &mut Self {
+        let index = self
+            .keys
+            .iter()
+            .position(|k| k.time > time)
+            .unwrap_or_else(|| self.keys.len());
+        self.keys.insert(index, Keyframe { time, value });
+        self
+    }
+
+    pub fn keys(&self) -> &[Keyframe<T>] {
+        &self.keys
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.keys.last().map_or(0f32, |k| k.time)
+    }
+
+    pub fn sample(&self, time: f32) -> Option<T> {
+        let first = self.keys.first()?;
+        if time <= first.time {
+            return Some(first.value.clone());
+        }
+        for pair in self.keys.windows(2) {
+            let (a, b) = (&pair[0], &pair[1]);
+            if time <= b.time {
+                let t = (time - a.time) / (b.time - a.time).max(f32::EPSILON);
+                return Some(a.value.interpolate(&b.value, t));
+            }
+        }
+        self.keys.last().map(|k| k.value.clone())
+    }
+}
+
+impl<T: Interpolate + Clone> Default for Track<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 位置随时间变化的点光源 位置在世界空间
+#[derive(Debug, Clone)]
+pub struct LightTrack {
+    pub position: Track<Vector3f>,
+    pub intensity: Vector3f,
+}
+
+/// 某一时刻的相机 模型和光源
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub view: Matrix4<f32>,
+    pub model: Matrix4<f32>,
+    /// 世界空间的光源
+    pub lights: Vec<Light>,
+}
+
+impl Frame {
+    pub fn apply(&self, r: &mut Rasterizer) {
+        r.set_view(&self.view);
+        r.set_model(&self.model);
+        r.set_lights(self.lights.clone());
+    }
+}
+
+/// 相机 模型和光源的关键帧动画 相机的变换是相机到世界的变换
+#[derive(Debug, Clone, Default)]
+pub struct Timeline {
+    pub camera: Track<Transform>,
+    pub model: Track<Transform>,
+    pub lights: Vec<LightTrack>,
+}
+
+impl Timeline {
+    /// 所有轨道中最晚的关键帧
+    pub fn duration(&self) -> f32 {
+        self.lights
+            .iter()
+            .map(|l| l.position.duration())
+            .fold(self.camera.duration().max(self.model.duration()), f32::max)
+    }
+
+    /// 相机在 eye 处看向 -z 模型绕 y 轴转一圈 光源不动
+    pub fn turntable(eye: Vector3f, model: Transform, duration: f32) -> Self {
+        let mut ret = Self {
+            camera: Track::constant(Transform::from_translation(eye)),
+            ..Default::default()
+        };
+        // 相邻关键帧相差 90 度 slerp 不会走反方向
+        for i in 0..=4 {
+            let angle = std::f32::consts::FRAC_PI_2 * i as f32;
+            let rotation = UnitQuaternion::from_axis_angle(&Vector3f::y_axis(), angle);
+            ret.model.insert(
+                duration * i as f32 / 4f32,
+                Transform {
+                    rotation: rotation * model.rotation,
+                    ..model
+                },
+            );
+        }
+        ret
+    }
+
+    pub fn frame(&self, time: f32) -> Frame {
+        let camera = self.camera.sample(time).unwrap_or_default();
+        let model = self.model.sample(time).unwrap_or_default();
+        Frame {
+            view: camera
+                .matrix()
+                .try_inverse()
+                .unwrap_or_else(Matrix4::identity),
+            model: model.matrix(),
+            lights: self
+                .lights
+                .iter()
+                .filter_map(|l| l.position.sample(time).map(|p| Light::new(p, l.intensity)))
+                .collect(),
+        }
+    }
+
+    /// 把整个时间线均匀分成 count 帧 不包含终点 首尾相接可以循环播放
+    pub fn frames(&self, count: usize) -> impl Iterator<Item = Frame> + '_ {
+        let duration = self.duration();
+        (0..count).map(move |i| self.frame(duration * i as f32 / count.max(1) as f32))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sample_clamps_outside_the_keys() {
+        assert_eq!(Track::<f32>::new().sample(0f32), None);
+        let mut track = Track::new();
+        track.insert(2f32, 10f32).insert(1f32, 4f32);
+        assert_eq!(track.keys()[0].time, 1f32);
+        assert_eq!(track.sample(0f32), Some(4f32));
+        assert_eq!(track.sample(3f32), Some(10f32));
+        assert_eq!(track.sample(1.5), Some(7f32));
+    }
+
+    #[test]
+    fn sample_lerps_vectors() {
+        let mut track = Track::new();
+        track
+            .insert(0f32, Vector3f::new(0f32, 0f32, 0f32))
+            .insert(4f32, Vector3f::new(4f32, -8f32, 2f32));
+        let v = track.sample(1f32).unwrap();
+        assert!((v - Vector3f::new(1f32, -2f32, 0.5)).magnitude() < 1e-6);
+    }
+
+    // 沿最短的弧插值 角度和时间成正比
+    #[test]
+    fn sample_slerps_rotations() {
+        let axis = Vector3f::y_axis();
+        let mut track = Track::new();
+        track
+            .insert(0f32, UnitQuaternion::identity())
+            .insert(1f32, UnitQuaternion::from_axis_angle(&axis, 2f32));
+        for &t in &[0.25f32, 0.5, 0.75] {
+            let q = track.sample(t).unwrap();
+            let expected = UnitQuaternion::from_axis_angle(&axis, 2f32 * t);
+            assert!(q.angle_to(&expected) < 1e-5, "t = {}", t);
+        }
+    }
+}
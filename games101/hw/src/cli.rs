@@ -0,0 +1,544 @@
+use super::fog::{Fog, FogMode};
+use super::shader::FragmentShaderPayload;
+use nalgebra::Vector3;
+use std::fmt;
+
+type Vector3f = Vector3<f32>;
+
+/// 可以选择的片元着色器
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Shader {
+    Normal,
+    Phong,
+    Texture,
+    Bump,
+    Displacement,
+    Environment,
+    Pbr,
+    Toon,
+}
+
+impl Shader {
+    pub fn all() -> [Shader; 8] {
+        [
+            Shader::Normal,
+            Shader::Phong,
+            Shader::Texture,
+            Shader::Bump,
+            Shader::Displacement,
+            Shader::Environment,
+            Shader::Pbr,
+            Shader::Toon,
+        ]
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Shader::Normal => "normal",
+            Shader::Phong => "phong",
+            Shader::Texture => "texture",
+            Shader::Bump => "bump",
+            Shader::Displacement => "displacement",
+            Shader::Environment => "environment",
+            Shader::Pbr => "pbr",
+            Shader::Toon => "toon",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::all().iter().copied().find(|s| s.name() == name)
+    }
+
+    pub fn function(&self) -> fn(&FragmentShaderPayload) -> Vector3f {
+        match self {
+            Shader::Normal => super::normal_fragment_shader,
+            Shader::Phong => super::phone_fragment_shader,
+            Shader::Texture => super::texture_fragment_shader,
+            Shader::Bump => super::bump_fragment_shader,
+            Shader::Displacement => super::displacement_fragment_shader,
+            Shader::Environment => super::environment_fragment_shader,
+            Shader::Pbr => super::pbr_fragment_shader,
+            Shader::Toon => super::toon_fragment_shader,
+        }
+    }
+
+    /// 没有指定 --texture 时使用的贴图 相对模型所在的目录
+    pub fn default_texture(&self) -> &'static str {
+        match self {
+            Shader::Texture | Shader::Pbr | Shader::Toon => "spot_texture_low.png",
+            _ => "hmap.jpg",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pipeline {
+    Forward,
+    Deferred,
+    Ssao,
+    Ssr,
+}
+
+impl Pipeline {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "forward" => Some(Pipeline::Forward),
+            "deferred" => Some(Pipeline::Deferred),
+            "ssao" => Some(Pipeline::Ssao),
+            "ssr" => Some(Pipeline::Ssr),
+            _ => None,
+        }
+    }
+}
+
+/// 渲染程序的命令行参数
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+    pub model: String,
+    /// 为 None 时按着色器从模型目录中选择
+    pub texture: Option<String>,
+    pub width: i32,
+    pub height: i32,
+    /// 模型绕 y 轴旋转的角度
+    pub angle: f32,
+    pub eye: Vector3f,
+    pub shader: Shader,
+    pub msaa: usize,
+    pub pipeline: Pipeline,
+    /// 使用标准的后处理链 否则只截断颜色
+    pub post_process: bool,
+    /// 描边的宽度 (像素) toon 着色器为 None 时使用默认宽度
+    pub outline: Option<f32>,
+    pub fog: Option<Fog>,
+    /// 为 None 时打开窗口交互
+    pub output: Option<String>,
+    /// 每个着色器各渲染一张 文件名加上着色器的名字
+    pub all_shaders: bool,
+    /// 转台动画的帧数
+    pub turntable: Option<usize>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            model: "./models/spot/spot_triangulated_good.obj".to_owned(),
+            texture: None,
+            width: 700,
+            height: 700,
+            angle: 140f32,
+            eye: Vector3f::new(0f32, 0f32, 10f32),
+            shader: Shader::Phong,
+            msaa: 1,
+            pipeline: Pipeline::Forward,
+            post_process: false,
+            outline: None,
+            fog: None,
+            output: None,
+            all_shaders: false,
+            turntable: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CliError {
+    /// 请求打印帮助 不算真正的错误
+    Help,
+    UnknownOption(String),
+    MissingValue(String),
+    InvalidValue {
+        option: String,
+        value: String,
+        expected: String,
+    },
+    /// 参数之间的冲突
+    Conflict(String),
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::Help => write!(f, "help requested"),
+            CliError::UnknownOption(option) => write!(f, "unknown option '{}'", option),
+            CliError::MissingValue(option) => write!(f, "option '{}' needs a value", option),
+            CliError::InvalidValue {
+                option,
+                value,
+                expected,
+            } => write!(
+                f,
+                "invalid value '{}' for '{}': expected {}",
+                value, option, expected
+            ),
+            CliError::Conflict(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for CliError {}
+
+pub fn usage(program: &str) -> String {
+    let shaders: Vec<_> = Shader::all().iter().map(|s| s.name()).collect();
+    format!(
+        "usage: {} [options]
+
+options:
+    -m, --model <path>        obj file to render [./models/spot/spot_triangulated_good.obj]
+    -t, --texture <path>      texture bound when the material has none
+                              [spot_texture_low.png or hmap.jpg next to the model]
+    -s, --size <w>x<h>        image size, a single number for a square [700]
+        --angle <degrees>     rotation of the model around y [140]
+        --eye <x,y,z>         camera position, looking down -z [0,0,10]
+        --shader <name>       one of {} [phong]
+        --all-shaders         render every shader, appending its name to the output file
+        --msaa <1|2|4>        samples per pixel [1]
+        --pipeline <name>     forward, deferred, ssao or ssr [forward]
+        --post-process        tone map, sRGB encode and FXAA the output
+        --outline <width>     draw ink outlines from depth and normal edges
+                              (always on for the toon shader) [2]
+        --fog <mode>          linear, exp, exp2 or height fog in the built-in shaders
+        --fog-density <d>     fog density, linear fog is opaque at 1/d [0.05]
+        --fog-color <r,g,b>   fog colour in linear rgb, also the background [0.5,0.6,0.7]
+        --turntable <frames>  render a turntable animation as a png sequence
+                              (and a gif when the output ends with .gif)
+    -o, --output <file>       write the image instead of opening a window
+    -h, --help                print this message",
+        program,
+        shaders.join(", ")
+    )
+}
+
+fn invalid(option: &str, value: &str, expected: &str) -> CliError {
+    CliError::InvalidValue {
+        option: option.to_owned(),
+        value: value.to_owned(),
+        expected: expected.to_owned(),
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(option: &str, value: &str) -> Result<T, CliError> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| invalid(option, value, "a number"))
+}
+
+// f32 的 parse 接受 NaN 和 inf 这里一并拒绝
+fn parse_float(option: &str, value: &str) -> Result<f32, CliError> {
+    match parse_number::<f32>(option, value)? {
+        v if v.is_finite() => Ok(v),
+        _ => Err(invalid(option, value, "a finite number")),
+    }
+}
+
+fn parse_size(option: &str, value: &str) -> Result<(i32, i32), CliError> {
+    let expected = "<width>x<height> or a single positive number";
+    let mut parts = value.splitn(2, |c| c == 'x' || c == 'X');
+    let width: i32 = parse_number(option, parts.next().unwrap_or_default())
+        .map_err(|_| invalid(option, value, expected))?;
+    let height = match parts.next() {
+        Some(h) => parse_number(option, h).map_err(|_| invalid(option, value, expected))?,
+        None => width,
+    };
+    if width <= 0 || height <= 0 || width > 16384 || height > 16384 {
+        return Err(invalid(option, value, "a size between 1 and 16384"));
+    }
+    Ok((width, height))
+}
+
+fn parse_vector(option: &str, value: &str) -> Result<Vector3f, CliError> {
+    let parts: Vec<_> = value.split(',').collect();
+    if parts.len() != 3 {
+        return Err(invalid(option, value, "three comma separated numbers"));
+    }
+    let mut ret = Vector3f::zeros();
+    for (i, part) in parts.iter().enumerate() {
+        ret[i] = parse_float(option, part)
+            .map_err(|_| invalid(option, value, "three comma separated numbers"))?;
+    }
+    Ok(ret)
+}
+
+// 线性雾在 1 / density 处完全不透明 高度雾以模型所在的 y = 0 为基准
+fn fog_mode(name: &str, density: f32) -> Option<FogMode> {
+    match name {
+        "linear" => Some(FogMode::Linear {
+            start: 0f32,
+            end: 1f32 / density,
+        }),
+        "exp" => Some(FogMode::Exponential),
+        "exp2" => Some(FogMode::ExponentialSquared),
+        "height" => Some(FogMode::Height {
+            base: 0f32,
+            falloff: 1f32,
+        }),
+        _ => None,
+    }
+}
+
+// 开关类的参数不能带 =value
+fn no_value(option: &str, inline: &Option<String>) -> Result<(), CliError> {
+    match inline {
+        Some(value) => Err(invalid(option, value, "no value")),
+        None => Ok(()),
+    }
+}
+
+/// args 不包含程序名
+pub fn parse<I, S>(args: I) -> Result<Options, CliError>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    let mut options = Options::default();
+    // --fog 的几个参数顺序任意 最后再组合
+    let (mut fog, mut fog_density, mut fog_color) = (None, None, None);
+    let mut args = args.into_iter().map(|s| s.as_ref().to_owned());
+    while let Some(arg) = args.next() {
+        // 同时支持 --name value 和 --name=value
+        let (option, inline) = match arg.find('=') {
+            Some(i) if arg.starts_with("--") => {
+                (arg[..i].to_owned(), Some(arg[i + 1..].to_owned()))
+            }
+            _ => (arg.clone(), None),
+        };
+        let mut value = || {
+            inline
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| CliError::MissingValue(option.clone()))
+        };
+        match &option[..] {
+            "-h" | "--help" => return Err(CliError::Help),
+            "-m" | "--model" => options.model = value()?,
+            "-t" | "--texture" => options.texture = Some(value()?),
+            "-s" | "--size" => {
+                let (width, height) = parse_size(&option, &value()?)?;
+                options.width = width;
+                options.height = height;
+            }
+            "--angle" => options.angle = parse_float(&option, &value()?)?,
+            "--eye" => options.eye = parse_vector(&option, &value()?)?,
+            "--shader" => {
+                let name = value()?;
+                let names: Vec<_> = Shader::all().iter().map(|s| s.name()).collect();
+                options.shader = Shader::from_name(&name).ok_or_else(|| {
+                    invalid(&option, &name, &format!("one of {}", names.join(", ")))
+                })?;
+            }
+            "--all-shaders" => {
+                no_value(&option, &inline)?;
+                options.all_shaders = true;
+            }
+            "--msaa" => {
+                let v = value()?;
+                options.msaa = match parse_number(&option, &v) {
+                    Ok(n @ 1) | Ok(n @ 2) | Ok(n @ 4) => n,
+                    _ => return Err(invalid(&option, &v, "1, 2 or 4")),
+                };
+            }
+            "--pipeline" => {
+                let v = value()?;
+                options.pipeline = Pipeline::from_name(&v)
+                    .ok_or_else(|| invalid(&option, &v, "forward, deferred, ssao or ssr"))?;
+            }
+            "--post-process" => {
+                no_value(&option, &inline)?;
+                options.post_process = true;
+            }
+            "--outline" => {
+                let v = value()?;
+                options.outline = match parse_float(&option, &v) {
+                    Ok(width) if width > 0f32 => Some(width),
+                    _ => return Err(invalid(&option, &v, "a positive width")),
+                };
+            }
+            "--fog" => {
+                let v = value()?;
+                fog_mode(&v, 1f32)
+                    .ok_or_else(|| invalid(&option, &v, "linear, exp, exp2 or height"))?;
+                fog = Some(v);
+            }
+            "--fog-density" => {
+                let v = value()?;
+                fog_density = match parse_float(&option, &v) {
+                    Ok(d) if d > 0f32 => Some(d),
+                    _ => return Err(invalid(&option, &v, "a positive number")),
+                };
+            }
+            "--fog-color" => fog_color = Some(parse_vector(&option, &value()?)?),
+            "--turntable" => {
+                let v = value()?;
+                options.turntable = match parse_number(&option, &v) {
+                    Ok(n) if n > 0 => Some(n),
+                    _ => return Err(invalid(&option, &v, "a positive number of frames")),
+                };
+            }
+            "-o" | "--output" => options.output = Some(value()?),
+            _ => return Err(CliError::UnknownOption(arg)),
+        }
+    }
+
+    match fog {
+        Some(name) => {
+            let default = Fog::default();
+            let density = fog_density.unwrap_or(default.density);
+            options.fog = Some(Fog::new(
+                fog_mode(&name, density).unwrap(),
+                fog_color.unwrap_or(default.color),
+                density,
+            ));
+        }
+        None if fog_density.is_some() || fog_color.is_some() => {
+            return Err(CliError::Conflict(
+                "--fog-density and --fog-color need --fog".to_owned(),
+            ));
+        }
+        None => {}
+    }
+    if options.output.is_none() && (options.all_shaders || options.turntable.is_some()) {
+        return Err(CliError::Conflict(
+            "--all-shaders and --turntable need an --output file".to_owned(),
+        ));
+    }
+    if options.all_shaders && options.turntable.is_some() {
+        return Err(CliError::Conflict(
+            "--all-shaders can not be combined with --turntable".to_owned(),
+        ));
+    }
+    Ok(options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn invalid_option(args: &[&str]) -> String {
+        match parse(args) {
+            Err(CliError::InvalidValue { option, .. }) => option,
+            other => panic!("{:?} should be an invalid value, got {:?}", args, other),
+        }
+    }
+
+    #[test]
+    fn defaults_without_arguments() {
+        assert_eq!(parse(&[] as &[&str]), Ok(Options::default()));
+    }
+
+    #[test]
+    fn values_inline_and_separate() {
+        let options = parse(&[
+            "--size=320x200",
+            "--msaa",
+            "4",
+            "--eye",
+            "1, 2,3",
+            "--shader=pbr",
+            "-o",
+            "out.png",
+        ])
+        .unwrap();
+        assert_eq!((options.width, options.height), (320, 200));
+        assert_eq!(options.msaa, 4);
+        assert_eq!(options.eye, Vector3f::new(1f32, 2f32, 3f32));
+        assert_eq!(options.shader, Shader::Pbr);
+        assert_eq!(options.output.as_deref(), Some("out.png"));
+        assert_eq!(
+            parse(&["-s", "64"]).map(|o| (o.width, o.height)),
+            Ok((64, 64))
+        );
+    }
+
+    #[test]
+    fn bad_sizes() {
+        for size in &["0", "10x", "x10", "-5", "10x0", "100000", "axb"] {
+            assert_eq!(invalid_option(&["--size", size]), "--size", "{}", size);
+        }
+    }
+
+    #[test]
+    fn bad_numbers() {
+        assert_eq!(invalid_option(&["--msaa", "3"]), "--msaa");
+        assert_eq!(invalid_option(&["--msaa", "two"]), "--msaa");
+        assert_eq!(invalid_option(&["--eye", "1,2"]), "--eye");
+        assert_eq!(invalid_option(&["--eye", "1,2,z"]), "--eye");
+        assert_eq!(invalid_option(&["--angle", "north"]), "--angle");
+        assert_eq!(invalid_option(&["--shader", "wireframe"]), "--shader");
+        assert_eq!(invalid_option(&["--turntable", "0"]), "--turntable");
+    }
+
+    #[test]
+    fn non_finite_numbers() {
+        for v in &["NaN", "inf", "-inf", "infinity", "1e39"] {
+            assert_eq!(invalid_option(&["--angle", v]), "--angle", "{}", v);
+            assert_eq!(invalid_option(&["--outline", v]), "--outline", "{}", v);
+            assert_eq!(
+                invalid_option(&["--fog-density", v]),
+                "--fog-density",
+                "{}",
+                v
+            );
+            let eye = format!("0,{},10", v);
+            assert_eq!(invalid_option(&["--eye", &eye]), "--eye", "{}", eye);
+            let color = format!("{},0,0", v);
+            assert_eq!(
+                invalid_option(&["--fog-color", &color]),
+                "--fog-color",
+                "{}",
+                color
+            );
+        }
+        assert_eq!(invalid_option(&["--msaa", "inf"]), "--msaa");
+    }
+
+    #[test]
+    fn unknown_option() {
+        assert_eq!(
+            parse(&["--sise", "10"]),
+            Err(CliError::UnknownOption("--sise".to_owned()))
+        );
+        assert_eq!(
+            parse(&["model.obj"]),
+            Err(CliError::UnknownOption("model.obj".to_owned()))
+        );
+    }
+
+    #[test]
+    fn missing_value() {
+        assert_eq!(
+            parse(&["-o", "out.png", "--model"]),
+            Err(CliError::MissingValue("--model".to_owned()))
+        );
+    }
+
+    #[test]
+    fn switch_with_value() {
+        assert_eq!(
+            invalid_option(&["--all-shaders=yes", "-o", "a.png"]),
+            "--all-shaders"
+        );
+        assert_eq!(invalid_option(&["--post-process=1"]), "--post-process");
+    }
+
+    #[test]
+    fn conflicts() {
+        assert!(matches!(
+            parse(&["--all-shaders"]),
+            Err(CliError::Conflict(_))
+        ));
+        assert!(matches!(
+            parse(&["--turntable", "8"]),
+            Err(CliError::Conflict(_))
+        ));
+        assert!(matches!(
+            parse(&["--all-shaders", "--turntable", "8", "-o", "a.gif"]),
+            Err(CliError::Conflict(_))
+        ));
+    }
+
+    #[test]
+    fn help() {
+        assert_eq!(parse(&["-h"]), Err(CliError::Help));
+        assert_eq!(parse(&["--size", "10", "--help"]), Err(CliError::Help));
+    }
+}
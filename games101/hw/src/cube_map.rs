@@ -0,0 +1,198 @@
+use super::texture::{FilterMode, Sampler, Texture, WrapMode};
+use nalgebra::{Vector2, Vector3};
+
+type Vector2f = Vector2<f32>;
+type Vector3f = Vector3<f32>;
+
+/// 立方体贴图的六个面 顺序和 OpenGL 的 TEXTURE_CUBE_MAP_POSITIVE_X 等一致
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CubeFace {
+    PositiveX,
+    NegativeX,
+    PositiveY,
+    NegativeY,
+    PositiveZ,
+    NegativeZ,
+}
+
+impl CubeFace {
+    pub fn all() -> [CubeFace; 6] {
+        [
+            CubeFace::PositiveX,
+            CubeFace::NegativeX,
+            CubeFace::PositiveY,
+            CubeFace::NegativeY,
+            CubeFace::PositiveZ,
+            CubeFace::NegativeZ,
+        ]
+    }
+
+    /// games202 cubemap 目录中的文件名
+    pub fn file_name(&self) -> &'static str {
+        match self {
+            CubeFace::PositiveX => "posx.jpg",
+            CubeFace::NegativeX => "negx.jpg",
+            CubeFace::PositiveY => "posy.jpg",
+            CubeFace::NegativeY => "negy.jpg",
+            CubeFace::PositiveZ => "posz.jpg",
+            CubeFace::NegativeZ => "negz.jpg",
+        }
+    }
+
+    /// games202 skybox 目录中文件名的后缀
+    pub fn skybox_suffix(&self) -> &'static str {
+        match self {
+            CubeFace::PositiveX => "right",
+            CubeFace::NegativeX => "left",
+            CubeFace::PositiveY => "top",
+            CubeFace::NegativeY => "down",
+            CubeFace::PositiveZ => "front",
+            CubeFace::NegativeZ => "back",
+        }
+    }
+
+    /// 方向所在的面 以及面上的坐标 s t 从左上角开始 范围 [0, 1]
+    /// 和 OpenGL 规范中 major axis 的表一致
+    pub fn from_direction(dir: &Vector3f) -> (CubeFace, f32, f32) {
+        let (ax, ay, az) = (dir.x.abs(), dir.y.abs(), dir.z.abs());
+        let (face, sc, tc, ma) = if ax >= ay && ax >= az {
+            if dir.x > 0f32 {
+                (CubeFace::PositiveX, -dir.z, -dir.y, ax)
+            } else {
+                (CubeFace::NegativeX, dir.z, -dir.y, ax)
+            }
+        } else if ay >= az {
+            if dir.y > 0f32 {
+                (CubeFace::PositiveY, dir.x, dir.z, ay)
+            } else {
+                (CubeFace::NegativeY, dir.x, -dir.z, ay)
+            }
+        } else if dir.z > 0f32 {
+            (CubeFace::PositiveZ, dir.x, -dir.y, az)
+        } else {
+            (CubeFace::NegativeZ, -dir.x, -dir.y, az)
+        };
+        (face, (sc / ma + 1f32) / 2f32, (tc / ma + 1f32) / 2f32)
+    }
+
+    /// from_direction 的逆 面上 (s, t) 处对应的方向 没有归一化
+    pub fn direction(&self, s: f32, t: f32) -> Vector3f {
+        let (sc, tc) = (s * 2f32 - 1f32, t * 2f32 - 1f32);
+        match self {
+            CubeFace::PositiveX => Vector3f::new(1f32, -tc, -sc),
+            CubeFace::NegativeX => Vector3f::new(-1f32, -tc, sc),
+            CubeFace::PositiveY => Vector3f::new(sc, 1f32, tc),
+            CubeFace::NegativeY => Vector3f::new(sc, -1f32, -tc),
+            CubeFace::PositiveZ => Vector3f::new(sc, -tc, 1f32),
+            CubeFace::NegativeZ => Vector3f::new(-sc, -tc, -1f32),
+        }
+    }
+}
+
+/// 六张图组成的立方体贴图 按世界空间的方向采样 颜色范围和 Texture 一样是 [0, 255]
+pub struct CubeMap {
+    faces: Vec<Texture>,
+}
+
+impl CubeMap {
+    /// faces 按 CubeFace::all 的顺序给出
+    pub fn new(faces: [&str; 6]) -> Self {
+        Self {
+            faces: faces
+                .iter()
+                .map(|path| {
+                    let mut texture = Texture::new(path);
+                    // 面和面之间不能环绕
+                    texture.set_sampler(Sampler::new(WrapMode::ClampToEdge, FilterMode::Bilinear));
+                    texture
+                })
+                .collect(),
+        }
+    }
+
+    /// 用已有的六张纹理构造 按 CubeFace::all 的顺序 每个面应该是同样大小的正方形
+    pub fn from_faces(faces: Vec<Texture>) -> Self {
+        assert_eq!(faces.len(), 6);
+        Self {
+            faces: faces
+                .into_iter()
+                .map(|mut texture| {
+                    texture.set_sampler(Sampler::new(WrapMode::ClampToEdge, FilterMode::Bilinear));
+                    texture
+                })
+                .collect(),
+        }
+    }
+
+    /// 按 f(方向) 生成每个面 size x size 的立方体贴图 方向已经归一化
+    pub fn from_fn(size: i32, f: impl Fn(&Vector3f) -> Vector3f) -> Self {
+        let faces = CubeFace::all()
+            .iter()
+            .map(|face| {
+                let mut pixels = Vec::with_capacity((size * size) as usize);
+                for y in 0..size {
+                    for x in 0..size {
+                        let (s, t) = (
+                            (x as f32 + 0.5) / size as f32,
+                            (y as f32 + 0.5) / size as f32,
+                        );
+                        pixels.push(f(&face.direction(s, t).normalize()));
+                    }
+                }
+                Texture::from_pixels(size, size, pixels)
+            })
+            .collect();
+        Self::from_faces(faces)
+    }
+
+    /// 每个面的边长 (像素)
+    pub fn size(&self) -> i32 {
+        self.faces[0].width
+    }
+
+    /// games202 的 cubemap 目录 比如 GraceCathedral 里面是 posx.jpg 等六张图
+    pub fn from_dir(dir: &str) -> Self {
+        let paths: Vec<_> = CubeFace::all()
+            .iter()
+            .map(|face| format!("{}/{}", dir, face.file_name()))
+            .collect();
+        Self::new([
+            &paths[0], &paths[1], &paths[2], &paths[3], &paths[4], &paths[5],
+        ])
+    }
+
+    /// games202 的 skybox 目录 文件名为 {prefix}_right.jpg 等
+    pub fn from_skybox(dir: &str, prefix: &str) -> Self {
+        let paths: Vec<_> = CubeFace::all()
+            .iter()
+            .map(|face| format!("{}/{}_{}.jpg", dir, prefix, face.skybox_suffix()))
+            .collect();
+        Self::new([
+            &paths[0], &paths[1], &paths[2], &paths[3], &paths[4], &paths[5],
+        ])
+    }
+
+    pub fn face(&self, face: CubeFace) -> &Texture {
+        &self.faces[face as usize]
+    }
+
+    pub fn sample(&self, dir: &Vector3f) -> Vector3f {
+        if dir.magnitude_squared() <= f32::EPSILON {
+            return nalgebra::zero();
+        }
+        let (face, s, t) = CubeFace::from_direction(dir);
+        // Texture 的 v 从图像底部开始
+        let uv = Vector2f::new(s, 1f32 - t);
+        self.face(face)
+            .sample(uv, nalgebra::zero(), nalgebra::zero())
+    }
+
+    /// 在每个面的 mipmap 上按指定层级采样 面与面之间不做过滤
+    pub fn sample_lod(&self, dir: &Vector3f, lod: f32) -> Vector3f {
+        if dir.magnitude_squared() <= f32::EPSILON {
+            return nalgebra::zero();
+        }
+        let (face, s, t) = CubeFace::from_direction(dir);
+        self.face(face).sample_lod(Vector2f::new(s, 1f32 - t), lod)
+    }
+}
@@ -0,0 +1,86 @@
+use nalgebra::Vector3;
+
+type Vector3f = Vector3<f32>;
+
+/// 雾的浓度随距离或高度变化的方式
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FogMode {
+    /// 距离在 [start, end] 之间线性变浓 超过 end 完全是雾的颜色 不使用 density
+    Linear { start: f32, end: f32 },
+    /// 透过率为 exp(-density * d)
+    Exponential,
+    /// 透过率为 exp(-(density * d)^2) 近处更清楚 远处变浓得更快
+    ExponentialSquared,
+    /// 世界空间高度 base 处的密度为 density 往上按 exp(-falloff * (h - base)) 变稀
+    /// 沿视线对密度积分 低处的雾更浓
+    Height { base: f32, falloff: f32 },
+}
+
+/// 场景中的雾 在世界空间中定义 由 Rasterizer::set_fog 设置
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fog {
+    pub mode: FogMode,
+    /// 线性颜色 和着色器的输出相同
+    pub color: Vector3f,
+    pub density: f32,
+}
+
+impl Default for Fog {
+    fn default() -> Self {
+        Self {
+            mode: FogMode::Exponential,
+            color: Vector3f::new(0.5, 0.6, 0.7),
+            density: 0.05,
+        }
+    }
+}
+
+impl Fog {
+    pub fn new(mode: FogMode, color: Vector3f, density: f32) -> Self {
+        Self {
+            mode,
+            color,
+            density,
+        }
+    }
+
+    /// 从相机 eye 看向 point (都在世界空间) 时物体颜色留下的比例 1 为没有雾
+    pub fn transmittance(&self, eye: &Vector3f, point: &Vector3f) -> f32 {
+        let distance = (point - eye).magnitude();
+        let density = self.density.max(0f32);
+        let ret = match self.mode {
+            FogMode::Linear { start, end } => {
+                if end > start {
+                    (end - distance) / (end - start)
+                } else if distance < start {
+                    1f32
+                } else {
+                    0f32
+                }
+            }
+            FogMode::Exponential => (-density * distance).exp(),
+            FogMode::ExponentialSquared => (-(density * distance).powi(2)).exp(),
+            FogMode::Height { base, falloff } => {
+                let optical_depth = if falloff > 0f32 {
+                    // 密度沿视线的积分 高度差很小时 (1 - e^-x) / x 取极限 1
+                    let x = falloff * (point.y - eye.y);
+                    let ratio = if x.abs() > 1e-4 {
+                        (1f32 - (-x).exp()) / x
+                    } else {
+                        1f32 - x / 2f32
+                    };
+                    density * distance * (-falloff * (eye.y - base)).exp() * ratio
+                } else {
+                    density * distance
+                };
+                (-optical_depth).exp()
+            }
+        };
+        ret.max(0f32).min(1f32)
+    }
+
+    /// 按透过率在物体颜色和雾的颜色之间插值
+    pub fn apply(&self, color: Vector3f, eye: &Vector3f, point: &Vector3f) -> Vector3f {
+        self.color.lerp(&color, self.transmittance(eye, point))
+    }
+}
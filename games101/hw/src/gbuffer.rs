@@ -0,0 +1,193 @@
+use super::image;
+use nalgebra::{Vector2, Vector3};
+
+type Vector2f = Vector2<f32>;
+type Vector3f = Vector3<f32>;
+
+/// G-buffer 中一个像素保存的着色输入 除前五项外都是重建 FragmentShaderPayload 需要的插值结果
+#[derive(Debug, Default, Clone, Copy)]
+pub struct GSample {
+    /// 漫反射贴图的颜色 没有贴图时为顶点颜色 范围 [0, 1]
+    pub albedo: Vector3f,
+    /// 观察空间法线
+    pub normal: Vector3f,
+    pub view_pos: Vector3f,
+    /// 和 depth buffer 相同的屏幕空间深度
+    pub depth: f32,
+    /// 0 表示没有材质 其余见 Rasterizer::material_name
+    pub material_id: u32,
+    /// 材质的镜面反射系数 屏幕空间反射按它和菲涅尔项混合 没有材质时为 0
+    pub specular: Vector3f,
+    /// 环境光可见度 1 为不遮挡 开启 SSAO 时在光照之前写入
+    pub ambient_occlusion: f32,
+
+    pub color: Vector3f,
+    pub tangent: Vector3f,
+    pub bitangent: Vector3f,
+    pub tex_coords: Vector2f,
+    pub tex_coords_dx: Vector2f,
+    pub tex_coords_dy: Vector2f,
+    pub alpha: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GBufferChannel {
+    Albedo,
+    Normal,
+    ViewPosition,
+    Depth,
+    MaterialId,
+    AmbientOcclusion,
+}
+
+impl GBufferChannel {
+    pub fn all() -> [GBufferChannel; 6] {
+        [
+            GBufferChannel::Albedo,
+            GBufferChannel::Normal,
+            GBufferChannel::ViewPosition,
+            GBufferChannel::Depth,
+            GBufferChannel::MaterialId,
+            GBufferChannel::AmbientOcclusion,
+        ]
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            GBufferChannel::Albedo => "albedo",
+            GBufferChannel::Normal => "normal",
+            GBufferChannel::ViewPosition => "view_pos",
+            GBufferChannel::Depth => "depth",
+            GBufferChannel::MaterialId => "material_id",
+            GBufferChannel::AmbientOcclusion => "ambient_occlusion",
+        }
+    }
+}
+
+/// 延迟着色使用的 G-buffer 像素排列和 frame buffer 相同
+#[derive(Default)]
+pub struct GBuffer {
+    pub width: i32,
+    pub height: i32,
+    samples: Vec<Option<GSample>>,
+}
+
+// 把 [min, max] 线性映射到 [0, 1]
+fn normalize(v: f32, min: f32, max: f32) -> f32 {
+    if max > min {
+        (v - min) / (max - min)
+    } else {
+        0f32
+    }
+}
+
+// 给每个材质 id 一个容易区分的颜色
+fn id_color(id: u32) -> Vector3f {
+    let h = id.wrapping_mul(2654435761);
+    Vector3f::new(
+        ((h >> 16) & 0xff) as f32,
+        ((h >> 8) & 0xff) as f32,
+        (h & 0xff) as f32,
+    )
+}
+
+impl GBuffer {
+    pub fn new(width: i32, height: i32) -> Self {
+        Self {
+            width,
+            height,
+            samples: vec![None; (width * height) as usize],
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.samples.iter_mut().for_each(|s| *s = None);
+    }
+
+    /// 屏幕坐标对应的下标 和 frame buffer 一样 x 方向是镜像的
+    pub fn index(&self, x: i32, y: i32) -> Option<usize> {
+        if x < 0 || x >= self.width || y < 0 || y >= self.height {
+            return None;
+        }
+        let index = (y * self.width + self.width - 1 - x) as usize;
+        if index < self.samples.len() {
+            Some(index)
+        } else {
+            None
+        }
+    }
+
+    /// None 表示该像素没有被不透明的三角形覆盖
+    pub fn samples(&self) -> &[Option<GSample>] {
+        &self.samples
+    }
+
+    pub(crate) fn samples_mut(&mut self) -> &mut [Option<GSample>] {
+        &mut self.samples
+    }
+
+    /// 把一个通道转成和 output_buffer 相同格式的 rgb 数据 用于调试显示
+    /// 观察空间位置和深度按被覆盖像素的范围归一化 深度越近越亮
+    pub fn channel(&self, channel: GBufferChannel) -> Vec<f32> {
+        let covered = self.samples.iter().flatten();
+        let (min, max) = covered.fold(
+            (
+                Vector3f::from_element(f32::MAX),
+                Vector3f::from_element(f32::MIN),
+            ),
+            |(min, max), s| {
+                let v = match channel {
+                    GBufferChannel::Depth => Vector3f::from_element(s.depth),
+                    _ => s.view_pos,
+                };
+                (min.inf(&v), max.sup(&v))
+            },
+        );
+
+        let mut ret = Vec::with_capacity(self.samples.len() * 3);
+        for sample in &self.samples {
+            let color = match sample {
+                None => nalgebra::zero(),
+                Some(s) => match channel {
+                    GBufferChannel::Albedo => s.albedo * 255f32,
+                    GBufferChannel::Normal => {
+                        (s.normal + Vector3f::from_element(1f32)) / 2f32 * 255f32
+                    }
+                    GBufferChannel::ViewPosition => {
+                        Vector3f::new(
+                            normalize(s.view_pos.x, min.x, max.x),
+                            normalize(s.view_pos.y, min.y, max.y),
+                            normalize(s.view_pos.z, min.z, max.z),
+                        ) * 255f32
+                    }
+                    GBufferChannel::Depth => {
+                        Vector3f::from_element(1f32 - normalize(s.depth, min.x, max.x) * 0.9)
+                            * 255f32
+                    }
+                    GBufferChannel::MaterialId => match s.material_id {
+                        0 => Vector3f::from_element(128f32),
+                        id => id_color(id),
+                    },
+                    GBufferChannel::AmbientOcclusion => {
+                        Vector3f::from_element(s.ambient_occlusion * 255f32)
+                    }
+                },
+            };
+            ret.extend(color.iter());
+        }
+        ret
+    }
+
+    pub fn save_channel(&self, channel: GBufferChannel, path: &str) -> opencv::Result<()> {
+        let mut buf = self.channel(channel);
+        image::save(path, self.width, self.height, &mut buf)
+    }
+
+    /// 所有通道分别保存为 {prefix}_{通道名}.png
+    pub fn save_all(&self, prefix: &str) -> opencv::Result<()> {
+        for channel in GBufferChannel::all().iter() {
+            self.save_channel(*channel, &format!("{}_{}.png", prefix, channel.name()))?;
+        }
+        Ok(())
+    }
+}
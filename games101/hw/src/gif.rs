@@ -0,0 +1,283 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io::{self, Write};
+
+// 4x4 Bayer 矩阵 用于有序抖动
+const BAYER: [u8; 16] = [0, 8, 2, 10, 12, 4, 14, 6, 3, 11, 1, 9, 15, 7, 13, 5];
+
+/// 不依赖外部库的 GIF89a 编码器 使用固定的 6x6x6 调色板加有序抖动 动画无限循环
+pub struct GifEncoder<W: Write> {
+    writer: W,
+    width: u16,
+    height: u16,
+    /// 每帧的显示时间 单位 1/100 秒
+    delay: u16,
+}
+
+// 按 LSB 优先把变长的码字打包成字节
+struct BitWriter {
+    bytes: Vec<u8>,
+    current: u32,
+    bits: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: u16, size: u32) {
+        self.current |= (code as u32) << self.bits;
+        self.bits += size;
+        while self.bits >= 8 {
+            self.bytes.push(self.current as u8);
+            self.current >>= 8;
+            self.bits -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            self.bytes.push(self.current as u8);
+        }
+        self.bytes
+    }
+}
+
+// 调色板索引 最小码长为 8 返回未补齐最后一个字节的码流
+fn lzw(indices: &[u8]) -> BitWriter {
+    const CLEAR: u16 = 256;
+    const END: u16 = 257;
+    let mut out = BitWriter {
+        bytes: vec![],
+        current: 0,
+        bits: 0,
+    };
+    let mut dict: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut size = 9;
+    let mut next = END + 1;
+    out.write(CLEAR, size);
+
+    let mut prefix = match indices.first() {
+        Some(&k) => k as u16,
+        None => {
+            out.write(END, size);
+            return out;
+        }
+    };
+    for &k in &indices[1..] {
+        if let Some(&code) = dict.get(&(prefix, k)) {
+            prefix = code;
+            continue;
+        }
+        out.write(prefix, size);
+        if next < 4096 {
+            dict.insert((prefix, k), next);
+            next += 1;
+            // 解码器比编码器晚一步加入字典项
+            if next > (1 << size) && size < 12 {
+                size += 1;
+            }
+        } else {
+            // 字典满了 重新开始
+            out.write(CLEAR, size);
+            dict.clear();
+            size = 9;
+            next = END + 1;
+        }
+        prefix = k as u16;
+    }
+    out.write(prefix, size);
+    // 解码器读到最后一个码字时同样会加入字典项 END 要用加入之后的码长
+    if next < 4096 {
+        next += 1;
+        if next > (1 << size) && size < 12 {
+            size += 1;
+        }
+    }
+    out.write(END, size);
+    out
+}
+
+impl<W: Write> GifEncoder<W> {
+    /// 写入文件头 全局调色板和循环播放的扩展块 宽高必须在 1 到 65535 之间
+    pub fn new(mut writer: W, width: i32, height: i32, delay: u16) -> io::Result<Self> {
+        let dimension = |v: i32| match u16::try_from(v) {
+            Ok(v) if v > 0 => Ok(v),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("gif size {}x{} is out of range 1 to 65535", width, height),
+            )),
+        };
+        let (width, height) = (dimension(width)?, dimension(height)?);
+        writer.write_all(b"GIF89a")?;
+        writer.write_all(&width.to_le_bytes())?;
+        writer.write_all(&height.to_le_bytes())?;
+        // 有全局调色板 256 色
+        writer.write_all(&[0xf7, 0, 0])?;
+        let mut palette = vec![0u8; 256 * 3];
+        for i in 0..216 {
+            let (r, g, b) = (i / 36, i / 6 % 6, i % 6);
+            palette[i * 3] = (r * 51) as u8;
+            palette[i * 3 + 1] = (g * 51) as u8;
+            palette[i * 3 + 2] = (b * 51) as u8;
+        }
+        writer.write_all(&palette)?;
+        writer.write_all(&[0x21, 0xff, 0x0b])?;
+        writer.write_all(b"NETSCAPE2.0")?;
+        writer.write_all(&[0x03, 0x01, 0, 0, 0])?;
+        Ok(Self {
+            writer,
+            width,
+            height,
+            delay,
+        })
+    }
+
+    /// rgb 为 output_buffer 格式的数据 按行存储 范围 0 到 255
+    pub fn add_frame(&mut self, rgb: &[f32]) -> io::Result<()> {
+        let (width, height) = (self.width as usize, self.height as usize);
+        assert!(rgb.len() >= width * height * 3);
+        let mut indices = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let threshold = (BAYER[(y % 4) * 4 + x % 4] as f32 + 0.5) / 16f32;
+                let c = &rgb[(y * width + x) * 3..];
+                let q = |v: f32| ((v.max(0f32).min(255f32) / 51f32 + threshold) as u8).min(5);
+                indices.push(q(c[0]) * 36 + q(c[1]) * 6 + q(c[2]));
+            }
+        }
+
+        // graphic control extension 设置每帧的延迟
+        self.writer.write_all(&[0x21, 0xf9, 0x04, 0x04])?;
+        self.writer.write_all(&self.delay.to_le_bytes())?;
+        self.writer.write_all(&[0, 0])?;
+        // image descriptor 整张图 使用全局调色板
+        self.writer.write_all(&[0x2c, 0, 0, 0, 0])?;
+        self.writer.write_all(&self.width.to_le_bytes())?;
+        self.writer.write_all(&self.height.to_le_bytes())?;
+        self.writer.write_all(&[0, 8])?;
+        for block in lzw(&indices).finish().chunks(255) {
+            self.writer.write_all(&[block.len() as u8])?;
+            self.writer.write_all(block)?;
+        }
+        self.writer.write_all(&[0])
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.writer.write_all(&[0x3b])?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 最小码长为 8 的 LZW 解码 返回解出的索引和读到 END 为止用掉的位数
+    fn decode(bytes: &[u8]) -> (Vec<u8>, usize) {
+        let mut pos = 0;
+        let mut read = |size: usize| -> usize {
+            let code = (0..size)
+                .map(|i| {
+                    let bit = pos + i;
+                    let byte = *bytes.get(bit / 8).expect("stream ended before END");
+                    ((byte >> (bit % 8)) as usize & 1) << i
+                })
+                .sum();
+            pos += size;
+            code
+        };
+        let mut dict: Vec<Vec<u8>> = vec![];
+        let mut size = 9;
+        let mut prev: Option<Vec<u8>> = None;
+        let mut out = vec![];
+        loop {
+            let code = read(size);
+            if code == 256 {
+                dict = (0..=255).map(|i| vec![i]).collect();
+                dict.extend(vec![vec![], vec![]]);
+                size = 9;
+                prev = None;
+                continue;
+            }
+            if code == 257 {
+                break;
+            }
+            assert!(!dict.is_empty(), "stream does not start with CLEAR");
+            let entry = if code < dict.len() {
+                dict[code].clone()
+            } else {
+                assert_eq!(code, dict.len(), "code {} is not in the dictionary", code);
+                let mut entry = prev.clone().expect("first code after CLEAR is unknown");
+                entry.push(entry[0]);
+                entry
+            };
+            out.extend(&entry);
+            if let Some(mut item) = prev {
+                if dict.len() < 4096 {
+                    item.push(entry[0]);
+                    dict.push(item);
+                    if dict.len() == 1 << size && size < 12 {
+                        size += 1;
+                    }
+                }
+            }
+            prev = Some(entry);
+        }
+        (out, pos)
+    }
+
+    fn round_trip(indices: &[u8]) {
+        let stream = lzw(indices);
+        let bits = stream.bytes.len() * 8 + stream.bits as usize;
+        let bytes = stream.finish();
+        let (decoded, used) = decode(&bytes);
+        assert!(
+            decoded == indices,
+            "{} indices do not round trip",
+            indices.len()
+        );
+        // 码长不一致时解码器读到的 END 位置和写入的不同
+        assert_eq!(used, bits, "{} indices", indices.len());
+    }
+
+    #[test]
+    fn empty_and_single() {
+        round_trip(&[]);
+        round_trip(&[7]);
+        round_trip(&[7, 7, 7, 7, 7]);
+    }
+
+    // n 个互不相同的索引写出 n 个码字 255 个时最后一个码字之后解码器的码长变为 10
+    #[test]
+    fn end_at_code_size_boundary() {
+        for n in 250..=256 {
+            let indices: Vec<u8> = (0..n).map(|i| i as u8).collect();
+            round_trip(&indices);
+        }
+    }
+
+    // 字典加满 4096 项后清空重来 多次跨过所有码长
+    #[test]
+    fn dictionary_reset() {
+        let mut state = 1u32;
+        let mut noise = |len: usize, colors: u32| -> Vec<u8> {
+            (0..len)
+                .map(|_| {
+                    state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+                    ((state >> 16) % colors) as u8
+                })
+                .collect()
+        };
+        for &len in &[5000, 20000, 20001, 20002] {
+            round_trip(&noise(len, 216));
+        }
+        round_trip(&noise(60000, 4));
+    }
+
+    #[test]
+    fn size_out_of_range() {
+        for &(width, height) in &[(65536, 10), (10, 70000), (0, 10), (10, -1)] {
+            assert!(GifEncoder::new(Vec::new(), width, height, 4).is_err());
+        }
+        assert!(GifEncoder::new(Vec::new(), 65535, 1, 4).is_ok());
+    }
+}
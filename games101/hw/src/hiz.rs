@@ -0,0 +1,156 @@
+/// 深度缓冲的层次 min/max 金字塔 坐标和光栅化时的屏幕坐标相同
+/// 第 0 层每个元素对应 BLOCK x BLOCK 个像素 往上每层边长减半 最上层只有一个元素
+#[derive(Debug, Clone, Default)]
+pub struct HiZ {
+    width: i32,
+    height: i32,
+    levels: Vec<Level>,
+}
+
+#[derive(Debug, Clone)]
+struct Level {
+    width: i32,
+    height: i32,
+    // 一个元素覆盖的像素边长
+    block: i32,
+    min: Vec<f32>,
+    max: Vec<f32>,
+}
+
+impl Level {
+    fn index(&self, bx: i32, by: i32) -> usize {
+        (by * self.width + bx) as usize
+    }
+}
+
+impl HiZ {
+    /// 第 0 层元素的边长 (像素) 屏幕分块的边长是它的整数倍
+    pub const BLOCK: i32 = 8;
+
+    pub fn new(width: i32, height: i32, depth: f32) -> Self {
+        let mut levels = vec![];
+        let mut block = Self::BLOCK;
+        loop {
+            let (w, h) = ((width + block - 1) / block, (height + block - 1) / block);
+            levels.push(Level {
+                width: w,
+                height: h,
+                block,
+                min: vec![depth; (w * h) as usize],
+                max: vec![depth; (w * h) as usize],
+            });
+            if w <= 1 && h <= 1 {
+                break;
+            }
+            block *= 2;
+        }
+        Self {
+            width,
+            height,
+            levels,
+        }
+    }
+
+    pub fn reset(&mut self, depth: f32) {
+        for level in &mut self.levels {
+            level.min.iter_mut().for_each(|d| *d = depth);
+            level.max.iter_mut().for_each(|d| *d = depth);
+        }
+    }
+
+    /// 重新计算覆盖 [x0, x1) x [y0, y1) 的元素 depth 返回一个像素所有采样点的最小和最大深度
+    pub fn update<F>(&mut self, x0: i32, y0: i32, x1: i32, y1: i32, depth: F)
+    where
+        F: Fn(i32, i32) -> (f32, f32),
+    {
+        let (x0, y0) = (x0.max(0), y0.max(0));
+        let (x1, y1) = (x1.min(self.width), y1.min(self.height));
+        if x0 >= x1 || y0 >= y1 {
+            return;
+        }
+        let base = &mut self.levels[0];
+        for by in y0 / base.block..=(y1 - 1) / base.block {
+            for bx in x0 / base.block..=(x1 - 1) / base.block {
+                let (mut lo, mut hi) = (f32::MAX, f32::MIN);
+                for y in by * base.block..((by + 1) * base.block).min(self.height) {
+                    for x in bx * base.block..((bx + 1) * base.block).min(self.width) {
+                        let (min, max) = depth(x, y);
+                        lo = lo.min(min);
+                        hi = hi.max(max);
+                    }
+                }
+                let index = base.index(bx, by);
+                base.min[index] = lo;
+                base.max[index] = hi;
+            }
+        }
+        for l in 1..self.levels.len() {
+            let (lower, upper) = self.levels.split_at_mut(l);
+            let (lower, upper) = (&lower[l - 1], &mut upper[0]);
+            for by in y0 / upper.block..=(y1 - 1) / upper.block {
+                for bx in x0 / upper.block..=(x1 - 1) / upper.block {
+                    let (mut lo, mut hi) = (f32::MAX, f32::MIN);
+                    for cy in by * 2..(by * 2 + 2).min(lower.height) {
+                        for cx in bx * 2..(bx * 2 + 2).min(lower.width) {
+                            let index = lower.index(cx, cy);
+                            lo = lo.min(lower.min[index]);
+                            hi = hi.max(lower.max[index]);
+                        }
+                    }
+                    let index = upper.index(bx, by);
+                    upper.min[index] = lo;
+                    upper.max[index] = hi;
+                }
+            }
+        }
+    }
+
+    // 选择矩形最多跨 2x2 个元素的层 返回覆盖矩形的元素中深度的范围
+    fn range(&self, x0: i32, y0: i32, x1: i32, y1: i32) -> Option<(f32, f32)> {
+        let (x0, y0) = (x0.max(0), y0.max(0));
+        let (x1, y1) = (x1.min(self.width), y1.min(self.height));
+        if x0 >= x1 || y0 >= y1 {
+            return None;
+        }
+        let level = self
+            .levels
+            .iter()
+            .find(|l| {
+                (x1 - 1) / l.block - x0 / l.block <= 1 && (y1 - 1) / l.block - y0 / l.block <= 1
+            })
+            .unwrap_or_else(|| self.levels.last().unwrap());
+        let (mut lo, mut hi) = (f32::MAX, f32::MIN);
+        for by in y0 / level.block..=(y1 - 1) / level.block {
+            for bx in x0 / level.block..=(x1 - 1) / level.block {
+                let index = level.index(bx, by);
+                lo = lo.min(level.min[index]);
+                hi = hi.max(level.max[index]);
+            }
+        }
+        Some((lo, hi))
+    }
+
+    /// [x0, x1) x [y0, y1) 内最远的深度 深度不小于它的片元一定不能通过深度测试
+    /// 矩形在屏幕外时返回 None
+    pub fn max_depth(&self, x0: i32, y0: i32, x1: i32, y1: i32) -> Option<f32> {
+        self.range(x0, y0, x1, y1).map(|(_, max)| max)
+    }
+
+    /// [x0, x1) x [y0, y1) 内最近的深度 深度小于它的片元一定能通过深度测试
+    pub fn min_depth(&self, x0: i32, y0: i32, x1: i32, y1: i32) -> Option<f32> {
+        self.range(x0, y0, x1, y1).map(|(min, _)| min)
+    }
+}
+
+/// 层次 Z 和深度测试剔除的统计 clear 深度缓冲时清零
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CullStats {
+    /// 包围盒被遮挡 整个跳过的网格 只在开启遮挡剔除时统计
+    pub meshes: usize,
+    /// 光栅化之前被层次 Z 整个剔除的三角形
+    pub triangles: usize,
+    /// 三角形和屏幕分块的组合中 分块已有的深度都比三角形近 没有逐像素处理的次数
+    pub tiles: usize,
+    /// 逐像素处理时没有通过深度测试 因而没有着色的采样点
+    pub fragments: usize,
+}
@@ -0,0 +1,196 @@
+use super::cube_map::CubeMap;
+use nalgebra::{Vector2, Vector3};
+
+type Vector2f = Vector2<f32>;
+type Vector3f = Vector3<f32>;
+
+const PI: f32 = std::f32::consts::PI;
+
+// Hammersley 低差异序列
+fn hammersley(i: u32, n: u32) -> Vector2f {
+    Vector2f::new(i as f32 / n as f32, i.reverse_bits() as f32 / 4294967296f32)
+}
+
+// 以 n 为 z 轴的正交基
+fn basis(n: &Vector3f) -> (Vector3f, Vector3f) {
+    let up = if n.z.abs() < 0.999 {
+        Vector3f::z()
+    } else {
+        Vector3f::x()
+    };
+    let t = up.cross(n).normalize();
+    (t, n.cross(&t))
+}
+
+// 按 GGX 分布重要性采样半程向量 roughness 为感知粗糙度 alpha = roughness^2
+fn importance_sample_ggx(xi: Vector2f, n: &Vector3f, roughness: f32) -> Vector3f {
+    let a = roughness * roughness;
+    let phi = 2f32 * PI * xi.x;
+    let cos_theta = ((1f32 - xi.y) / (1f32 + (a * a - 1f32) * xi.y)).sqrt();
+    let sin_theta = (1f32 - cos_theta * cos_theta).max(0f32).sqrt();
+    let (t, b) = basis(n);
+    (t * phi.cos() * sin_theta + b * phi.sin() * sin_theta + n * cos_theta).normalize()
+}
+
+/// GGX 法线分布
+pub fn distribution_ggx(n_dot_h: f32, roughness: f32) -> f32 {
+    let a2 = roughness.powi(4);
+    let d = n_dot_h * n_dot_h * (a2 - 1f32) + 1f32;
+    a2 / (PI * d * d).max(1e-7)
+}
+
+// Schlick-GGX 的几何遮蔽 k 按直接光和 IBL 取不同的值
+fn geometry_schlick_ggx(n_dot_v: f32, k: f32) -> f32 {
+    n_dot_v / (n_dot_v * (1f32 - k) + k)
+}
+
+/// 直接光照使用的 Smith 几何项 k = (roughness + 1)^2 / 8
+pub fn geometry_smith(n_dot_v: f32, n_dot_l: f32, roughness: f32) -> f32 {
+    let k = (roughness + 1f32).powi(2) / 8f32;
+    geometry_schlick_ggx(n_dot_v, k) * geometry_schlick_ggx(n_dot_l, k)
+}
+
+// IBL 使用的 Smith 几何项 k = roughness^2 / 2
+fn geometry_smith_ibl(n_dot_v: f32, n_dot_l: f32, roughness: f32) -> f32 {
+    let k = roughness * roughness / 2f32;
+    geometry_schlick_ggx(n_dot_v, k) * geometry_schlick_ggx(n_dot_l, k)
+}
+
+/// split sum 近似中和环境光无关的部分 镜面反射 = prefiltered * (F0 * A + B)
+pub struct BrdfLut {
+    size: usize,
+    data: Vec<Vector2f>,
+}
+
+impl BrdfLut {
+    /// 横轴为 N·V 纵轴为粗糙度
+    pub fn new(size: usize, samples: u32) -> Self {
+        let mut data = Vec::with_capacity(size * size);
+        let n = Vector3f::z();
+        for j in 0..size {
+            let roughness = (j as f32 + 0.5) / size as f32;
+            for i in 0..size {
+                let n_dot_v = (i as f32 + 0.5) / size as f32;
+                let v = Vector3f::new((1f32 - n_dot_v * n_dot_v).sqrt(), 0f32, n_dot_v);
+                let mut ab = Vector2f::zeros();
+                for k in 0..samples {
+                    let h = importance_sample_ggx(hammersley(k, samples), &n, roughness);
+                    let l = 2f32 * v.dot(&h) * h - v;
+                    let (n_dot_l, n_dot_h, v_dot_h) = (l.z, h.z.max(0f32), v.dot(&h).max(0f32));
+                    if n_dot_l > 0f32 {
+                        let g = geometry_smith_ibl(n_dot_v, n_dot_l, roughness);
+                        let g_vis = g * v_dot_h / (n_dot_h * n_dot_v).max(1e-7);
+                        let fc = (1f32 - v_dot_h).powi(5);
+                        ab += Vector2f::new((1f32 - fc) * g_vis, fc * g_vis);
+                    }
+                }
+                data.push(ab / samples as f32);
+            }
+        }
+        Self { size, data }
+    }
+
+    /// 双线性插值查表
+    pub fn lookup(&self, n_dot_v: f32, roughness: f32) -> Vector2f {
+        let max = (self.size - 1) as f32;
+        let x = (n_dot_v * self.size as f32 - 0.5).max(0f32).min(max);
+        let y = (roughness * self.size as f32 - 0.5).max(0f32).min(max);
+        let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+        let (x1, y1) = ((x0 + 1).min(self.size - 1), (y0 + 1).min(self.size - 1));
+        let at = |x: usize, y: usize| self.data[y * self.size + x];
+        let (s, t) = (x - x0 as f32, y - y0 as f32);
+        let c0 = at(x0, y0).lerp(&at(x1, y0), s);
+        let c1 = at(x0, y1).lerp(&at(x1, y1), s);
+        c0.lerp(&c1, t)
+    }
+}
+
+/// 基于图像的光照 启动时由环境贴图预计算 颜色范围和 CubeMap 一样是 [0, 255]
+pub struct Ibl {
+    irradiance: CubeMap,
+    // 第 k 层对应粗糙度 k / (levels - 1)
+    prefiltered: Vec<CubeMap>,
+    brdf_lut: BrdfLut,
+}
+
+impl Ibl {
+    pub fn new(environment: &CubeMap) -> Self {
+        Self::with_settings(environment, 64, 5, 64)
+    }
+
+    /// size 为粗糙度 0 那一层的边长 levels 为预过滤的层数 samples 为每个纹素的采样数
+    pub fn with_settings(environment: &CubeMap, size: i32, levels: usize, samples: u32) -> Self {
+        let source_size = environment.size() as f32;
+        // 每个纹素对应的立体角 用于按采样的概率密度选择源贴图的层级 避免噪点
+        let texel_solid_angle = 4f32 * PI / (6f32 * source_size * source_size);
+
+        let levels = levels.max(2);
+        let prefiltered = (0..levels)
+            .map(|level| {
+                let roughness = level as f32 / (levels - 1) as f32;
+                let level_size = (size >> level).max(4);
+                CubeMap::from_fn(level_size, |n| {
+                    if level == 0 {
+                        return environment.sample(n);
+                    }
+                    // 近似认为 N = V = R
+                    let (mut sum, mut weight) = (Vector3f::zeros(), 0f32);
+                    for i in 0..samples {
+                        let h = importance_sample_ggx(hammersley(i, samples), n, roughness);
+                        let l = 2f32 * n.dot(&h) * h - n;
+                        let n_dot_l = n.dot(&l);
+                        if n_dot_l > 0f32 {
+                            let n_dot_h = n.dot(&h).max(0f32);
+                            let pdf = distribution_ggx(n_dot_h, roughness) / 4f32;
+                            let sample_solid_angle = 1f32 / (samples as f32 * pdf + 1e-4);
+                            let lod = 0.5 * (sample_solid_angle / texel_solid_angle).log2();
+                            sum += environment.sample_lod(&l, lod.max(0f32)) * n_dot_l;
+                            weight += n_dot_l;
+                        }
+                    }
+                    sum / weight.max(1e-4)
+                })
+            })
+            .collect();
+
+        // 漫反射只需要很低的分辨率 按余弦分布采样 平均值就是 E / π
+        let irradiance_lod = (source_size / 16f32).log2().max(0f32);
+        let irradiance = CubeMap::from_fn(16, |n| {
+            let (t, b) = basis(n);
+            let count = samples * 4;
+            let mut sum = Vector3f::zeros();
+            for i in 0..count {
+                let xi = hammersley(i, count);
+                let (r, phi) = (xi.y.sqrt(), 2f32 * PI * xi.x);
+                let l = t * r * phi.cos() + b * r * phi.sin() + n * (1f32 - xi.y).sqrt();
+                sum += environment.sample_lod(&l, irradiance_lod);
+            }
+            sum / count as f32
+        });
+
+        Self {
+            irradiance,
+            prefiltered,
+            brdf_lut: BrdfLut::new(32, samples),
+        }
+    }
+
+    /// 法线方向 (世界空间) 的漫反射辐照度 已经除以 π
+    pub fn irradiance(&self, normal: &Vector3f) -> Vector3f {
+        self.irradiance.sample(normal)
+    }
+
+    /// 反射方向 (世界空间) 上按粗糙度预过滤的环境光
+    pub fn prefiltered(&self, reflected: &Vector3f, roughness: f32) -> Vector3f {
+        let level = roughness.max(0f32).min(1f32) * (self.prefiltered.len() - 1) as f32;
+        let l0 = level.floor() as usize;
+        let l1 = (l0 + 1).min(self.prefiltered.len() - 1);
+        let c0 = self.prefiltered[l0].sample(reflected);
+        let c1 = self.prefiltered[l1].sample(reflected);
+        c0.lerp(&c1, level - l0 as f32)
+    }
+
+    pub fn brdf(&self, n_dot_v: f32, roughness: f32) -> Vector2f {
+        self.brdf_lut.lookup(n_dot_v, roughness)
+    }
+}
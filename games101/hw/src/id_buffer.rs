@@ -0,0 +1,67 @@
+use nalgebra::Vector3;
+
+type Vector3f = Vector3<f32>;
+
+/// ID buffer 中一个像素保存的内容 只记录写入深度的不透明三角形
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IdSample {
+    /// draw_scene 中为节点的 NodeId draw_meshes 中为 set_mesh_id 的值加上网格的下标
+    /// 其余绘制为 set_mesh_id 的值
+    pub mesh_id: u32,
+    /// 实例化绘制时实例的下标 其他绘制为 0
+    pub instance_id: u32,
+    /// 三角形在网格或下标缓冲中的序号 曲面细分出的三角形和原三角形相同
+    pub triangle_id: u32,
+    /// 屏幕空间的重心坐标 和片元插值使用的相同
+    pub barycentric: Vector3f,
+    // 绘制时保存的三角形的下标 见 Rasterizer::pick
+    pub(crate) slot: u32,
+}
+
+/// 拾取的结果 位置和法线在世界空间
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Pick {
+    pub mesh_id: u32,
+    pub instance_id: u32,
+    pub triangle_id: u32,
+    /// 经过透视校正的重心坐标 按它插值得到三角形上真实的位置
+    pub barycentric: Vector3f,
+    pub position: Vector3f,
+    pub normal: Vector3f,
+}
+
+// 开启 ID buffer 时每个提交的三角形在世界空间的顶点 拾取时按重心坐标插值
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct PickTriangle {
+    pub position: [Vector3f; 3],
+    pub normal: [Vector3f; 3],
+    /// 顶点在裁剪空间的 w 用来校正屏幕空间的重心坐标
+    pub w: [f32; 3],
+}
+
+impl PickTriangle {
+    // 屏幕空间的重心坐标对 1 / w 是线性的 除以 w 再归一化得到三角形上的重心坐标
+    fn perspective_correct(&self, b: Vector3f) -> Vector3f {
+        let corrected = Vector3f::new(b.x / self.w[0], b.y / self.w[1], b.z / self.w[2]);
+        let sum = corrected.sum();
+        if sum.is_finite() && sum != 0f32 {
+            corrected / sum
+        } else {
+            b
+        }
+    }
+
+    pub fn pick(&self, sample: &IdSample) -> Pick {
+        let b = self.perspective_correct(sample.barycentric);
+        let position = self.position[0] * b.x + self.position[1] * b.y + self.position[2] * b.z;
+        let normal = self.normal[0] * b.x + self.normal[1] * b.y + self.normal[2] * b.z;
+        Pick {
+            mesh_id: sample.mesh_id,
+            instance_id: sample.instance_id,
+            triangle_id: sample.triangle_id,
+            barycentric: b,
+            position,
+            normal: normal.try_normalize(f32::EPSILON).unwrap_or(normal),
+        }
+    }
+}
@@ -0,0 +1,51 @@
+use opencv::{core, imgcodecs, imgproc, prelude::*};
+
+/// 把 output_buffer 格式的数据 (按行存储的 rgb f32, 范围 0 到 255) 转成 opencv 的 BGR 图像
+pub fn to_mat(width: i32, height: i32, buf: &mut [f32]) -> opencv::Result<Mat> {
+    assert!(buf.len() >= (width * height * 3) as usize);
+    let ptr = buf.as_mut_ptr() as *mut std::ffi::c_void;
+    let mut ret = Mat::default()?;
+    unsafe {
+        // 这里的 Mat 直接引用 buf 的内存 只在本函数内使用
+        let image =
+            Mat::new_rows_cols_with_data(height, width, core::CV_32FC3, ptr, core::Mat_AUTO_STEP)?;
+        let mut temp = Mat::default()?;
+        image.convert_to(&mut temp, core::CV_8UC3, 1f64, 0f64)?;
+        imgproc::cvt_color(&temp, &mut ret, imgproc::COLOR_RGB2BGR, 0)?;
+    }
+    Ok(ret)
+}
+
+/// imwrite 返回 false (比如不支持的扩展名) 时也返回错误
+pub fn save(path: &str, width: i32, height: i32, buf: &mut [f32]) -> opencv::Result<()> {
+    let image = to_mat(width, height, buf)?;
+    if !imgcodecs::imwrite(path, &image, &core::Vector::new())? {
+        return Err(opencv::Error::new(
+            core::StsError,
+            format!("can not write image {}", path),
+        ));
+    }
+    Ok(())
+}
+
+/// 读入图像 返回宽 高和 output_buffer 格式的数据
+pub fn load(path: &str) -> opencv::Result<(i32, i32, Vec<f32>)> {
+    let image = imgcodecs::imread(path, imgcodecs::IMREAD_COLOR)?;
+    let (width, height) = (image.cols(), image.rows());
+    if width == 0 || height == 0 {
+        return Err(opencv::Error::new(
+            core::StsError,
+            format!("can not read image {}", path),
+        ));
+    }
+    let mut rgb = Mat::default()?;
+    imgproc::cvt_color(&image, &mut rgb, imgproc::COLOR_BGR2RGB, 0)?;
+    let mut ret = Vec::with_capacity((width * height * 3) as usize);
+    for y in 0..height {
+        for x in 0..width {
+            let color = rgb.at_2d::<core::Vec3b>(y, x)?;
+            ret.extend((0..3).map(|i| color[i] as f32));
+        }
+    }
+    Ok((width, height, ret))
+}
@@ -1,8 +1,26 @@
+pub mod animation;
+pub mod cli;
+pub mod cube_map;
+pub mod fog;
+pub mod gbuffer;
+pub mod gif;
+pub mod hiz;
+pub mod ibl;
+pub mod id_buffer;
+pub mod image;
+pub mod material;
+pub mod mesh;
 pub mod obj_loader;
+pub mod post_process;
 pub mod rasterizer;
+pub mod scene;
 pub mod shader;
+pub mod ssao;
+pub mod ssr;
+pub mod tessellation;
 pub mod texture;
 pub mod triangle;
+pub mod vertex_buffer;
 
 use nalgebra::{Matrix3, Matrix4, Vector3, Vector4};
 
@@ -90,8 +108,10 @@ pub fn vertex_shader(payload: &shader::VertexShaderPayload) -> Vector3<f32> {
 }
 
 pub fn normal_fragment_shader(payload: &shader::FragmentShaderPayload) -> Vector3<f32> {
-    let return_color = (payload.normal.normalize() + Vector3::from_element(1f32)) / 2f32;
-    return_color * 255f32
+    apply_fog(
+        payload,
+        (payload.normal.normalize() + Vector3::from_element(1f32)) / 2f32,
+    )
 }
 
 pub fn reflect(vec: &Vector3<f32>, axis: &Vector3<f32>) -> Vector3<f32> {
@@ -99,16 +119,66 @@ pub fn reflect(vec: &Vector3<f32>, axis: &Vector3<f32>) -> Vector3<f32> {
     (2f32 * costheta * axis - vec).normalize()
 }
 
+/// 点光源 交给着色器时 position 在观察空间
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Light {
-    position: Vector3f,
-    intensity: Vector3f,
+    pub position: Vector3f,
+    pub intensity: Vector3f,
+}
+
+impl Light {
+    pub fn new(position: Vector3f, intensity: Vector3f) -> Self {
+        Self {
+            position,
+            intensity,
+        }
+    }
 }
 
 fn scale(a: Vector3f, b: Vector3f) -> Vector3f {
     Vector3f::new(a.x * b.x, a.y * b.y, a.z * b.z)
 }
 
+// 作业中固定的两个点光源 位置在观察空间
+static DEFAULT_LIGHTS: [Light; 2] = [
+    Light {
+        position: Vector3f::new(20f32, 20f32, 20f32),
+        intensity: Vector3f::new(500f32, 500f32, 500f32),
+    },
+    Light {
+        position: Vector3f::new(-20f32, 20f32, 0f32),
+        intensity: Vector3f::new(500f32, 500f32, 500f32),
+    },
+];
+
+pub fn default_lights() -> Vec<Light> {
+    DEFAULT_LIGHTS.to_vec()
+}
+
+// 没有通过 Rasterizer::set_lights 设置光源时使用默认的两个 每个片元都会调用 不做分配
+fn lights<'a>(payload: &shader::FragmentShaderPayload<'a>) -> &'a [Light] {
+    if payload.lights.is_empty() {
+        &DEFAULT_LIGHTS
+    } else {
+        payload.lights
+    }
+}
+
+/// 所有内置着色器在返回前混合场景的雾 没有设置雾时原样返回
+/// 自定义着色器也可以调用它得到一致的效果
+pub fn apply_fog(payload: &shader::FragmentShaderPayload, color: Vector3f) -> Vector3f {
+    match payload.fog {
+        None => color,
+        Some(fog) => {
+            let point = payload.view_to_world * payload.view_pos + payload.eye_pos;
+            fog.apply(color, &payload.eye_pos, &point)
+        }
+    }
+}
+
+// 调用方传入的 ka 已经乘上了 SSAO 的环境光可见度
 fn blinn_phone_calc(
+    lights: &[Light],
     ka: Vector3f,
     kd: Vector3f,
     ks: Vector3f,
@@ -116,16 +186,6 @@ fn blinn_phone_calc(
     point: Vector3f,
     normal: Vector3f,
 ) -> Vector3f {
-    let l1: Light = Light {
-        position: Vector3f::from_element(20f32),
-        intensity: Vector3f::from_element(500f32),
-    };
-    let l2: Light = Light {
-        position: Vector3f::new(-20f32, 20f32, 0f32),
-        intensity: Vector3f::from_element(500f32),
-    };
-    let lights: Vec<Light> = vec![l1, l2];
-
     let ambient_light_intensity: Vector3f = Vector3f::from_element(10f32);
 
     let p: i32 = 150;
@@ -154,16 +214,20 @@ fn blinn_phone_calc(
         ret += la;
     }
 
-    ret * 255f32
+    ret
 }
 
 pub fn texture_fragment_shader(payload: &shader::FragmentShaderPayload) -> Vector3f {
     let texture_color = match payload.texture {
         None => nalgebra::zero(),
-        Some(texture) => texture.get_color_bilinear(payload.tex_coords[0], payload.tex_coords[1]),
+        Some(texture) => texture.sample(
+            payload.tex_coords,
+            payload.tex_coords_dx,
+            payload.tex_coords_dy,
+        ),
     };
 
-    let ka = Vector3f::from_element(0.005);
+    let ka = Vector3f::from_element(0.005) * payload.ambient_occlusion;
     let kd = texture_color / 255f32;
     let ks = Vector3f::from_element(0.7937);
 
@@ -171,11 +235,12 @@ pub fn texture_fragment_shader(payload: &shader::FragmentShaderPayload) -> Vecto
     let point = payload.view_pos;
     let normal = payload.normal;
 
-    blinn_phone_calc(ka, kd, ks, color, point, normal)
+    let ret = blinn_phone_calc(lights(payload), ka, kd, ks, color, point, normal);
+    apply_fog(payload, ret)
 }
 
 pub fn phone_fragment_shader(payload: &shader::FragmentShaderPayload) -> Vector3f {
-    let ka = Vector3f::from_element(0.005);
+    let ka = Vector3f::from_element(0.005) * payload.ambient_occlusion;
     let kd = payload.color;
     let ks = Vector3f::from_element(0.7937);
 
@@ -183,28 +248,47 @@ pub fn phone_fragment_shader(payload: &shader::FragmentShaderPayload) -> Vector3
     let point = payload.view_pos;
     let normal = payload.normal;
 
-    blinn_phone_calc(ka, kd, ks, color, point, normal)
+    let ret = blinn_phone_calc(lights(payload), ka, kd, ks, color, point, normal);
+    apply_fog(payload, ret)
 }
 
-fn calc_bump_normal(payload: &shader::FragmentShaderPayload) -> Vector3f {
-    let kh = 0.2;
-    let kn = 0.1;
-
+// 插值得到的 TBN 没有切线时 (比如没有 uv 的网格) 退回到只由法线推出的切线
+fn tbn_matrix(payload: &shader::FragmentShaderPayload) -> Matrix3<f32> {
     let normal = payload.normal;
+    if payload.tangent.magnitude_squared() > 0f32 {
+        return Matrix3::from_columns(&[payload.tangent, payload.bitangent, normal]);
+    }
     let (x, y, z) = (normal.x, normal.y, normal.z);
-    let t = Vector3f::new(
-        x * y / (x * x + z * z).sqrt(),
-        (x * x + z * z).sqrt(),
-        z * y / (x * x + z * z).sqrt(),
-    );
+    let r = (x * x + z * z).sqrt();
+    // 法线沿 ±y 时上式除以 0 改用 x 轴作切线
+    let t = if r > 1e-6 {
+        Vector3f::new(x * y / r, r, z * y / r)
+    } else {
+        Vector3f::x()
+    };
     let b = normal.cross(&t);
+    Matrix3::from_columns(&[t, b, normal])
+}
+
+fn luminance(color: Vector3f) -> f32 {
+    color.dot(&Vector3f::new(0.2126, 0.7152, 0.0722))
+}
+
+fn calc_bump_normal(payload: &shader::FragmentShaderPayload) -> Vector3f {
+    let kh = 0.2;
+    let kn = 0.1;
 
     let u = payload.tex_coords[0];
     let v = payload.tex_coords[1];
-    let texture = payload.texture.unwrap();
-    let h = |u, v| texture.get_color(u, v).magnitude();
-
-    let tbn = Matrix3::from_columns(&[t, b, normal]);
+    // 材质带有 bump 贴图时用它作高度图
+    let texture = payload
+        .material
+        .and_then(|m| m.texture(material::TextureUnit::Bump))
+        .or(payload.texture)
+        .unwrap();
+    let h = |u, v| luminance(texture.get_color(u, v));
+
+    let tbn = tbn_matrix(payload);
     let du = kh * kn * (h(u + 1f32 / texture.width as f32, v) - h(u, v));
     let dv = kh * kn * (h(u, v + 1f32 / texture.height as f32) - h(u, v));
     let ln = Vector3f::new(-du, -dv, 1f32);
@@ -213,7 +297,7 @@ fn calc_bump_normal(payload: &shader::FragmentShaderPayload) -> Vector3f {
 }
 
 pub fn displacement_fragment_shader(payload: &shader::FragmentShaderPayload) -> Vector3f {
-    let ka = Vector3f::from_element(0.005);
+    let ka = Vector3f::from_element(0.005) * payload.ambient_occlusion;
     let kd = payload.color;
     let ks = Vector3f::from_element(0.7937);
 
@@ -222,10 +306,225 @@ pub fn displacement_fragment_shader(payload: &shader::FragmentShaderPayload) ->
 
     let normal = calc_bump_normal(payload);
 
-    blinn_phone_calc(ka, kd, ks, color, point, normal)
+    let ret = blinn_phone_calc(lights(payload), ka, kd, ks, color, point, normal);
+    apply_fog(payload, ret)
 }
 
 pub fn bump_fragment_shader(payload: &shader::FragmentShaderPayload) -> Vector3f {
-    let normal = calc_bump_normal(payload);
-    normal * 255f32
+    apply_fog(payload, calc_bump_normal(payload))
+}
+
+// 切线空间法线贴图 rgb 映射到 [-1, 1] 材质没有法线贴图时使用插值的法线
+fn calc_normal_map_normal(payload: &shader::FragmentShaderPayload) -> Vector3f {
+    let texture = payload
+        .material
+        .and_then(|m| m.texture(material::TextureUnit::Normal));
+    match texture {
+        None => payload.normal,
+        Some(texture) => {
+            let color = texture.sample(
+                payload.tex_coords,
+                payload.tex_coords_dx,
+                payload.tex_coords_dy,
+            );
+            let ln = color / 255f32 * 2f32 - Vector3f::from_element(1f32);
+            (tbn_matrix(payload) * ln).normalize()
+        }
+    }
+}
+
+pub fn normal_map_fragment_shader(payload: &shader::FragmentShaderPayload) -> Vector3f {
+    let ka = Vector3f::from_element(0.005) * payload.ambient_occlusion;
+    let kd = payload.color;
+    let ks = Vector3f::from_element(0.7937);
+
+    let color = payload.color;
+    let point = payload.view_pos;
+
+    let normal = calc_normal_map_normal(payload);
+
+    let ret = blinn_phone_calc(lights(payload), ka, kd, ks, color, point, normal);
+    apply_fog(payload, ret)
+}
+
+fn refract(incident: &Vector3f, normal: &Vector3f, eta: f32) -> Option<Vector3f> {
+    let cos = -normal.dot(incident);
+    let k = 1f32 - eta * eta * (1f32 - cos * cos);
+    if k < 0f32 {
+        None
+    } else {
+        Some(incident * eta + normal * (eta * cos - k.sqrt()))
+    }
+}
+
+// 反射和折射都从环境贴图取颜色 按 Schlick 菲涅尔项混合 全反射时只有反射
+pub fn environment_fragment_shader(payload: &shader::FragmentShaderPayload) -> Vector3f {
+    let environment = match payload.environment {
+        None => return apply_fog(payload, nalgebra::zero()),
+        Some(environment) => environment,
+    };
+    // 没有材质或者折射率无效时当作玻璃
+    let ior = payload
+        .material
+        .map(|m| m.ni)
+        .filter(|&ni| ni >= 1f32)
+        .unwrap_or(1.5);
+
+    let incident = payload.view_pos.normalize();
+    let normal = payload.normal.normalize();
+    let reflected = incident - 2f32 * incident.dot(&normal) * normal;
+    let sample = |dir: &Vector3f| environment.sample(&(payload.view_to_world * dir)) / 255f32;
+
+    let f0 = ((ior - 1f32) / (ior + 1f32)).powi(2);
+    let cos = (-incident.dot(&normal)).max(0f32);
+    let fresnel = f0 + (1f32 - f0) * (1f32 - cos).powi(5);
+    let ret = match refract(&incident, &normal, 1f32 / ior) {
+        None => sample(&reflected),
+        Some(refracted) => sample(&reflected) * fresnel + sample(&refracted) * (1f32 - fresnel),
+    };
+    apply_fog(payload, ret)
+}
+
+fn texture_scale(payload: &shader::FragmentShaderPayload, unit: material::TextureUnit) -> f32 {
+    payload
+        .material
+        .and_then(|m| m.texture(unit))
+        .map_or(1f32, |texture| {
+            texture
+                .sample(
+                    payload.tex_coords,
+                    payload.tex_coords_dx,
+                    payload.tex_coords_dy,
+                )
+                .x
+                / 255f32
+        })
+}
+
+// Cook-Torrance 镜面反射 GGX 法线分布 Smith 几何项 Schlick 菲涅尔项
+// 漫反射按 (1 - F)(1 - metallic) 分配能量 有 IBL 时用 split sum 近似计算环境光
+pub fn pbr_fragment_shader(payload: &shader::FragmentShaderPayload) -> Vector3f {
+    let base_color = match (payload.texture, payload.material) {
+        (Some(texture), _) => {
+            texture.sample(
+                payload.tex_coords,
+                payload.tex_coords_dx,
+                payload.tex_coords_dy,
+            ) / 255f32
+        }
+        (None, Some(material)) => material.kd,
+        (None, None) => payload.color,
+    };
+    let metallic = payload.material.map_or(0f32, |m| m.metallic)
+        * texture_scale(payload, material::TextureUnit::Metallic);
+    let roughness = (payload.material.map_or(0.5, |m| m.roughness)
+        * texture_scale(payload, material::TextureUnit::Roughness))
+    .max(0.04)
+    .min(1f32);
+
+    let normal = match payload
+        .material
+        .and_then(|m| m.texture(material::TextureUnit::Normal))
+    {
+        Some(_) => calc_normal_map_normal(payload),
+        None => payload.normal.normalize(),
+    };
+    let point = payload.view_pos;
+    let v = (-point).normalize();
+    let n_dot_v = normal.dot(&v).max(1e-4);
+    let f0 = Vector3f::from_element(0.04).lerp(&base_color, metallic);
+    let fresnel = |cos: f32, f0: Vector3f, max: Vector3f| f0 + (max - f0) * (1f32 - cos).powi(5);
+
+    let mut ret: Vector3f = nalgebra::zero();
+    for light in lights(payload) {
+        let l = light.position - point;
+        let radiance = light.intensity / l.magnitude_squared();
+        let l = l.normalize();
+        let h = (l + v).normalize();
+        let n_dot_l = normal.dot(&l);
+        if n_dot_l <= 0f32 {
+            continue;
+        }
+        let f = fresnel(h.dot(&v).max(0f32), f0, Vector3f::from_element(1f32));
+        let d = ibl::distribution_ggx(normal.dot(&h).max(0f32), roughness);
+        let g = ibl::geometry_smith(n_dot_v, n_dot_l, roughness);
+        let specular = f * (d * g / (4f32 * n_dot_v * n_dot_l).max(1e-4));
+        let kd = (Vector3f::from_element(1f32) - f) * (1f32 - metallic);
+        let diffuse = scale(kd, base_color) / MY_PI;
+        ret += scale(diffuse + specular, radiance) * n_dot_l;
+    }
+
+    let ambient = match payload.ibl {
+        None => base_color * 0.03,
+        Some(ibl) => {
+            // 粗糙表面的菲涅尔项不会接近 1
+            let max = Vector3f::from_element(1f32 - roughness).sup(&f0);
+            let f = fresnel(n_dot_v, f0, max);
+            let kd = (Vector3f::from_element(1f32) - f) * (1f32 - metallic);
+            let world_normal = payload.view_to_world * normal;
+            let world_reflected = payload.view_to_world * (2f32 * normal.dot(&v) * normal - v);
+            let diffuse = scale(
+                scale(kd, base_color),
+                ibl.irradiance(&world_normal) / 255f32,
+            );
+            let brdf = ibl.brdf(n_dot_v, roughness);
+            let specular = scale(
+                ibl.prefiltered(&world_reflected, roughness) / 255f32,
+                f * brdf.x + Vector3f::from_element(brdf.y),
+            );
+            diffuse + specular
+        }
+    };
+    apply_fog(payload, ret + ambient * payload.ambient_occlusion)
+}
+
+// 卡通着色漫反射的色阶数
+const TOON_BANDS: f32 = 3f32;
+
+// 把 [0, 1] 的光照量化成 TOON_BANDS 个色阶 最暗的一级保留环境光
+fn toon_ramp(x: f32) -> f32 {
+    let band = (x.max(0f32).min(1f32) * TOON_BANDS)
+        .floor()
+        .min(TOON_BANDS - 1f32);
+    0.3 + 0.7 * band / (TOON_BANDS - 1f32)
+}
+
+// 卡通着色 漫反射按光源方向量化成色阶 高光只有有和无两种 只关心光源方向不计距离衰减
+// 轮廓线由后处理的 Outline 效果绘制
+pub fn toon_fragment_shader(payload: &shader::FragmentShaderPayload) -> Vector3f {
+    let base_color = match payload.texture {
+        Some(texture) => {
+            texture.sample(
+                payload.tex_coords,
+                payload.tex_coords_dx,
+                payload.tex_coords_dy,
+            ) / 255f32
+        }
+        None => payload.color,
+    };
+    let normal = payload.normal.normalize();
+    let v = (-payload.view_pos).normalize();
+
+    let lights = lights(payload);
+    // 按强度加权 最亮的光源权重为 1
+    let max_intensity = lights
+        .iter()
+        .map(|light| light.intensity.max())
+        .fold(f32::EPSILON, f32::max);
+    let mut diffuse = 0f32;
+    let mut highlight = false;
+    for light in lights {
+        let l = (light.position - payload.view_pos).normalize();
+        let weight = light.intensity.max() / max_intensity;
+        diffuse += normal.dot(&l).max(0f32) * weight;
+        let h = (l + v).normalize();
+        highlight |= weight > 0.5 && normal.dot(&h).max(0f32).powi(64) > 0.5;
+    }
+
+    let shade = toon_ramp(diffuse) * payload.ambient_occlusion;
+    let specular = if highlight { 0.3 } else { 0f32 };
+    apply_fog(
+        payload,
+        base_color * shade + Vector3f::from_element(specular),
+    )
 }
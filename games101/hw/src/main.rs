@@ -1,139 +1,327 @@
-use nalgebra::{Vector3, Vector4};
-use opencv::{core, highgui, imgcodecs, imgproc, prelude::*};
-use opencv_learn::{obj_loader::Loader, rasterizer, texture::Texture, triangle::Triangle, *};
-use std::default::Default;
+use nalgebra::{UnitQuaternion, Vector3, Vector4};
+use opencv::{core, highgui, imgcodecs, prelude::*};
+use opencv_learn::animation::{Timeline, Transform};
+use opencv_learn::cli::{self, CliError, Options, Pipeline, Shader};
+use opencv_learn::post_process::{Outline, PostEffect, PostProcess};
+use opencv_learn::{cube_map::CubeMap, gif::GifEncoder, ibl::Ibl, rasterizer, scene::Scene};
+use opencv_learn::{texture::Texture, *};
 use std::env;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use std::process;
+use std::sync::{Arc, Mutex};
 
-const SIZE: i32 = 700;
+const ENVIRONMENT_MAP: &str = "../../games202/homework4/homework4/assets/cubemap/GraceCathedral";
+
+fn set_matrices(angle: f32, options: &Options, r: &mut rasterizer::Rasterizer) {
+    r.set_model(&opencv_learn::get_model_matrix(angle));
+    r.set_view(&opencv_learn::get_view_matrix(options.eye));
+    r.set_projection(&opencv_learn::get_projection_matrix(
+        45f32,
+        options.width as f32 / options.height as f32,
+        0.1f32,
+        50f32,
+    ));
+}
 
 fn draw_image(
-    angle: &f32,
-    eye_pos: &Vector3<f32>,
+    angle: f32,
+    options: &Options,
     r: &mut rasterizer::Rasterizer,
-    triangle_list: &Vec<triangle::Triangle>,
+    scene: &Scene,
+    skybox: Option<&CubeMap>,
 ) -> Mat {
-    r.clear(rasterizer::Buffers::Color | rasterizer::Buffers::Depth);
+    r.clear(rasterizer::Buffers::COLOR | rasterizer::Buffers::DEPTH);
 
-    r.set_model(&opencv_learn::get_model_matrix(*angle));
-    r.set_view(&opencv_learn::get_view_matrix(*eye_pos));
-    r.set_projection(&opencv_learn::get_projection_matrix(
-        45f32, 1f32, 0.1f32, 50f32,
-    ));
+    set_matrices(angle, options, r);
 
-    r.draw_triangles(&triangle_list.iter().map(|t| t).collect());
-    let mut buf = r.frame_buffer();
-    let ptr = buf.as_mut_ptr() as *mut std::ffi::c_void;
-    let mut ret = Mat::default().unwrap();
-    unsafe {
-        let image =
-            Mat::new_rows_cols_with_data(SIZE, SIZE, core::CV_32FC3, ptr, core::Mat_AUTO_STEP)
-                .expect("build image fail");
-        let mut temp = Mat::default().unwrap();
-        image
-            .convert_to(&mut temp, core::CV_8UC3, 1f64, 0f64)
-            .expect("convert err");
-        imgproc::cvt_color(&temp, &mut ret, imgproc::COLOR_RGB2BGR, 0).expect("cvt color error");
+    r.draw_scene(scene);
+    if let Some(skybox) = skybox {
+        r.draw_skybox(skybox);
     }
-    ret
+    let mut buf = r.output_buffer();
+    image::to_mat(options.width, options.height, &mut buf).expect("build image fail")
 }
 
-type Vector3f = Vector3<f32>;
-fn main() {
-    let args: Vec<_> = env::args().collect();
+// 按时间线渲染 frames 帧 保存为 {prefix}_0000.png 这样的序列 输出文件是 .gif 时再编码成动图
+fn render_animation(
+    r: &mut rasterizer::Rasterizer,
+    scene: &Scene,
+    skybox: Option<&CubeMap>,
+    options: &Options,
+    frames: usize,
+    filename: &str,
+) -> Result<(), String> {
+    let model = Transform::new(
+        nalgebra::zero(),
+        UnitQuaternion::from_axis_angle(&Vector3::y_axis(), options.angle.to_radians()),
+        Vector3::from_element(2.5),
+    );
+    let timeline = Timeline::turntable(options.eye, model, 1f32);
 
-    let mut command_line = false;
-    let mut filename = "output.png";
-
-    let mut triangles = vec![];
-    let obj_path = "./models/spot/".to_owned();
-    // load obj file
-    {
-        let mut loader: Loader = Default::default();
-        loader
-            .load_file(&(obj_path.clone() + "spot_triangulated_good.obj"))
-            .expect("load file err");
-        for mesh in &loader.loaded_meshes {
-            let mut index = 0;
-            while index < mesh.vertices.len() {
-                let mut t: Triangle = Default::default();
-                for j in 0..3 {
-                    let vert = mesh.vertices[index + j];
-                    t.set_vertex(
-                        j,
-                        Vector4::new(vert.position.x, vert.position.y, vert.position.z, 1f32),
-                    );
-                    t.set_normal(j, vert.normal);
-                    t.set_tex_coord(j, vert.texture_coordinates);
-                }
-                triangles.push(t);
-                index += 3;
-            }
+    let prefix = filename.rsplitn(2, '.').last().unwrap_or(filename);
+    let mut gif = if filename.ends_with(".gif") {
+        let file =
+            File::create(filename).map_err(|e| format!("can not create '{}': {}", filename, e))?;
+        // 一圈大约 3 秒
+        let delay = (300 / frames.max(1)).max(2) as u16;
+        let encoder = GifEncoder::new(BufWriter::new(file), options.width, options.height, delay);
+        Some(encoder.map_err(|e| format!("can not write '{}': {}", filename, e))?)
+    } else {
+        None
+    };
+    set_matrices(options.angle, options, r);
+    for (i, frame) in timeline.frames(frames).enumerate() {
+        println!("Rendering frame {}/{}", i + 1, frames);
+        r.clear(rasterizer::Buffers::COLOR | rasterizer::Buffers::DEPTH);
+        frame.apply(r);
+        r.draw_scene(scene);
+        if let Some(skybox) = skybox {
+            r.draw_skybox(skybox);
         }
+        let mut buf = r.output_buffer();
+        let path = format!("{}_{:04}.png", prefix, i);
+        image::save(&path, options.width, options.height, &mut buf)
+            .map_err(|e| format!("can not write '{}': {}", path, e))?;
+        if let Some(gif) = gif.as_mut() {
+            gif.add_frame(&buf)
+                .map_err(|e| format!("can not write '{}': {}", filename, e))?;
+        }
+    }
+    if let Some(gif) = gif {
+        gif.finish()
+            .map_err(|e| format!("can not write '{}': {}", filename, e))?;
+    }
+    Ok(())
+}
+
+fn load_texture(path: &str) -> Result<Texture, String> {
+    if !Path::new(path).is_file() {
+        return Err(format!("texture file '{}' does not exist", path));
     }
+    Ok(Texture::new(path))
+}
 
-    let mut angle = 140f32;
-    let mut r = rasterizer::Rasterizer::new(SIZE, SIZE);
-
-    let texture_path = "hmap.jpg";
-    r.set_texture(Texture::new(&(obj_path.clone() + texture_path)));
-
-    let mut active_shader: fn(&shader::FragmentShaderPayload) -> Vector3f = phone_fragment_shader;
-    if args.len() >= 2 {
-        command_line = true;
-        filename = &args[1];
-        if args.len() >= 3 {
-            match &args[2][..] {
-                "texture" => {
-                    println!("Rasterizing using the texture shader");
-                    active_shader = texture_fragment_shader;
-                    r.set_texture(Texture::new(&(obj_path.clone() + "spot_texture_low.png")));
-                }
-                "normal" => {
-                    println!("Rasterizing using the normal shader");
-                    active_shader = normal_fragment_shader;
-                }
-                "phong" => {
-                    println!("Rasterizing using the phong shader");
-                    active_shader = phone_fragment_shader;
-                }
-                "bump" => {
-                    println!("Rasterizing using the bump shader");
-                    active_shader = bump_fragment_shader;
-                }
-                "displacement" => {
-                    println!("Resterizing using the displacement shader");
-                    active_shader = displacement_fragment_shader;
-                }
-                arg => println!("error shader argument {}", arg),
-            }
+fn load_environment() -> Result<Arc<CubeMap>, String> {
+    if !Path::new(ENVIRONMENT_MAP).is_dir() {
+        return Err(format!(
+            "environment map directory '{}' does not exist",
+            ENVIRONMENT_MAP
+        ));
+    }
+    Ok(Arc::new(CubeMap::from_dir(ENVIRONMENT_MAP)))
+}
+
+// 按参数和着色器设置 Rasterizer 返回需要绘制的天空盒
+fn setup(
+    options: &Options,
+    shader: Shader,
+    r: &mut rasterizer::Rasterizer,
+) -> Result<Option<Arc<CubeMap>>, String> {
+    let texture_path = match &options.texture {
+        Some(path) => path.clone(),
+        None => Path::new(&options.model)
+            .with_file_name(shader.default_texture())
+            .to_string_lossy()
+            .into_owned(),
+    };
+    r.set_texture(load_texture(&texture_path)?);
+    r.set_msaa(options.msaa);
+    let mut post_process = if options.post_process {
+        PostProcess::standard()
+    } else {
+        PostProcess::default()
+    };
+    // 描边自带抗锯齿 放在链的最后
+    if shader == Shader::Toon || options.outline.is_some() {
+        let default = Outline::default();
+        post_process.push(PostEffect::Outline(Outline {
+            width: options.outline.unwrap_or(default.width),
+            ..default
+        }));
+    }
+    if !post_process.effects.is_empty() {
+        r.set_post_process(post_process);
+    }
+    if let Some(fog) = options.fog {
+        r.set_fog(Some(fog));
+        r.set_clear_values(rasterizer::ClearValues {
+            color: Vector4::new(fog.color.x, fog.color.y, fog.color.z, 1f32),
+            ..Default::default()
+        });
+    }
+    match options.pipeline {
+        Pipeline::Forward => {}
+        Pipeline::Deferred => r.set_deferred(true),
+        Pipeline::Ssao => r.set_ssao(Some(Default::default())),
+        Pipeline::Ssr => r.set_ssr(Some(Default::default())),
+    }
+
+    let mut skybox = None;
+    match shader {
+        Shader::Displacement => r.set_tessellation(Some(Default::default())),
+        Shader::Environment => {
+            let environment = load_environment()?;
+            r.set_environment(Some(environment.clone()));
+            skybox = Some(environment);
         }
+        Shader::Pbr => {
+            let environment = load_environment()?;
+            r.set_ibl(Some(Arc::new(Ibl::new(&environment))));
+            r.set_environment(Some(environment.clone()));
+            skybox = Some(environment);
+        }
+        _ => {}
+    }
+    // 天空盒在无穷远处 有雾时看不到 背景就是雾的颜色
+    if options.fog.is_some() {
+        skybox = None;
     }
+    Ok(skybox)
+}
 
-    r.set_fragment_shader(&active_shader);
-    r.set_vertex_shader(&vertex_shader);
+// --all-shaders 时每个着色器的输出文件 output.png 变为 output_phong.png
+fn shader_output(output: &str, shader: Shader) -> String {
+    let path = Path::new(output);
+    let stem = path
+        .file_stem()
+        .map_or_else(|| output.to_owned(), |s| s.to_string_lossy().into_owned());
+    let name = match path.extension() {
+        Some(ext) => format!("{}_{}.{}", stem, shader.name(), ext.to_string_lossy()),
+        None => format!("{}_{}", stem, shader.name()),
+    };
+    path.with_file_name(name).to_string_lossy().into_owned()
+}
 
-    let eye_pos = Vector3::new(0f32, 0f32, 10f32);
-    let mut key = 0 as u8;
-    // let mut frame_count = 0;
+fn fail(message: &str) -> ! {
+    eprintln!("error: {}", message);
+    process::exit(1);
+}
+
+fn main() {
+    let args: Vec<_> = env::args().collect();
+    let program = args.first().map_or("opencv_learn", |s| &s[..]);
+    let options = match cli::parse(args.iter().skip(1)) {
+        Ok(options) => options,
+        Err(CliError::Help) => {
+            println!("{}", cli::usage(program));
+            return;
+        }
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, cli::usage(program));
+            process::exit(2);
+        }
+    };
 
-    if command_line {
-        let image = draw_image(&angle, &eye_pos, &mut r, &triangles);
-        imgcodecs::imwrite(filename, &image, &core::Vector::new()).unwrap();
+    // load obj file, textures referenced by the mtl are bound per mesh
+    if !Path::new(&options.model).is_file() {
+        fail(&format!("model file '{}' does not exist", options.model));
+    }
+    let meshes = mesh::load_obj(&options.model)
+        .unwrap_or_else(|e| fail(&format!("can not load '{}': {}", options.model, e)));
+    // 每个网格一个节点 整体的旋转仍然由 set_model 给出
+    let scene = Scene::from_meshes(meshes);
+
+    let shaders = if options.all_shaders {
+        Shader::all().to_vec()
     } else {
-        while key != 27 {
-            let image = draw_image(&angle, &eye_pos, &mut r, &triangles);
+        vec![options.shader]
+    };
+    for shader in shaders {
+        println!("Rasterizing using the {} shader", shader.name());
+        let active_shader = shader.function();
+        let mut r = rasterizer::Rasterizer::new(options.width, options.height);
+        let skybox = setup(&options, shader, &mut r).unwrap_or_else(|e| fail(&e));
+        r.set_fragment_shader(&active_shader);
+        r.set_vertex_shader(&vertex_shader);
+
+        let output = match &options.output {
+            Some(output) if options.all_shaders => shader_output(output, shader),
+            Some(output) => output.clone(),
+            None => {
+                show(&options, &mut r, &scene, skybox.as_deref());
+                continue;
+            }
+        };
+        if let Some(frames) = options.turntable {
+            render_animation(&mut r, &scene, skybox.as_deref(), &options, frames, &output)
+                .unwrap_or_else(|e| fail(&e));
+            continue;
+        }
 
-            highgui::imshow("show image", &image).unwrap();
-            key = highgui::wait_key(0).unwrap() as u8;
+        let image = draw_image(options.angle, &options, &mut r, &scene, skybox.as_deref());
+        match imgcodecs::imwrite(&output, &image, &core::Vector::new()) {
+            Ok(true) => println!("Saved {}", output),
+            Ok(false) => fail(&format!("can not write '{}'", output)),
+            Err(e) => fail(&format!("can not write '{}': {}", output, e)),
+        }
+        // 延迟着色时把 G-buffer 各通道一起保存
+        if options.pipeline != Pipeline::Forward {
+            let prefix = output.rsplitn(2, '.').last().unwrap_or(&output);
+            r.gbuffer()
+                .save_all(prefix)
+                .unwrap_or_else(|e| fail(&format!("can not write the G-buffer: {}", e)));
+        }
+    }
+}
 
-            // frame_count += 1;
-            if key == b'a' {
-                angle += 10f32;
+// 打开窗口 a d 旋转模型 esc 退出 鼠标左键点击打印拾取到的网格
+fn show(
+    options: &Options,
+    r: &mut rasterizer::Rasterizer,
+    scene: &Scene,
+    skybox: Option<&CubeMap>,
+) {
+    const WINDOW: &str = "show image";
+    let clicked = Arc::new(Mutex::new(None));
+    let sink = clicked.clone();
+    highgui::named_window(WINDOW, highgui::WINDOW_AUTOSIZE).unwrap();
+    highgui::set_mouse_callback(
+        WINDOW,
+        Some(Box::new(move |event, x, y, _| {
+            if event == highgui::EVENT_LBUTTONDOWN {
+                *sink.lock().unwrap() = Some((x, y));
             }
+        })),
+    )
+    .unwrap();
+    r.set_id_buffer(true);
 
-            if key == b'd' {
-                angle -= 10f32;
+    let mut angle = options.angle;
+    let mut redraw = true;
+    let mut key = 0 as u8;
+    while key != 27 {
+        if redraw {
+            let image = draw_image(angle, options, r, scene, skybox);
+            highgui::imshow(WINDOW, &image).unwrap();
+            redraw = false;
+        }
+        key = highgui::wait_key(20).unwrap() as u8;
+
+        if let Some((x, y)) = clicked.lock().unwrap().take() {
+            match r.pick(x, y) {
+                Some(hit) => println!(
+                    "({}, {}): {} triangle {} at {:?} normal {:?}",
+                    x,
+                    y,
+                    scene.node(hit.mesh_id as usize).name,
+                    hit.triangle_id,
+                    hit.position.as_slice(),
+                    hit.normal.as_slice()
+                ),
+                None => println!("({}, {}): nothing", x, y),
             }
         }
+
+        if key == b'a' {
+            angle += 10f32;
+            redraw = true;
+        }
+
+        if key == b'd' {
+            angle -= 10f32;
+            redraw = true;
+        }
     }
 }
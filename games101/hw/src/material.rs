@@ -0,0 +1,166 @@
+use super::obj_loader;
+use super::texture::Texture;
+use nalgebra::Vector3;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+type Vector3f = Vector3<f32>;
+
+/// 材质上可以绑定纹理的槽位
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TextureUnit {
+    Ambient,
+    Diffuse,
+    Specular,
+    SpecularHighlight,
+    Alpha,
+    Bump,
+    Normal,
+    Displacement,
+    Metallic,
+    Roughness,
+}
+
+impl TextureUnit {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TextureUnit::Ambient => "ambient",
+            TextureUnit::Diffuse => "diffuse",
+            TextureUnit::Specular => "specular",
+            TextureUnit::SpecularHighlight => "specular_highlight",
+            TextureUnit::Alpha => "alpha",
+            TextureUnit::Bump => "bump",
+            TextureUnit::Normal => "normal",
+            TextureUnit::Displacement => "displacement",
+            TextureUnit::Metallic => "metallic",
+            TextureUnit::Roughness => "roughness",
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Material {
+    pub name: String,
+    pub ka: Vector3f,
+    pub kd: Vector3f,
+    pub ks: Vector3f,
+    pub ns: f32,
+    /// 折射率
+    pub ni: f32,
+    /// dissolve 1 为完全不透明
+    pub d: f32,
+    /// PBR 着色器使用的金属度和粗糙度 绑定了对应贴图时再乘以贴图的值
+    pub metallic: f32,
+    pub roughness: f32,
+    textures: HashMap<TextureUnit, Arc<Texture>>,
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            ka: nalgebra::zero(),
+            kd: nalgebra::zero(),
+            ks: nalgebra::zero(),
+            ns: 0f32,
+            ni: 1f32,
+            d: 1f32,
+            metallic: 0f32,
+            roughness: 0.5,
+            textures: HashMap::new(),
+        }
+    }
+}
+
+// mtl 里的路径可能是别的机器上的绝对路径 也可能带 -bm 之类的选项
+// 先按相对 obj 目录查找 找不到再只用文件名查找
+fn resolve_map_path(dir: &str, map: &str) -> Option<String> {
+    let map = map.split_whitespace().last()?;
+    let candidates = [
+        Path::new(dir).join(map),
+        Path::new(dir).join(map.rsplit(|c| c == '/' || c == '\\').next()?),
+    ];
+    candidates
+        .iter()
+        .find(|p| p.is_file())
+        .map(|p| p.to_string_lossy().into_owned())
+}
+
+impl Material {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    /// 从 obj_loader 的材质构造 并加载所有贴图 dir 为 obj 文件所在目录
+    /// 同一路径的贴图只加载一次 在 cache 中共享 找不到的贴图在 stderr 上警告并跳过
+    pub fn from_obj(
+        mat: &obj_loader::Material,
+        dir: &str,
+        cache: &mut HashMap<String, Arc<Texture>>,
+    ) -> Self {
+        let mut ret = Self {
+            name: mat.name.clone(),
+            ka: mat.ka,
+            kd: mat.kd,
+            ks: mat.ks,
+            ns: mat.ns,
+            ni: mat.ni,
+            d: mat.d,
+            metallic: mat.pm.unwrap_or(0f32),
+            // 没有 Pr 时按 Blinn-Phong 的高光指数换算
+            roughness: mat
+                .pr
+                .unwrap_or_else(|| (2f32 / (mat.ns.max(0f32) + 2f32)).sqrt()),
+            ..Default::default()
+        };
+        let maps = [
+            (TextureUnit::Ambient, &mat.map_ka),
+            (TextureUnit::Diffuse, &mat.map_kd),
+            (TextureUnit::Specular, &mat.map_ks),
+            (TextureUnit::SpecularHighlight, &mat.map_ns),
+            (TextureUnit::Alpha, &mat.map_d),
+            (TextureUnit::Bump, &mat.map_bump),
+            (TextureUnit::Normal, &mat.map_norm),
+            (TextureUnit::Displacement, &mat.map_disp),
+            (TextureUnit::Metallic, &mat.map_pm),
+            (TextureUnit::Roughness, &mat.map_pr),
+        ];
+        for (unit, map) in maps.iter() {
+            if map.is_empty() {
+                continue;
+            }
+            match resolve_map_path(dir, map) {
+                None => eprintln!(
+                    "texture not found, material: {}, {}: {}",
+                    mat.name,
+                    unit.name(),
+                    map
+                ),
+                Some(path) => {
+                    let texture = cache
+                        .entry(path.clone())
+                        .or_insert_with(|| Arc::new(Texture::new(&path)))
+                        .clone();
+                    ret.textures.insert(*unit, texture);
+                }
+            }
+        }
+        ret
+    }
+
+    pub fn bind_texture(&mut self, unit: TextureUnit, texture: Arc<Texture>) {
+        self.textures.insert(unit, texture);
+    }
+
+    pub fn texture(&self, unit: TextureUnit) -> Option<&Texture> {
+        self.textures.get(&unit).map(|t| t.as_ref())
+    }
+
+    pub fn is_transparent(&self) -> bool {
+        self.d < 1f32 || self.textures.contains_key(&TextureUnit::Alpha)
+    }
+}
@@ -0,0 +1,84 @@
+use super::material::Material;
+use super::obj_loader::{self, Loader};
+use super::triangle::Triangle;
+use nalgebra::{Vector3, Vector4};
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+/// 可以直接交给 Rasterizer 绘制的网格 每个网格带有自己的材质
+#[derive(Default, Clone)]
+pub struct Mesh {
+    pub name: String,
+    pub triangles: Vec<Triangle>,
+    pub material: Option<Arc<Material>>,
+}
+
+impl Mesh {
+    pub fn from_obj_mesh(mesh: &obj_loader::Mesh) -> Self {
+        let mut triangles = vec![];
+        let mut index = 0;
+        while index + 2 < mesh.indices.len() {
+            let mut t: Triangle = Default::default();
+            for j in 0..3 {
+                let vert = mesh.vertices[mesh.indices[index + j]];
+                t.set_vertex(
+                    j,
+                    Vector4::new(vert.position.x, vert.position.y, vert.position.z, 1f32),
+                );
+                t.set_normal(j, vert.normal);
+                t.set_tex_coord(j, vert.texture_coordinates);
+                t.set_tangent(j, vert.tangent);
+            }
+            triangles.push(t);
+            index += 3;
+        }
+        Self {
+            name: mesh.name.clone(),
+            triangles,
+            material: None,
+        }
+    }
+
+    /// 模型空间的轴对齐包围盒 (最小点, 最大点) 没有三角形时为 None
+    pub fn bounds(&self) -> Option<(Vector3<f32>, Vector3<f32>)> {
+        let mut vertices = self
+            .triangles
+            .iter()
+            .flat_map(|t| t.v.iter().map(|v| v.xyz()));
+        let first = vertices.next()?;
+        Some(vertices.fold((first, first), |(min, max), v| (min.inf(&v), max.sup(&v))))
+    }
+}
+
+/// 加载 obj 文件 mtl 中引用的贴图按材质绑定到各个网格上
+pub fn load_obj(path: &str) -> io::Result<Vec<Mesh>> {
+    let mut loader: Loader = Default::default();
+    loader.load_file(path)?;
+
+    let dir = Path::new(path)
+        .parent()
+        .map(|p| p.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let mut texture_cache = HashMap::new();
+    let mut materials: HashMap<String, Arc<Material>> = HashMap::new();
+
+    Ok(loader
+        .loaded_meshes
+        .iter()
+        .map(|m| {
+            let mut mesh = Mesh::from_obj_mesh(m);
+            if !m.material.name.is_empty() {
+                let material = materials
+                    .entry(m.material.name.clone())
+                    .or_insert_with(|| {
+                        Arc::new(Material::from_obj(&m.material, &dir, &mut texture_cache))
+                    })
+                    .clone();
+                mesh.material = Some(material);
+            }
+            mesh
+        })
+        .collect())
+}
@@ -1,4 +1,6 @@
-use nalgebra::{Vector2, Vector3};
+use nalgebra::{Vector2, Vector3, Vector4};
+use std::collections::HashMap;
+type Vector4f = Vector4<f32>;
 type Vector3f = Vector3<f32>;
 type Vector2f = Vector2<f32>;
 
@@ -7,6 +9,8 @@ pub struct Vertex {
     pub position: Vector3f,
     pub normal: Vector3f,
     pub texture_coordinates: Vector2f,
+    /// Tangent, w is the handedness of the bitangent: b = w * cross(n, t)
+    pub tangent: Vector4f,
 }
 
 #[derive(Default, Clone)]
@@ -27,6 +31,10 @@ pub struct Material {
     pub d: f32,
     /// Illumination
     pub illum: i32,
+    /// PBR extension, metallic
+    pub pm: Option<f32>,
+    /// PBR extension, roughness
+    pub pr: Option<f32>,
 
     pub map_ka: String,
     pub map_kd: String,
@@ -34,6 +42,10 @@ pub struct Material {
     pub map_ns: String,
     pub map_d: String,
     pub map_bump: String,
+    pub map_norm: String,
+    pub map_disp: String,
+    pub map_pm: String,
+    pub map_pr: String,
 }
 
 #[derive(Default)]
@@ -45,7 +57,8 @@ pub struct Mesh {
 }
 
 impl Mesh {
-    pub fn new(vertices: Vec<Vertex>, indices: Vec<usize>) -> Self {
+    pub fn new(mut vertices: Vec<Vertex>, indices: Vec<usize>) -> Self {
+        algorithm::compute_tangents(&mut vertices, &indices);
         Self {
             vertices,
             indices,
@@ -78,7 +91,7 @@ mod math {
 }
 
 mod algorithm {
-    use super::Vector3f;
+    use super::{HashMap, Vector3f, Vector4f, Vertex};
     fn same_side(p1: Vector3f, p2: Vector3f, a: Vector3f, b: Vector3f) -> bool {
         let cp1 = (b - a).cross(&(p1 - a));
         let cp2 = (b - a).cross(&(p2 - a));
@@ -135,6 +148,80 @@ mod algorithm {
         s.split(token).collect()
     }
 
+    // Vertices with equal position, normal and uv count as one and share a tangent
+    fn vertex_key(v: &Vertex) -> [u32; 8] {
+        [
+            v.position.x.to_bits(),
+            v.position.y.to_bits(),
+            v.position.z.to_bits(),
+            v.normal.x.to_bits(),
+            v.normal.y.to_bits(),
+            v.normal.z.to_bits(),
+            v.texture_coordinates.x.to_bits(),
+            v.texture_coordinates.y.to_bits(),
+        ]
+    }
+
+    // Like MikkTSpace: compute each triangle's tangent and bitangent from its uvs,
+    // accumulate them on shared vertices weighted by the corner angle,
+    // then orthogonalize against the normal
+    pub fn compute_tangents(vertices: &mut Vec<Vertex>, indices: &Vec<usize>) {
+        let mut sums: HashMap<[u32; 8], (Vector3f, Vector3f)> = HashMap::new();
+        for face in indices.chunks(3).filter(|face| face.len() == 3) {
+            let (v0, v1, v2) = (vertices[face[0]], vertices[face[1]], vertices[face[2]]);
+            let (e1, e2) = (v1.position - v0.position, v2.position - v0.position);
+            let duv1 = v1.texture_coordinates - v0.texture_coordinates;
+            let duv2 = v2.texture_coordinates - v0.texture_coordinates;
+            let r = duv1.x * duv2.y - duv2.x * duv1.y;
+            if r.abs() < f32::EPSILON {
+                continue;
+            }
+            let t = (e1 * duv2.y - e2 * duv1.y) / r;
+            let b = (e2 * duv1.x - e1 * duv2.x) / r;
+
+            for k in 0..3 {
+                let p = vertices[face[k]].position;
+                let a = vertices[face[(k + 1) % 3]].position - p;
+                let c = vertices[face[(k + 2) % 3]].position - p;
+                let weight = if a.magnitude() > 0f32 && c.magnitude() > 0f32 {
+                    super::math::angle_between_v3(a, c)
+                } else {
+                    0f32
+                };
+                let sum = sums
+                    .entry(vertex_key(&vertices[face[k]]))
+                    .or_insert((nalgebra::zero(), nalgebra::zero()));
+                sum.0 += t * weight;
+                sum.1 += b * weight;
+            }
+        }
+
+        for vert in vertices.iter_mut() {
+            let n = vert.normal.normalize();
+            let (t, b) = sums
+                .get(&vertex_key(vert))
+                .cloned()
+                .unwrap_or((nalgebra::zero(), nalgebra::zero()));
+            let mut t = t - n * n.dot(&t);
+            if t.magnitude() < f32::EPSILON {
+                // No uvs or degenerate uvs, pick any direction perpendicular to the normal
+                let axis = if n.x.abs() < 0.9 {
+                    Vector3f::x()
+                } else {
+                    Vector3f::y()
+                };
+                t = axis - n * n.dot(&axis);
+            }
+            let t = t.normalize();
+            let w = if n.cross(&t).dot(&b) < 0f32 {
+                -1f32
+            } else {
+                1f32
+            };
+            vert.tangent = Vector4f::new(t.x, t.y, t.z, w);
+        }
+    }
+
     pub fn get_element<T: Clone>(elements: &Vec<T>, index: &str) -> T {
         let index: i32 = index.parse().unwrap();
         let index = if index < 0 {
@@ -301,6 +388,8 @@ impl Loader {
                         temp = Default::default();
                     }
                     first = false;
+                    temp.d = 1f32;
+                    temp.ni = 1f32;
                     temp.name = if line.len() > 7 {
                         algorithm::tail(&line)
                     } else {
@@ -312,13 +401,15 @@ impl Loader {
                 // diffuse color
                 "Kd" => temp.kd = algorithm::get_vector3_from_line(&line),
                 // specular color
-                "Ks" => temp.kd = algorithm::get_vector3_from_line(&line),
+                "Ks" => temp.ks = algorithm::get_vector3_from_line(&line),
                 // specular exponent
                 "Ns" => temp.ns = algorithm::tail(&line).parse().unwrap(),
                 // optical density
                 "Ni" => temp.ni = algorithm::tail(&line).parse().unwrap(),
                 // dissolv
                 "d" => temp.d = algorithm::tail(&line).parse().unwrap(),
+                // transparency, inverse of dissolve
+                "Tr" => temp.d = 1f32 - algorithm::tail(&line).parse::<f32>().unwrap(),
                 // Illumination
                 "illum" => temp.illum = algorithm::tail(&line).parse().unwrap(),
                 // ambient texture map
@@ -326,13 +417,22 @@ impl Loader {
                 // diffuse texture map
                 "map_Kd" => temp.map_kd = algorithm::tail(&line),
                 // specular texture map
-                "map_Ks" => temp.map_kd = algorithm::tail(&line),
+                "map_Ks" => temp.map_ks = algorithm::tail(&line),
                 // specular highlight map
                 "map_Ns" => temp.map_ns = algorithm::tail(&line),
                 // alpha texture map
                 "map_d" => temp.map_d = algorithm::tail(&line),
                 // bump map
                 "map_Bump" | "map_bump" | "bump" => temp.map_bump = algorithm::tail(&line),
+                // tangent space normal map
+                "norm" | "map_Kn" => temp.map_norm = algorithm::tail(&line),
+                // displacement map
+                "disp" => temp.map_disp = algorithm::tail(&line),
+                // PBR extension
+                "Pm" => temp.pm = algorithm::tail(&line).parse().ok(),
+                "Pr" => temp.pr = algorithm::tail(&line).parse().ok(),
+                "map_Pm" => temp.map_pm = algorithm::tail(&line),
+                "map_Pr" => temp.map_pr = algorithm::tail(&line),
                 _ => (),
             };
         }
@@ -354,7 +454,9 @@ impl Loader {
         let mut normals = vec![];
         let mut vertices = vec![];
         let mut indices = vec![];
+        // Material name of each mesh, parallel to loaded_meshes
         let mut mesh_mat_names: Vec<String> = vec![];
+        let mut current_mat_name = "".to_owned();
 
         let mut listening = false;
         let mut mesh_name = "".to_owned();
@@ -374,10 +476,7 @@ impl Loader {
                     print!("\t| texcoords > {}", tex_coords.len());
                     print!("\t| normals > {}", normals.len());
                     print!("\t| triangles > {}", vertices.len() / 3);
-                    print!(
-                        "\t| material: {}",
-                        mesh_mat_names.last().unwrap_or(&" ".to_owned())
-                    );
+                    print!("\t| material: {}", current_mat_name);
                     println!("")
                 }
             }
@@ -389,6 +488,7 @@ impl Loader {
                         temp.name = mesh_name.to_string();
 
                         self.loaded_meshes.push(temp);
+                        mesh_mat_names.push(current_mat_name.clone());
 
                         vertices.clear();
                         indices.clear();
@@ -434,7 +534,6 @@ impl Loader {
                     }
                 }
                 "usemtl" => {
-                    mesh_mat_names.push(algorithm::tail(&line));
                     // Create new Mesh, if Material changes within a group
                     if !indices.is_empty() && !vertices.is_empty() {
                         let mut temp_mesh = Mesh::new(vertices.clone(), indices.clone());
@@ -455,7 +554,9 @@ impl Loader {
                             }
                         }
                         self.loaded_meshes.push(temp_mesh);
+                        mesh_mat_names.push(current_mat_name.clone());
                     }
+                    current_mat_name = algorithm::tail(&line);
 
                     #[cfg(feature = "show_loader_print")]
                     {
@@ -492,9 +593,13 @@ impl Loader {
             temp.name = mesh_name.to_owned();
 
             self.loaded_meshes.push(temp);
+            mesh_mat_names.push(current_mat_name.clone());
         }
 
         for i in 0..mesh_mat_names.len() {
+            if mesh_mat_names[i].is_empty() {
+                continue;
+            }
             match self
                 .loaded_materials
                 .iter()
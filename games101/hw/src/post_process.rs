@@ -0,0 +1,337 @@
+use nalgebra::Vector3;
+
+type Vector3f = Vector3<f32>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ToneMapping {
+    /// 每个通道 x / (1 + x)
+    Reinhard,
+    /// Narkowicz 对 ACES 曲线的拟合
+    Aces,
+}
+
+/// 后处理链中的一步
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PostEffect {
+    /// 线性颜色乘以 2^ev
+    Exposure(f32),
+    ToneMap(ToneMapping),
+    /// 线性颜色编码为 sRGB
+    SrgbEncode,
+    /// 应该放在色调映射和 sRGB 编码之后 按显示亮度找边缘
+    Fxaa,
+    /// radius 为开始变暗的位置 (中心到角落的比例) strength 为角落变暗的比例
+    Vignette {
+        strength: f32,
+        radius: f32,
+    },
+    /// 需要 Geometry 没有时跳过
+    Outline(Outline),
+}
+
+/// 按深度和法线的不连续找轮廓线和折痕 画上墨线
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Outline {
+    /// 线宽 (像素) 可以是小数 边缘做抗锯齿
+    pub width: f32,
+    pub color: Vector3f,
+    /// 相邻像素深度的相对差超过它时为轮廓
+    pub depth_threshold: f32,
+    /// 相邻像素法线夹角的余弦小于它时为折痕
+    pub normal_threshold: f32,
+}
+
+impl Default for Outline {
+    fn default() -> Self {
+        Self {
+            width: 2f32,
+            color: nalgebra::zero(),
+            depth_threshold: 0.02,
+            normal_threshold: 0.7,
+        }
+    }
+}
+
+/// 描边使用的逐像素几何信息 和颜色的排列相同
+pub struct Geometry<'a> {
+    /// 屏幕空间深度 越小越近 没有被覆盖的像素为清除值
+    pub depth: &'a [f32],
+    /// 单位法线 没有被覆盖的像素为 0
+    pub normal: &'a [Vector3f],
+}
+
+/// 从 frame buffer 的线性 HDR 颜色得到最终显示颜色的后处理链 按顺序执行
+/// 链为空时只把颜色截断到 [0, 1]
+#[derive(Debug, Clone, Default)]
+pub struct PostProcess {
+    pub effects: Vec<PostEffect>,
+}
+
+// 按行存储的 rgb 图像
+struct Image {
+    width: i32,
+    height: i32,
+    data: Vec<Vector3f>,
+}
+
+impl Image {
+    fn at(&self, x: i32, y: i32) -> Vector3f {
+        let x = x.max(0).min(self.width - 1);
+        let y = y.max(0).min(self.height - 1);
+        self.data[(y * self.width + x) as usize]
+    }
+}
+
+fn luma(color: &Vector3f) -> f32 {
+    color.dot(&Vector3f::new(0.299, 0.587, 0.114))
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).max(0f32).min(1f32);
+    t * t * (3f32 - 2f32 * t)
+}
+
+fn tone_map(mapping: ToneMapping, c: f32) -> f32 {
+    let c = c.max(0f32);
+    match mapping {
+        ToneMapping::Reinhard => c / (1f32 + c),
+        ToneMapping::Aces => (c * (2.51 * c + 0.03)) / (c * (2.43 * c + 0.59) + 0.14),
+    }
+}
+
+fn srgb_encode(c: f32) -> f32 {
+    let c = c.max(0f32).min(1f32);
+    if c <= 0.0031308 {
+        12.92 * c
+    } else {
+        1.055 * c.powf(1f32 / 2.4) - 0.055
+    }
+}
+
+// FXAA 3.11 quality 版本的简化 先按亮度判断边缘方向 再沿边缘搜索两端
+// 由像素到端点的距离决定和边缘另一侧像素混合的比例
+fn fxaa(image: &Image) -> Vec<Vector3f> {
+    const EDGE_THRESHOLD: f32 = 0.125;
+    const EDGE_THRESHOLD_MIN: f32 = 0.0312;
+    const SEARCH_STEPS: i32 = 12;
+    const SUBPIXEL_QUALITY: f32 = 0.75;
+
+    let l = |x: i32, y: i32| luma(&image.at(x, y));
+    let mut ret = image.data.clone();
+    for y in 0..image.height {
+        for x in 0..image.width {
+            let m = l(x, y);
+            let (n, s, e, w) = (l(x, y - 1), l(x, y + 1), l(x + 1, y), l(x - 1, y));
+            let max = m.max(n).max(s).max(e).max(w);
+            let min = m.min(n).min(s).min(e).min(w);
+            let range = max - min;
+            if range < EDGE_THRESHOLD_MIN.max(max * EDGE_THRESHOLD) {
+                continue;
+            }
+            let (nw, ne, sw, se) = (
+                l(x - 1, y - 1),
+                l(x + 1, y - 1),
+                l(x - 1, y + 1),
+                l(x + 1, y + 1),
+            );
+
+            // 和周围平均亮度差别越大 越像是单个像素的锯齿
+            let average = (2f32 * (n + s + e + w) + nw + ne + sw + se) / 12f32;
+            let subpixel = smoothstep(0f32, 1f32, ((average - m).abs() / range).min(1f32));
+            let subpixel = subpixel * subpixel * SUBPIXEL_QUALITY;
+
+            let horizontal = ((n + s) - 2f32 * m).abs() * 2f32
+                + ((ne + se) - 2f32 * e).abs()
+                + ((nw + sw) - 2f32 * w).abs();
+            let vertical = ((e + w) - 2f32 * m).abs() * 2f32
+                + ((ne + nw) - 2f32 * n).abs()
+                + ((se + sw) - 2f32 * s).abs();
+            let is_horizontal = horizontal >= vertical;
+
+            // 边缘两侧 取亮度变化更大的一侧
+            let (l1, l2) = if is_horizontal { (n, s) } else { (w, e) };
+            let (g1, g2) = ((l1 - m).abs(), (l2 - m).abs());
+            let (side, side_luma, gradient) = if g1 >= g2 { (-1, l1, g1) } else { (1, l2, g2) };
+            let edge_luma = (m + side_luma) / 2f32;
+            let gradient = gradient / 4f32;
+
+            // 沿边缘方向 在像素和另一侧像素之间取亮度
+            let (along, across) = if is_horizontal {
+                ((1, 0), (0, side))
+            } else {
+                ((0, 1), (side, 0))
+            };
+            let edge_at = |k: i32| {
+                let (px, py) = (x + along.0 * k, y + along.1 * k);
+                (l(px, py) + l(px + across.0, py + across.1)) / 2f32
+            };
+            let search = |dir: i32| {
+                let mut k = 1;
+                while k < SEARCH_STEPS && (edge_at(dir * k) - edge_luma).abs() < gradient {
+                    k += 1;
+                }
+                (k, edge_at(dir * k) - edge_luma)
+            };
+            let (dist1, delta1) = search(-1);
+            let (dist2, delta2) = search(1);
+            let (dist, delta) = if dist1 < dist2 {
+                (dist1, delta1)
+            } else {
+                (dist2, delta2)
+            };
+            // 端点的亮度变化方向和当前像素一致时才需要混合
+            let offset = if (m - edge_luma < 0f32) != (delta < 0f32) {
+                0.5 - dist as f32 / (dist1 + dist2) as f32
+            } else {
+                0f32
+            };
+            let offset = offset.max(subpixel);
+
+            let other = image.at(x + across.0, y + across.1);
+            ret[(y * image.width + x) as usize] = image.at(x, y).lerp(&other, offset);
+        }
+    }
+    ret
+}
+
+// 先标记边缘像素 深度的跳变只标记近的一侧 线条落在物体上而不是背景上
+// 再按到最近边缘像素的距离画线
+fn outline(image: &mut Image, geometry: &Geometry, config: &Outline) {
+    let (width, height) = (image.width, image.height);
+    let at = |x: i32, y: i32| (y * width + x) as usize;
+    let mut edge = vec![false; (width * height) as usize];
+    for y in 0..height {
+        for x in 0..width {
+            let (d, n) = (geometry.depth[at(x, y)], geometry.normal[at(x, y)]);
+            for (dx, dy) in [(1, 0), (-1, 0), (0, 1), (0, -1)].iter() {
+                let (qx, qy) = (x + dx, y + dy);
+                if qx < 0 || qx >= width || qy < 0 || qy >= height {
+                    continue;
+                }
+                let (qd, qn) = (geometry.depth[at(qx, qy)], geometry.normal[at(qx, qy)]);
+                let silhouette = qd - d > config.depth_threshold * d.abs().max(f32::EPSILON);
+                // 折痕两侧都会检测到 只标记右边和下边的像素
+                let crease = (*dx < 0 || *dy < 0)
+                    && n != Vector3f::zeros()
+                    && qn != Vector3f::zeros()
+                    && n.dot(&qn) < config.normal_threshold;
+                if silhouette || crease {
+                    edge[at(x, y)] = true;
+                    break;
+                }
+            }
+        }
+    }
+
+    let half = config.width.max(0f32) / 2f32;
+    let radius = (half + 0.5).ceil() as i32;
+    let source = image.data.clone();
+    for y in 0..height {
+        for x in 0..width {
+            let mut nearest = f32::MAX;
+            for qy in (y - radius).max(0)..(y + radius + 1).min(height) {
+                for qx in (x - radius).max(0)..(x + radius + 1).min(width) {
+                    if edge[at(qx, qy)] {
+                        let d = (((qx - x) * (qx - x) + (qy - y) * (qy - y)) as f32).sqrt();
+                        nearest = nearest.min(d);
+                    }
+                }
+            }
+            // 1 像素宽时只有边缘像素本身 线宽更大时边缘按距离过渡
+            let coverage = (half + 0.5 - nearest).max(0f32).min(1f32);
+            if coverage > 0f32 {
+                image.data[at(x, y)] = source[at(x, y)].lerp(&config.color, coverage);
+            }
+        }
+    }
+}
+
+fn vignette(image: &mut Image, strength: f32, radius: f32) {
+    let (cx, cy) = (image.width as f32 / 2f32, image.height as f32 / 2f32);
+    let corner = (cx * cx + cy * cy).sqrt();
+    for y in 0..image.height {
+        for x in 0..image.width {
+            let (dx, dy) = (x as f32 + 0.5 - cx, y as f32 + 0.5 - cy);
+            let d = (dx * dx + dy * dy).sqrt() / corner;
+            let factor = 1f32 - strength * smoothstep(radius, 1f32, d);
+            image.data[(y * image.width + x) as usize] *= factor;
+        }
+    }
+}
+
+impl PostProcess {
+    pub fn new(effects: Vec<PostEffect>) -> Self {
+        Self { effects }
+    }
+
+    /// 常用的 HDR 输出 ACES 色调映射 sRGB 编码后做 FXAA
+    pub fn standard() -> Self {
+        Self::new(vec![
+            PostEffect::Exposure(0f32),
+            PostEffect::ToneMap(ToneMapping::Aces),
+            PostEffect::SrgbEncode,
+            PostEffect::Fxaa,
+        ])
+    }
+
+    pub fn push(&mut self, effect: PostEffect) {
+        self.effects.push(effect);
+    }
+
+    /// 链中有描边时需要传入 Geometry
+    pub fn needs_geometry(&self) -> bool {
+        self.effects
+            .iter()
+            .any(|e| matches!(e, PostEffect::Outline(_)))
+    }
+
+    /// hdr 为按行存储的线性 rgb 返回同样排列的显示颜色 范围 [0, 1]
+    pub fn apply(&self, width: i32, height: i32, hdr: &[f32]) -> Vec<f32> {
+        self.apply_with(width, height, hdr, None)
+    }
+
+    pub fn apply_with(
+        &self,
+        width: i32,
+        height: i32,
+        hdr: &[f32],
+        geometry: Option<&Geometry>,
+    ) -> Vec<f32> {
+        let mut image = Image {
+            width,
+            height,
+            data: hdr
+                .chunks(3)
+                .take((width * height) as usize)
+                .map(|c| Vector3f::new(c[0], c[1], c[2]))
+                .collect(),
+        };
+        for effect in &self.effects {
+            match *effect {
+                PostEffect::Exposure(ev) => {
+                    let scale = 2f32.powf(ev);
+                    image.data.iter_mut().for_each(|c| *c *= scale);
+                }
+                PostEffect::ToneMap(mapping) => image
+                    .data
+                    .iter_mut()
+                    .for_each(|c| *c = c.map(|v| tone_map(mapping, v))),
+                PostEffect::SrgbEncode => {
+                    image.data.iter_mut().for_each(|c| *c = c.map(srgb_encode))
+                }
+                PostEffect::Fxaa => image.data = fxaa(&image),
+                PostEffect::Vignette { strength, radius } => vignette(&mut image, strength, radius),
+                PostEffect::Outline(config) => {
+                    if let Some(geometry) = geometry {
+                        outline(&mut image, geometry, &config);
+                    }
+                }
+            }
+        }
+        image
+            .data
+            .iter()
+            .flat_map(|c| c.iter().map(|v| v.max(0f32).min(1f32)).collect::<Vec<_>>())
+            .collect()
+    }
+}
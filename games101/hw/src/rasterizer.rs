@@ -1,25 +1,205 @@
-use num_derive::FromPrimitive;
-use num_traits::FromPrimitive;
+use bitflags::bitflags;
 
-#[derive(FromPrimitive, std::cmp::PartialEq, Clone)]
-pub enum Buffers {
-    Color = 1,
-    Depth = 2,
-    Both = 3,
+bitflags! {
+    pub struct Buffers: u32 {
+        const COLOR = 0b001;
+        const DEPTH = 0b010;
+        const STENCIL = 0b100;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompareFunc {
+    Never,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Equal,
+    NotEqual,
+    Always,
+}
+
+impl CompareFunc {
+    pub fn test<T: PartialOrd>(&self, a: T, b: T) -> bool {
+        match self {
+            CompareFunc::Never => false,
+            CompareFunc::Less => a < b,
+            CompareFunc::LessEqual => a <= b,
+            CompareFunc::Greater => a > b,
+            CompareFunc::GreaterEqual => a >= b,
+            CompareFunc::Equal => a == b,
+            CompareFunc::NotEqual => a != b,
+            CompareFunc::Always => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StencilOp {
+    Keep,
+    Zero,
+    Replace,
+    IncrementClamp,
+    DecrementClamp,
+    Invert,
+    IncrementWrap,
+    DecrementWrap,
+}
+
+impl StencilOp {
+    fn apply(&self, value: u8, reference: u8) -> u8 {
+        match self {
+            StencilOp::Keep => value,
+            StencilOp::Zero => 0,
+            StencilOp::Replace => reference,
+            StencilOp::IncrementClamp => value.saturating_add(1),
+            StencilOp::DecrementClamp => value.saturating_sub(1),
+            StencilOp::Invert => !value,
+            StencilOp::IncrementWrap => value.wrapping_add(1),
+            StencilOp::DecrementWrap => value.wrapping_sub(1),
+        }
+    }
+}
+
+/// 和 OpenGL 一致 (reference & read_mask) func (stencil & read_mask) 时通过
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StencilState {
+    pub func: CompareFunc,
+    pub reference: u8,
+    pub read_mask: u8,
+    pub write_mask: u8,
+    /// 模板测试失败
+    pub fail: StencilOp,
+    /// 模板测试通过 深度测试失败
+    pub depth_fail: StencilOp,
+    /// 都通过
+    pub pass: StencilOp,
+}
+
+impl Default for StencilState {
+    fn default() -> Self {
+        Self {
+            func: CompareFunc::Always,
+            reference: 0,
+            read_mask: 0xff,
+            write_mask: 0xff,
+            fail: StencilOp::Keep,
+            depth_fail: StencilOp::Keep,
+            pass: StencilOp::Keep,
+        }
+    }
+}
+
+impl StencilState {
+    fn test(&self, stencil: u8) -> bool {
+        self.func
+            .test(self.reference & self.read_mask, stencil & self.read_mask)
+    }
+
+    fn update(&self, op: StencilOp, stencil: u8) -> u8 {
+        (stencil & !self.write_mask) | (op.apply(stencil, self.reference) & self.write_mask)
+    }
+}
+
+/// clear 时各个 buffer 写入的值
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClearValues {
+    pub color: Vector4<f32>,
+    pub depth: f32,
+    pub stencil: u8,
+}
+
+impl Default for ClearValues {
+    fn default() -> Self {
+        Self {
+            color: Vector4::zeros(),
+            depth: f32::MAX,
+            stencil: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlendFactor {
+    Zero,
+    One,
+    SrcAlpha,
+    OneMinusSrcAlpha,
+    DstAlpha,
+    OneMinusDstAlpha,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlendOp {
+    Add,
+    Subtract,
+    ReverseSubtract,
+    Min,
+    Max,
 }
 
-impl std::ops::BitOr for Buffers {
-    type Output = Self;
+/// 混合方程 result = op(src * src_factor, dst * dst_factor)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BlendState {
+    pub src_factor: BlendFactor,
+    pub dst_factor: BlendFactor,
+    pub op: BlendOp,
+}
 
-    fn bitor(self, rhs: Self) -> Self {
-        FromPrimitive::from_i32(self as i32 | rhs as i32).unwrap()
+impl BlendState {
+    pub fn alpha_blending() -> Self {
+        Self {
+            src_factor: BlendFactor::SrcAlpha,
+            dst_factor: BlendFactor::OneMinusSrcAlpha,
+            op: BlendOp::Add,
+        }
+    }
+
+    pub fn additive() -> Self {
+        Self {
+            src_factor: BlendFactor::One,
+            dst_factor: BlendFactor::One,
+            op: BlendOp::Add,
+        }
+    }
+
+    fn factor(factor: BlendFactor, src: &Vector4<f32>, dst: &Vector4<f32>) -> f32 {
+        match factor {
+            BlendFactor::Zero => 0f32,
+            BlendFactor::One => 1f32,
+            BlendFactor::SrcAlpha => src.w,
+            BlendFactor::OneMinusSrcAlpha => 1f32 - src.w,
+            BlendFactor::DstAlpha => dst.w,
+            BlendFactor::OneMinusDstAlpha => 1f32 - dst.w,
+        }
+    }
+
+    pub fn blend(&self, src: &Vector4<f32>, dst: &Vector4<f32>) -> Vector4<f32> {
+        let s = src * Self::factor(self.src_factor, src, dst);
+        let d = dst * Self::factor(self.dst_factor, src, dst);
+        match self.op {
+            BlendOp::Add => s + d,
+            BlendOp::Subtract => s - d,
+            BlendOp::ReverseSubtract => d - s,
+            BlendOp::Min => src.inf(dst),
+            BlendOp::Max => src.sup(dst),
+        }
     }
 }
 
-impl std::ops::BitAnd for Buffers {
-    type Output = Self;
-    fn bitand(self, rhs: Self) -> Self {
-        FromPrimitive::from_i32(self as i32 & rhs as i32).unwrap()
+/// 材质半透明 (d < 1 或带 map_d) 的网格怎样绘制
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Transparency {
+    /// 不透明物体之后从远到近排序 再做 alpha 混合
+    Sorted,
+    /// weighted blended order-independent transparency
+    WeightedBlended,
+}
+
+impl Default for Transparency {
+    fn default() -> Self {
+        Transparency::Sorted
     }
 }
 
@@ -30,8 +210,8 @@ pub enum Primitive {
 }
 
 #[derive(Default, Debug, Clone)]
-pub struct PosBufID {
-    pos_id: usize,
+pub struct VertBufID {
+    vert_id: usize,
 }
 
 #[derive(Default, Debug, Clone)]
@@ -40,16 +220,44 @@ pub struct IndBufID {
 }
 
 #[derive(Default, Debug, Clone)]
-pub struct ColBufID {
-    col_id: usize,
+pub struct InstBufID {
+    inst_id: usize,
 }
 
 extern crate nalgebra as na;
+use super::cube_map::CubeMap;
+use super::fog::Fog;
+use super::gbuffer::{GBuffer, GSample};
+use super::hiz::{CullStats, HiZ};
+use super::ibl::Ibl;
+use super::id_buffer::{IdSample, Pick, PickTriangle};
+use super::material::{Material, TextureUnit};
+use super::mesh::Mesh;
+use super::post_process::{Geometry, PostProcess};
+use super::scene::Scene;
 use super::shader::*;
+use super::ssao::{self, Ssao};
+use super::ssr::{self, Ssr};
+use super::tessellation::{self, Tessellation};
+use super::texture::Texture;
 use super::triangle::Triangle;
-use na::{Matrix4, Point3, Vector3, Vector4};
+use super::vertex_buffer::{Instance, VertexBuffer, VertexCacheStats};
+use super::Light;
+use na::{Matrix3, Matrix4, Vector2, Vector3, Vector4};
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+// 屏幕分块的边长 (像素)
+const TILE_SIZE: i32 = 32;
+// 顶点吸附到 1/256 像素的定点网格
+const SUBPIXEL_BITS: u32 = 8;
+const SUBPIXEL_ONE: i64 = 1 << SUBPIXEL_BITS;
+// 超出这个范围的顶点坐标会被截断 保证边函数的乘积不会溢出
+const MAX_SCREEN_COORD: f32 = (1 << 20) as f32;
 
+type Vector2f = Vector2<f32>;
 type Vector3f = Vector3<f32>;
 type Vector4f = Vector4<f32>;
 fn to_vector4<T>(vector3: Vector3<T>, w: T) -> Vector4<T>
@@ -69,28 +277,260 @@ where
     (v[0] * alpha + v[1] * beta + v[2] * gamma) / weight
 }
 
+fn to_fixed(v: f32) -> i64 {
+    (v.max(-MAX_SCREEN_COORD).min(MAX_SCREEN_COORD) * SUBPIXEL_ONE as f32).round() as i64
+}
+
+// 定点坐标下的三个边函数 E(x, y) = a * x + b * y + c
+// 边 k 是顶点 k 对面的边 E / area 就是顶点 k 的重心坐标
+// 符号统一成三角形内部为正 所以两种绕向的三角形都会被画出来
+struct EdgeFunctions {
+    a: [i64; 3],
+    b: [i64; 3],
+    c: [i64; 3],
+    // 左上规则 不是上边或左边的边上的点不算覆盖
+    bias: [i64; 3],
+    area: i64,
+}
+
+impl EdgeFunctions {
+    // 退化成线或点的三角形返回 None
+    fn new(v: &[Vector4f; 3]) -> Option<Self> {
+        let p: Vec<_> = v.iter().map(|v| (to_fixed(v.x), to_fixed(v.y))).collect();
+        let mut ret = Self {
+            a: [0; 3],
+            b: [0; 3],
+            c: [0; 3],
+            bias: [0; 3],
+            area: 0,
+        };
+        for k in 0..3 {
+            let (from, to) = (p[(k + 1) % 3], p[(k + 2) % 3]);
+            ret.a[k] = from.1 - to.1;
+            ret.b[k] = to.0 - from.0;
+            ret.c[k] = from.0 * to.1 - from.1 * to.0;
+        }
+        ret.area = ret.evaluate(p[0].0, p[0].1)[0];
+        if ret.area == 0 {
+            return None;
+        }
+        if ret.area < 0 {
+            for k in 0..3 {
+                ret.a[k] = -ret.a[k];
+                ret.b[k] = -ret.b[k];
+                ret.c[k] = -ret.c[k];
+            }
+            ret.area = -ret.area;
+        }
+        // y 和图像的行同向时 内部在下方的水平边是上边 内部在右侧的边是左边
+        for k in 0..3 {
+            let top_left = ret.a[k] > 0 || (ret.a[k] == 0 && ret.b[k] > 0);
+            ret.bias[k] = if top_left { 0 } else { -1 };
+        }
+        Some(ret)
+    }
+
+    fn evaluate(&self, x: i64, y: i64) -> [i64; 3] {
+        let mut ret = [0; 3];
+        for k in 0..3 {
+            ret[k] = self.a[k] * x + self.b[k] * y + self.c[k];
+        }
+        ret
+    }
+
+    // 向右移动一个像素
+    fn step_x(&self, e: &mut [i64; 3]) {
+        for k in 0..3 {
+            e[k] += self.a[k] * SUBPIXEL_ONE;
+        }
+    }
+
+    fn covers(&self, e: &[i64; 3]) -> bool {
+        (0..3).all(|k| e[k] + self.bias[k] >= 0)
+    }
+
+    fn barycentric(&self, e: &[i64; 3]) -> (f32, f32, f32) {
+        let area = self.area as f32;
+        (e[0] as f32 / area, e[1] as f32 / area, e[2] as f32 / area)
+    }
+}
+
+// 一个屏幕分块 保存该区域内 frame buffer 和 depth buffer 的拷贝
+// 各线程只写自己的分块 结束后再拷回全局 buffer
+struct Tile {
+    x0: i32,
+    y0: i32,
+    x1: i32,
+    y1: i32,
+    frame_buf: Vec<[Vector4f; 4]>,
+    depth_buf: Vec<[f32; 4]>,
+    stencil_buf: Vec<[u8; 4]>,
+    // weighted blended OIT 的累加值和透过率 分块内的三角形都画完后再合成
+    accum: Vec<Vector4f>,
+    revealage: Vec<f32>,
+    // 延迟着色时的 G-buffer 以及本次绘制写入 等待光照的三角形下标
+    gbuffer: Vec<Option<GSample>>,
+    pending: Vec<Option<usize>>,
+    // 开启 ID buffer 和法线缓冲时才有内容
+    id_buf: Vec<Option<IdSample>>,
+    normal_buf: Vec<Vector3f>,
+    // 分块内最远的深度 写入一定次数的深度之后重新计算
+    max_depth: f32,
+    depth_writes: usize,
+    culled_tiles: usize,
+    culled_fragments: usize,
+}
+
+impl Tile {
+    fn local_index(&self, x: i32, y: i32) -> usize {
+        ((y - self.y0) * (self.x1 - self.x0) + x - self.x0) as usize
+    }
+
+    // 写入的次数达到采样点数时才重新计算 平均每次写入只多一次比较
+    fn refresh_max_depth(&mut self, samples: usize) {
+        if self.depth_writes < self.depth_buf.len() * samples {
+            return;
+        }
+        self.max_depth = self
+            .depth_buf
+            .iter()
+            .flat_map(|d| d[..samples].iter())
+            .fold(f32::MIN, |a, &b| a.max(b));
+        self.depth_writes = 0;
+    }
+
+    fn resolve_oit(&mut self, samples: usize) {
+        for (index, pixel) in self.frame_buf.iter_mut().enumerate() {
+            let revealage = self.revealage[index];
+            if revealage >= 1f32 {
+                continue;
+            }
+            let accum = self.accum[index];
+            let color = accum.xyz() / accum.w.max(1e-5);
+            for dst in pixel.iter_mut().take(samples) {
+                let rgb = dst.xyz() * revealage + color * (1f32 - revealage);
+                *dst = Vector4::new(
+                    rgb.x,
+                    rgb.y,
+                    rgb.z,
+                    dst.w + (1f32 - dst.w) * (1f32 - revealage),
+                );
+            }
+        }
+    }
+}
+
+// 三角形所在的绘制阶段
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Pass {
+    Opaque,
+    Blended,
+    WeightedBlended,
+}
+
+// 一次绘制中所有顶点共用的变换矩阵
+#[derive(Clone, Copy)]
+struct VertexTransform {
+    mvp: Matrix4<f32>,
+    mv: Matrix4<f32>,
+    inv_trans_vm: Matrix4<f32>,
+    mesh_id: u32,
+    instance_id: usize,
+    tint: Vector3f,
+    // 为 true 时使用三角形自带的顶点颜色 否则使用 Mesh 的默认颜色
+    vertex_colors: bool,
+}
+
+// 顶点阶段的输出 normal tangent view_pos 在观察空间
+#[derive(Debug, Clone, Copy, Default)]
+struct ClipVertex {
+    ndc: Vector4f,
+    view_pos: Vector3f,
+    normal: Vector3f,
+    tangent: Vector4f,
+    tex_coords: Vector2f,
+    color: Vector3f,
+}
+
+// 经过顶点变换 等待光栅化的三角形
+struct ScreenTriangle<'m> {
+    triangle: Triangle,
+    view_pos: [Vector3f; 3],
+    material: Option<&'m Material>,
+    material_id: u32,
+    pass: Pass,
+    mesh_id: u32,
+    instance_id: u32,
+    triangle_id: u32,
+}
+
+// McGuire & Bavoil 2013 中按观察空间深度衰减的权重
+fn oit_weight(alpha: f32, view_depth: f32) -> f32 {
+    let d = view_depth.abs();
+    alpha
+        * (10f32 / (1e-5 + (d / 5f32).powi(2) + (d / 200f32).powi(6)))
+            .max(1e-2)
+            .min(3e3)
+}
+
 #[derive(Default)]
 pub struct Rasterizer<'a> {
     model: Matrix4<f32>,
     view: Matrix4<f32>,
     projection: Matrix4<f32>,
+    view_to_world: Matrix3<f32>,
+    // 相机在世界空间的位置
+    eye_pos: Vector3f,
 
-    pos_buf: HashMap<usize, Vec<Point3<f32>>>,
-    ind_buf: HashMap<usize, Vec<Vector3<usize>>>,
-    color_buf: HashMap<usize, Vec<Vector3f>>,
-    normal_id: Option<usize>,
-    normal_buf: HashMap<usize, Vec<Vector3f>>,
+    vert_buf: HashMap<usize, Arc<VertexBuffer>>,
+    ind_buf: HashMap<usize, Arc<Vec<u32>>>,
+    inst_buf: HashMap<usize, Arc<Vec<Instance>>>,
 
-    frame_buf: Vec<[Vector3<f32>; 4]>,
+    frame_buf: Vec<[Vector4<f32>; 4]>,
     depth_buf: Vec<[f32; 4]>,
+    stencil_buf: Vec<[u8; 4]>,
+
+    clear_values: ClearValues,
+    blend: Option<BlendState>,
+    depth_write: bool,
+    color_write: bool,
+    stencil: Option<StencilState>,
+    transparency: Transparency,
+    deferred: bool,
+    gbuffer: GBuffer,
+    // 本次绘制写入 G-buffer 等待光照的像素对应的三角形下标
+    pending: Vec<Option<usize>>,
+    // 和 depth buffer 一起写入 为空表示没有开启
+    id_buf: Vec<Option<IdSample>>,
+    normal_buf: Vec<Vector3f>,
+    pick_triangles: Vec<PickTriangle>,
+    mesh_id: u32,
+    hiz: HiZ,
+    occlusion_culling: bool,
+    cull_stats: CullStats,
+    material_names: Vec<String>,
+    ssao: Option<Ssao>,
+    ssr: Option<Ssr>,
+    post_process: PostProcess,
 
     width: i32,
     height: i32,
     next_id: usize,
+    threads: usize,
+    // 每个像素的采样数 延迟着色时只使用像素中心
+    msaa: usize,
+    tessellation: Option<Tessellation>,
 
     texture: Option<super::texture::Texture>,
-    vertex_shader: Option<&'a dyn Fn(&VertexShaderPayload) -> Vector3f>,
-    fragment_shader: Option<&'a dyn Fn(&FragmentShaderPayload) -> Vector3f>,
+    environment: Option<Arc<CubeMap>>,
+    ibl: Option<Arc<Ibl>>,
+    fog: Option<Fog>,
+    // 世界空间的光源 和变换到观察空间后交给着色器的光源
+    lights: Vec<Light>,
+    view_lights: Vec<Light>,
+    vertex_shader: Option<&'a (dyn Fn(&VertexShaderPayload) -> Vector3f + Sync)>,
+    fragment_shader: Option<&'a (dyn Fn(&FragmentShaderPayload) -> Vector3f + Sync)>,
+    fragment_shader_rgba: Option<&'a (dyn Fn(&FragmentShaderPayload) -> Vector4f + Sync)>,
 }
 
 // constructors
@@ -99,11 +539,18 @@ impl Rasterizer<'_> {
         let mut ret = Self {
             width,
             height,
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+            msaa: 1,
+            depth_write: true,
+            color_write: true,
+            view_to_world: Matrix3::identity(),
             ..Default::default()
         };
         ret.frame_buf
             .resize((width * height) as usize, [na::zero(); 4]);
         ret.depth_buf.resize((width * height) as usize, [0f32; 4]);
+        ret.stencil_buf.resize((width * height) as usize, [0u8; 4]);
+        ret.hiz = HiZ::new(width, height, 0f32);
         ret
     }
 }
@@ -111,29 +558,23 @@ impl Rasterizer<'_> {
 // loads and sets
 impl<'a> Rasterizer<'a> {
     // loads
-    pub fn load_positions(&mut self, positions: &Vec<Point3<f32>>) -> PosBufID {
-        let pos_id = self.get_next_id();
-        self.pos_buf.insert(pos_id, positions.clone());
-        PosBufID { pos_id }
+    pub fn load_vertices(&mut self, vertices: VertexBuffer) -> VertBufID {
+        let vert_id = self.get_next_id();
+        self.vert_buf.insert(vert_id, Arc::new(vertices));
+        VertBufID { vert_id }
     }
 
-    pub fn load_indices(&mut self, indices: &Vec<Vector3<usize>>) -> IndBufID {
+    /// 三角形列表 每三个下标一个三角形
+    pub fn load_indices(&mut self, indices: Vec<u32>) -> IndBufID {
         let ind_id = self.get_next_id();
-        self.ind_buf.insert(ind_id, indices.clone());
+        self.ind_buf.insert(ind_id, Arc::new(indices));
         IndBufID { ind_id }
     }
 
-    pub fn load_colors(&mut self, colors: &Vec<Vector3f>) -> ColBufID {
-        let col_id = self.get_next_id();
-        self.color_buf.insert(col_id, colors.clone());
-        ColBufID { col_id }
-    }
-
-    pub fn load_normals(&mut self, normals: &Vec<Vector3f>) -> ColBufID {
-        let col_id = self.get_next_id();
-        self.normal_buf.insert(col_id, normals.clone());
-        self.normal_id = Some(col_id);
-        ColBufID { col_id }
+    pub fn load_instances(&mut self, instances: Vec<Instance>) -> InstBufID {
+        let inst_id = self.get_next_id();
+        self.inst_buf.insert(inst_id, Arc::new(instances));
+        InstBufID { inst_id }
     }
 
     //set
@@ -142,6 +583,10 @@ impl<'a> Rasterizer<'a> {
     }
     pub fn set_view(&mut self, v: &Matrix4<f32>) {
         self.view = v.clone();
+        let inv = v.try_inverse().unwrap_or_else(Matrix4::identity);
+        self.view_to_world = Matrix3::from_fn(|r, c| inv[(r, c)]);
+        self.eye_pos = Vector3f::new(inv[(0, 3)], inv[(1, 3)], inv[(2, 3)]);
+        self.update_view_lights();
     }
     pub fn set_projection(&mut self, p: &Matrix4<f32>) {
         self.projection = p.clone();
@@ -151,22 +596,176 @@ impl<'a> Rasterizer<'a> {
         self.texture = Some(tex);
     }
 
+    /// 片元着色器中通过 payload.environment 使用的环境贴图
+    pub fn set_environment(&mut self, environment: Option<Arc<CubeMap>>) {
+        self.environment = environment;
+    }
+
+    /// 世界空间中的点光源 为空时着色器使用默认光源
+    pub fn set_lights(&mut self, lights: Vec<Light>) {
+        self.lights = lights;
+        self.update_view_lights();
+    }
+
+    fn update_view_lights(&mut self) {
+        let view = self.view;
+        self.view_lights = self
+            .lights
+            .iter()
+            .map(|light| {
+                let p = view * to_vector4(light.position, 1f32);
+                Light::new(p.xyz(), light.intensity)
+            })
+            .collect();
+    }
+
+    /// 内置着色器在输出前混合的雾 为 None 时没有雾
+    /// 远处的背景不会被雾覆盖 需要时把清除颜色设为雾的颜色
+    pub fn set_fog(&mut self, fog: Option<Fog>) {
+        self.fog = fog;
+    }
+
+    /// 片元着色器中通过 payload.ibl 使用的预计算环境光照
+    pub fn set_ibl(&mut self, ibl: Option<Arc<Ibl>>) {
+        self.ibl = ibl;
+    }
+
+    /// 光栅化使用的线程数 为 1 时在当前线程串行执行
+    pub fn set_threads(&mut self, threads: usize) {
+        self.threads = threads.max(1);
+    }
+
+    /// 开启后在光栅化前细分三角形 并按位移贴图移动顶点
+    /// 位移贴图取材质的 displacement 贴图 没有时使用 set_texture 的纹理
+    pub fn set_tessellation(&mut self, tessellation: Option<Tessellation>) {
+        self.tessellation = tessellation;
+    }
+
+    /// 不透明阶段使用的混合方程 None 时直接覆盖
+    pub fn set_blend(&mut self, blend: Option<BlendState>) {
+        self.blend = blend;
+    }
+
+    pub fn set_depth_write(&mut self, depth_write: bool) {
+        self.depth_write = depth_write;
+    }
+
+    /// 关闭后只更新深度和模板 用于先在模板中标记区域
+    pub fn set_color_write(&mut self, color_write: bool) {
+        self.color_write = color_write;
+    }
+
+    /// None 时关闭模板测试
+    pub fn set_stencil(&mut self, stencil: Option<StencilState>) {
+        self.stencil = stencil;
+    }
+
+    pub fn set_clear_values(&mut self, clear_values: ClearValues) {
+        self.clear_values = clear_values;
+    }
+
+    pub fn set_transparency(&mut self, transparency: Transparency) {
+        self.transparency = transparency;
+    }
+
+    /// 延迟着色 不透明的片元只写入 G-buffer 分块内的三角形都光栅化后每个像素着色一次
+    /// 半透明的三角形仍然在光照之后直接着色
+    pub fn set_deferred(&mut self, deferred: bool) {
+        self.deferred = deferred;
+        if deferred && self.gbuffer.samples().is_empty() {
+            self.gbuffer = GBuffer::new(self.width, self.height);
+            self.pending = vec![None; (self.width * self.height) as usize];
+        }
+    }
+
+    /// 开启后每个像素记录最近的不透明三角形 用 pick 查询
+    pub fn set_id_buffer(&mut self, enabled: bool) {
+        self.id_buf = match enabled {
+            true => vec![None; (self.width * self.height) as usize],
+            false => vec![],
+        };
+        self.pick_triangles.clear();
+    }
+
+    /// 开启后每个像素记录最近的不透明三角形的观察空间法线
+    pub fn set_normal_buffer(&mut self, enabled: bool) {
+        self.normal_buf = match enabled {
+            true => vec![na::zero(); (self.width * self.height) as usize],
+            false => vec![],
+        };
+    }
+
+    /// 开启后 draw_meshes 和 draw_scene 跳过包围盒被已有深度完全挡住的网格
+    pub fn set_occlusion_culling(&mut self, occlusion_culling: bool) {
+        self.occlusion_culling = occlusion_culling;
+    }
+
+    /// 之后绘制的三角形在 ID buffer 中的 mesh_id
+    pub fn set_mesh_id(&mut self, mesh_id: u32) {
+        self.mesh_id = mesh_id;
+    }
+
+    /// 多重采样抗锯齿的采样数 只支持 1 2 4 每个像素仍然只着色一次
+    /// 延迟着色的 G-buffer 每个像素只有一个样本 开启延迟着色时不起作用
+    pub fn set_msaa(&mut self, samples: usize) {
+        assert!(
+            matches!(samples, 1 | 2 | 4),
+            "unsupported msaa sample count {}",
+            samples
+        );
+        self.msaa = samples;
+    }
+
+    /// 屏幕空间环境光遮蔽 需要完整的 G-buffer 所以开启时也会开启延迟着色
+    pub fn set_ssao(&mut self, ssao: Option<Ssao>) {
+        self.ssao = ssao;
+        if ssao.is_some() {
+            self.set_deferred(true);
+        }
+    }
+
+    /// output_buffer 使用的后处理链
+    /// 链中有描边时同时开启法线缓冲
+    pub fn set_post_process(&mut self, post_process: PostProcess) {
+        if post_process.needs_geometry() && self.normal_buf.is_empty() {
+            self.set_normal_buffer(true);
+        }
+        self.post_process = post_process;
+    }
+
+    /// 屏幕空间反射 在光照之后沿反射方向对深度步进 命中时取已经着色的颜色
+    /// 反射强度由材质的 ks 决定 同样需要 G-buffer 所以会开启延迟着色
+    pub fn set_ssr(&mut self, ssr: Option<Ssr>) {
+        self.ssr = ssr;
+        if ssr.is_some() {
+            self.set_deferred(true);
+        }
+    }
+
     pub fn set_vertex_shader(
         &mut self,
-        _vertex_shader: &'a dyn Fn(&VertexShaderPayload) -> Vector3f,
+        _vertex_shader: &'a (dyn Fn(&VertexShaderPayload) -> Vector3f + Sync),
     ) {
         self.vertex_shader = Some(_vertex_shader);
     }
 
     pub fn set_fragment_shader(
         &mut self,
-        _fragment_shader: &'a dyn Fn(&FragmentShaderPayload) -> Vector3f,
+        _fragment_shader: &'a (dyn Fn(&FragmentShaderPayload) -> Vector3f + Sync),
     ) {
         self.fragment_shader = Some(_fragment_shader)
     }
 
+    /// 输出 rgba 的片元着色器 设置后优先于 set_fragment_shader
+    pub fn set_fragment_shader_rgba(
+        &mut self,
+        fragment_shader: &'a (dyn Fn(&FragmentShaderPayload) -> Vector4f + Sync),
+    ) {
+        self.fragment_shader_rgba = Some(fragment_shader)
+    }
+
     pub fn set_pixel(&mut self, point: &Vector3<i32>, index: usize, color: &Vector3<f32>) {
-        if point.x < 0 || point.x > self.width || point.y < 0 || point.y > self.height {
+        if point.x < 0 || point.x >= self.width || point.y < 0 || point.y >= self.height {
             return;
         }
 
@@ -176,34 +775,216 @@ impl<'a> Rasterizer<'a> {
             "set pixel, {:?} point: {:?}, color: {:?}",
             ind as usize, point, color
         );
-        self.frame_buf[ind as usize][index] = color.clone();
+        self.frame_buf[ind as usize][index] = to_vector4(*color, 1f32);
     }
 }
 
 impl Rasterizer<'_> {
     pub fn clear(&mut self, buff: Buffers) {
-        if (buff.clone() & Buffers::Color) == Buffers::Color {
+        let clear = self.clear_values;
+        if buff.contains(Buffers::COLOR) {
             self.frame_buf
                 .iter_mut()
-                .for_each(|f| *f = [Vector3::new(0.0, 0.0, 0.0); 4]);
+                .for_each(|f| *f = [clear.color; 4]);
+            // G-buffer 相当于延迟着色时的颜色缓冲 一起清除
+            self.gbuffer.clear();
         }
-        if (buff.clone() & Buffers::Depth) == Buffers::Depth {
-            self.depth_buf.iter_mut().for_each(|d| *d = [f32::MAX; 4]);
+        if buff.contains(Buffers::DEPTH) {
+            self.depth_buf
+                .iter_mut()
+                .for_each(|d| *d = [clear.depth; 4]);
+            self.id_buf.iter_mut().for_each(|id| *id = None);
+            self.normal_buf.iter_mut().for_each(|n| *n = na::zero());
+            self.pick_triangles.clear();
+            self.hiz.reset(clear.depth);
+            self.cull_stats = Default::default();
+        }
+        if buff.contains(Buffers::STENCIL) {
+            self.stencil_buf
+                .iter_mut()
+                .for_each(|s| *s = [clear.stencil; 4]);
         }
     }
 
+    /// 只在 set_deferred(true) 之后有内容
+    pub fn gbuffer(&self) -> &GBuffer {
+        &self.gbuffer
+    }
+
+    /// G-buffer 中 material_id 对应的材质名
+    pub fn material_name(&self, material_id: u32) -> Option<&str> {
+        match material_id {
+            0 => None,
+            id => self.material_names.get(id as usize - 1).map(|s| s.as_str()),
+        }
+    }
+
+    // 按材质名分配 id 0 留给没有材质的三角形
+    fn material_id(&mut self, material: Option<&Material>) -> u32 {
+        let name = match material {
+            None => return 0,
+            Some(m) => &m.name,
+        };
+        let index = match self.material_names.iter().position(|n| n == name) {
+            Some(index) => index,
+            None => {
+                self.material_names.push(name.clone());
+                self.material_names.len() - 1
+            }
+        };
+        index as u32 + 1
+    }
+
+    /// 按行存储 和 output_buffer 的像素一一对应 没有开启时为空
+    pub fn id_buffer(&self) -> &[Option<IdSample>] {
+        &self.id_buf
+    }
+
+    /// 每个像素所有采样点中最近的屏幕空间深度 排列和 output_buffer 相同
+    pub fn depth_buffer(&self) -> Vec<f32> {
+        let samples = self.sample_count();
+        self.depth_buf
+            .iter()
+            .map(|d| d[..samples].iter().fold(f32::MAX, |a, &b| a.min(b)))
+            .collect()
+    }
+
+    /// 观察空间的单位法线 没有被覆盖的像素为 0 没有开启时为空
+    pub fn normal_buffer(&self) -> &[Vector3f] {
+        &self.normal_buf
+    }
+
+    /// 输出图像第 y 行第 x 列的像素上最近的不透明三角形
+    pub fn pick(&self, x: i32, y: i32) -> Option<Pick> {
+        if x < 0 || x >= self.width || y < 0 || y >= self.height {
+            return None;
+        }
+        let sample = self.id_buf.get((y * self.width + x) as usize)?.as_ref()?;
+        Some(self.pick_triangles[sample.slot as usize].pick(sample))
+    }
+
+    pub fn hiz(&self) -> &HiZ {
+        &self.hiz
+    }
+
+    pub fn cull_stats(&self) -> CullStats {
+        self.cull_stats
+    }
+
+    /// 用当前的模型 观察和投影矩阵把模型空间的包围盒投影到屏幕 和层次 Z 比较
+    /// 返回 false 表示包围盒在屏幕外或者一定被已经画好的深度挡住
+    pub fn occlusion_query(&self, min: &Vector3f, max: &Vector3f) -> bool {
+        self.bounds_visible(&self.model, min, max)
+    }
+
+    fn bounds_visible(&self, model: &Matrix4<f32>, min: &Vector3f, max: &Vector3f) -> bool {
+        let (f1, f2) = Self::depth_range();
+        let mvp = self.projection * self.view * model;
+        let mut lower = Vector3f::from_element(f32::MAX);
+        let mut upper = Vector3f::from_element(f32::MIN);
+        let mut w_sign = 0f32;
+        for k in 0..8 {
+            let corner = Vector3f::new(
+                if k & 1 == 0 { min.x } else { max.x },
+                if k & 2 == 0 { min.y } else { max.y },
+                if k & 4 == 0 { min.z } else { max.z },
+            );
+            let clip = mvp * to_vector4(corner, 1f32);
+            // 包围盒跨过 w = 0 的平面时 角点的投影不再包住整个包围盒
+            if clip.w.abs() <= f32::EPSILON || clip.w * w_sign < 0f32 {
+                return true;
+            }
+            w_sign = clip.w.signum();
+            let ndc = clip / clip.w;
+            let screen = Vector3f::new(
+                0.5 * self.width as f32 * (ndc.x + 1f32),
+                0.5 * self.height as f32 * (ndc.y + 1f32),
+                ndc.z * f1 + f2,
+            );
+            lower = lower.inf(&screen);
+            upper = upper.sup(&screen);
+        }
+        match self.hiz.max_depth(
+            lower.x.floor() as i32,
+            lower.y.floor() as i32,
+            upper.x.floor() as i32 + 1,
+            upper.y.floor() as i32 + 1,
+        ) {
+            // 模板测试会在深度测试失败时改写模板 不能跳过
+            Some(max_depth) => self.stencil.is_some() || lower.z < max_depth,
+            None => false,
+        }
+    }
+
+    pub fn stencil_buffer(&self) -> Vec<u8> {
+        self.stencil_buf.iter().map(|s| s[0]).collect()
+    }
+
+    /// 每个像素连续存放所有采样点的模板值 像素的排列和 stencil_buffer 相同
+    pub fn stencil_samples(&self) -> Vec<u8> {
+        let samples = self.sample_count();
+        self.stencil_buf
+            .iter()
+            .flat_map(|s| s[..samples].iter().cloned())
+            .collect()
+    }
+
+    /// 经过后处理链的显示颜色 范围和 opencv 的 8 位图像一样是 [0, 255]
+    pub fn output_buffer(&mut self) -> Vec<f32> {
+        let hdr = self.frame_buffer();
+        let depth = match self.post_process.needs_geometry() {
+            true => self.depth_buffer(),
+            false => vec![],
+        };
+        let geometry = Geometry {
+            depth: &depth,
+            normal: &self.normal_buf,
+        };
+        let geometry = Some(&geometry).filter(|g| !g.depth.is_empty() && !g.normal.is_empty());
+        self.post_process
+            .apply_with(self.width, self.height, &hdr, geometry)
+            .into_iter()
+            .map(|c| c * 255f32)
+            .collect()
+    }
+
+    /// 着色器输出的线性 HDR 颜色 没有截断
     pub fn frame_buffer(&mut self) -> Vec<f32> {
+        let samples = self.sample_count();
         let mut ret = Vec::with_capacity(self.width as usize * self.height as usize);
         self.frame_buf
             .iter()
-            // .map(|colors| colors.iter().sum::<Vector3f>() / colors.len() as f32)
-            .map(|colors| colors.iter().sum::<Vector3f>())
-            .for_each(|color| color.data.iter().for_each(|f| ret.push(*f)));
+            .map(|colors| {
+                colors[..samples].iter().map(|c| c.xyz()).sum::<Vector3f>() / samples as f32
+            })
+            .for_each(|color| color.iter().for_each(|f| ret.push(*f)));
         ret
     }
 
+    fn sample_count(&self) -> usize {
+        if self.deferred {
+            1
+        } else {
+            self.msaa
+        }
+    }
+
+    // 采样点在像素内的位置 4 个采样时用旋转网格
+    fn sample_positions(&self) -> &'static [(f32, f32)] {
+        match self.sample_count() {
+            1 => &[(0.5, 0.5)],
+            2 => &[(0.25, 0.25), (0.75, 0.75)],
+            _ => &[
+                (0.375, 0.125),
+                (0.875, 0.375),
+                (0.125, 0.625),
+                (0.625, 0.875),
+            ],
+        }
+    }
+
     fn get_index(&self, x: i32, y: i32) -> usize {
-        (y * self.width + self.width - x) as usize
+        (y * self.width + self.width - 1 - x) as usize
     }
 
     fn get_next_id(&mut self) -> usize {
@@ -211,17 +992,6 @@ impl Rasterizer<'_> {
         self.next_id
     }
 
-    fn inside_triangle(x: f32, y: f32, v: &[Vector4<f32>; 3]) -> bool {
-        let v: Vec<_> = v.iter().map(|v4| Vector3f::new(v4.x, v4.y, 1f32)).collect();
-        let f0 = v[1].cross(&v[0]);
-        let f1 = v[2].cross(&v[1]);
-        let f2 = v[0].cross(&v[2]);
-        let p = Vector3f::new(x, y, 1f32);
-        (p.dot(&f0) * f0.dot(&v[2]) > 0f32)
-            && (p.dot(&f1) * f1.dot(&v[0]) > 0f32)
-            && (p.dot(&f2) * f2.dot(&v[1]) > 0f32)
-    }
-
     fn compute_barycentric2d(x: f32, y: f32, v: &[Vector4f; 3]) -> (f32, f32, f32) {
         let f = |a: Vector4f, b: Vector4f, c: Vector4f| {
             (x * (a.y - b.y) + y * (b.x - a.x) + a.x * b.y - b.x * a.y)
@@ -233,80 +1003,771 @@ impl Rasterizer<'_> {
         (alpha, beta, gamma)
     }
 
-    pub fn rasterize_triangle(&mut self, t: &Triangle, view_pos: &[Vector3f; 3]) {
+    // 三个顶点屏幕空间深度的最小和最大值
+    fn depth_bounds(vs: &[Vector4f; 3]) -> (f32, f32) {
+        vs.iter().fold((f32::MAX, f32::MIN), |(lo, hi), v| {
+            (lo.min(v.z), hi.max(v.z))
+        })
+    }
+
+    fn bounding_box(vs: &[Vector4f; 3]) -> ((f32, f32), (f32, f32)) {
+        vs.iter()
+            .fold(((f32::MAX, f32::MAX), (f32::MIN, f32::MIN)), |(l, u), v| {
+                ((l.0.min(v.x), l.1.min(v.y)), (u.0.max(v.x), u.1.max(v.y)))
+            })
+    }
+
+    fn new_tile(&self, x0: i32, y0: i32, x1: i32, y1: i32) -> Tile {
+        let mut tile = Tile {
+            x0,
+            y0,
+            x1,
+            y1,
+            frame_buf: Vec::with_capacity(((x1 - x0) * (y1 - y0)) as usize),
+            depth_buf: Vec::with_capacity(((x1 - x0) * (y1 - y0)) as usize),
+            stencil_buf: Vec::with_capacity(((x1 - x0) * (y1 - y0)) as usize),
+            accum: vec![na::zero(); ((x1 - x0) * (y1 - y0)) as usize],
+            revealage: vec![1f32; ((x1 - x0) * (y1 - y0)) as usize],
+            gbuffer: vec![],
+            pending: vec![],
+            id_buf: vec![],
+            normal_buf: vec![],
+            max_depth: f32::MAX,
+            depth_writes: usize::MAX,
+            culled_tiles: 0,
+            culled_fragments: 0,
+        };
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let index = self.get_index(x, y);
+                tile.frame_buf
+                    .push(self.frame_buf.get(index).cloned().unwrap_or_default());
+                tile.depth_buf
+                    .push(self.depth_buf.get(index).cloned().unwrap_or([f32::MAX; 4]));
+                tile.stencil_buf
+                    .push(self.stencil_buf.get(index).cloned().unwrap_or_default());
+                if self.deferred {
+                    tile.gbuffer
+                        .push(self.gbuffer.samples().get(index).cloned().flatten());
+                    tile.pending
+                        .push(self.pending.get(index).cloned().flatten());
+                }
+                if !self.id_buf.is_empty() {
+                    tile.id_buf.push(self.id_buf.get(index).cloned().flatten());
+                }
+                if !self.normal_buf.is_empty() {
+                    tile.normal_buf
+                        .push(self.normal_buf.get(index).cloned().unwrap_or_default());
+                }
+            }
+        }
+        tile.refresh_max_depth(self.sample_count());
+        tile
+    }
+
+    fn tile_at(&self, tile_index: i32, tiles_x: i32) -> Tile {
+        let (tx, ty) = (tile_index % tiles_x, tile_index / tiles_x);
+        self.new_tile(
+            tx * TILE_SIZE,
+            ty * TILE_SIZE,
+            self.width.min((tx + 1) * TILE_SIZE),
+            self.height.min((ty + 1) * TILE_SIZE),
+        )
+    }
+
+    fn write_back_tile(&mut self, tile: Tile) {
+        self.cull_stats.tiles += tile.culled_tiles;
+        self.cull_stats.fragments += tile.culled_fragments;
+        for y in tile.y0..tile.y1 {
+            for x in tile.x0..tile.x1 {
+                let index = self.get_index(x, y);
+                if index < self.frame_buf.len() {
+                    let local = tile.local_index(x, y);
+                    self.frame_buf[index] = tile.frame_buf[local];
+                    self.depth_buf[index] = tile.depth_buf[local];
+                    self.stencil_buf[index] = tile.stencil_buf[local];
+                    if self.deferred {
+                        self.gbuffer.samples_mut()[index] = tile.gbuffer[local];
+                        self.pending[index] = tile.pending[local];
+                    }
+                    if !self.id_buf.is_empty() {
+                        self.id_buf[index] = tile.id_buf[local];
+                    }
+                    if !self.normal_buf.is_empty() {
+                        self.normal_buf[index] = tile.normal_buf[local];
+                    }
+                }
+            }
+        }
+        let samples = self.sample_count();
+        let mut hiz = std::mem::take(&mut self.hiz);
+        hiz.update(tile.x0, tile.y0, tile.x1, tile.y1, |x, y| {
+            self.depth_buf[self.get_index(x, y)][..samples]
+                .iter()
+                .fold((f32::MAX, f32::MIN), |(lo, hi), &d| (lo.min(d), hi.max(d)))
+        });
+        self.hiz = hiz;
+    }
+
+    fn rasterize_in_tile(&self, tile: &mut Tile, triangles: &[ScreenTriangle], tri_index: usize) {
+        let st = &triangles[tri_index];
+        let t = &st.triangle;
         let vs = t.v;
 
         #[cfg(feature = "show_print")]
         println!("triangle vertex is  {:?}", vs);
-        let (lower_bound, upper_bound) = vs
-            .iter()
-            .fold(((f32::MAX, f32::MAX), (f32::MIN, f32::MIN)), |(l, u), v| {
-                ((l.0.min(v.x), l.1.min(v.y)), (u.0.max(v.x), u.1.max(v.y)))
-            });
+        let edges = match EdgeFunctions::new(&vs) {
+            Some(edges) => edges,
+            None => return,
+        };
+        let (lower_bound, upper_bound) = Self::bounding_box(&vs);
 
-        for i in 0i32.max(lower_bound.0 as i32)..self.height.min(upper_bound.0 as i32 + 1) {
-            for j in 0i32.max(lower_bound.1 as i32)..self.width.min(upper_bound.1 as i32 + 1) {
-                // // super sampling
-                // for (sub_index, (dx, dy)) in
-                //     [(0.25, 0.25), (0.25, 0.75), (0.75, 0.25), (0.75, 0.75)]
-                //         .iter()
-                //         .enumerate()
-                // {
-                let sub_index = 0;
-                let (dx, dy) = (0.5, 0.5);
-                let (x, y) = (i as f32 + dx, j as f32 + dy);
-                if Self::inside_triangle(x, y, &vs) {
+        let (x_begin, x_end) = (
+            tile.x0.max(lower_bound.0.floor() as i32),
+            tile.x1
+                .min(self.width)
+                .min(upper_bound.0.floor() as i32 + 1),
+        );
+        let (y_begin, y_end) = (
+            tile.y0.max(lower_bound.1.floor() as i32),
+            tile.y1
+                .min(self.height)
+                .min(upper_bound.1.floor() as i32 + 1),
+        );
+        let z_range = Self::depth_bounds(&vs);
+        if x_begin >= x_end || y_begin >= y_end {
+            return;
+        }
+        if self.stencil.is_none() {
+            tile.refresh_max_depth(self.sample_count());
+            if z_range.0 >= tile.max_depth {
+                tile.culled_tiles += 1;
+                return;
+            }
+        }
+        let positions = self.sample_positions();
+        let mut row = [[0i64; 3]; 4];
+        for j in y_begin..y_end {
+            // 每个采样点在行首的边函数值 之后逐像素增量更新
+            for (e, (dx, dy)) in row.iter_mut().zip(positions) {
+                *e = edges.evaluate(
+                    x_begin as i64 * SUBPIXEL_ONE + to_fixed(*dx),
+                    j as i64 * SUBPIXEL_ONE + to_fixed(*dy),
+                );
+            }
+            for i in x_begin..x_end {
+                // 每个像素只着色一次 结果写入所有通过测试的采样点
+                let mut shaded: Option<(Vector4f, f32)> = None;
+                for (sub_index, e) in row[..positions.len()].iter_mut().enumerate() {
+                    let covered = edges.covers(e);
+                    let (alpha, beta, gamma) = edges.barycentric(e);
+                    edges.step_x(e);
+                    if !covered {
+                        continue;
+                    }
                     #[cfg(feature = "show_print")]
                     println!("inside pos is {}, {}", i, j);
-                    let (alpha, beta, gamma) = Self::compute_barycentric2d(x, y, &t.v);
                     let z = 1.0 / (alpha / vs[0].w + beta / vs[1].w + gamma / vs[2].w);
                     let mut zp = alpha * vs[0].z / vs[0].w
                         + beta * vs[1].z / vs[1].w
                         + gamma * vs[2].z / vs[2].w;
-                    zp *= z;
-
-                    let index = self.get_index(i, j);
-                    if self.depth_buf[index][sub_index] > zp {
-                        self.depth_buf[index][sub_index] = zp;
-
-                        let interpolated_color = interpolate(alpha, beta, gamma, &t.color, 1f32);
-                        let interpolated_normal = interpolate(alpha, beta, gamma, &t.normal, 1f32);
-                        let interpolated_texcoords =
-                            interpolate(alpha, beta, gamma, &t.tex_coords, 1f32);
-
-                        // 空间中的位置 而不是投影位置
-                        let interpolated_shadingcoords =
-                            interpolate(alpha, beta, gamma, &view_pos, 1f32);
-
-                        let mut payload = super::shader::FragmentShaderPayload::new(
-                            interpolated_color,
-                            interpolated_normal.normalize(),
-                            interpolated_texcoords,
-                            self.texture.as_ref(),
-                        );
-                        payload.view_pos = interpolated_shadingcoords;
-                        let color = self.fragment_shader.unwrap()(&payload);
-                        self.set_pixel(&Vector3::new(i, j, 1), sub_index, &color);
+                    // 插值结果在顶点深度之间 截断舍入误差 层次 Z 的剔除才和逐像素测试一致
+                    zp = (zp * z).max(z_range.0).min(z_range.1);
+
+                    let index = tile.local_index(i, j);
+                    if let Some(stencil) = &self.stencil {
+                        let value = tile.stencil_buf[index][sub_index];
+                        let op = if !stencil.test(value) {
+                            stencil.fail
+                        } else if tile.depth_buf[index][sub_index] > zp {
+                            stencil.pass
+                        } else {
+                            stencil.depth_fail
+                        };
+                        tile.stencil_buf[index][sub_index] = stencil.update(op, value);
+                        if !stencil.test(value) {
+                            continue;
+                        }
+                    }
+                    if tile.depth_buf[index][sub_index] <= zp {
+                        tile.culled_fragments += 1;
+                        continue;
+                    }
+                    if st.pass == Pass::Opaque && self.depth_write {
+                        tile.depth_buf[index][sub_index] = zp;
+                        tile.depth_writes += 1;
+                        if !tile.normal_buf.is_empty() {
+                            let normal = interpolate(alpha, beta, gamma, &t.normal, 1f32);
+                            tile.normal_buf[index] =
+                                normal.try_normalize(f32::EPSILON).unwrap_or_else(na::zero);
+                        }
+                        if !tile.id_buf.is_empty() {
+                            // 本次绘制的三角形保存在 pick_triangles 的末尾
+                            let slot = self.pick_triangles.len() - triangles.len() + tri_index;
+                            tile.id_buf[index] = Some(IdSample {
+                                mesh_id: st.mesh_id,
+                                instance_id: st.instance_id,
+                                triangle_id: st.triangle_id,
+                                barycentric: Vector3f::new(alpha, beta, gamma),
+                                slot: slot as u32,
+                            });
+                        }
+                    }
+                    if !self.color_write {
+                        continue;
                     }
-                    // }
+
+                    let (color, view_depth) = match shaded {
+                        Some(shaded) => {
+                            // 加权混合的累积量按像素存储 只写一次
+                            if st.pass == Pass::WeightedBlended {
+                                continue;
+                            }
+                            shaded
+                        }
+                        None => {
+                            let sample = self.fragment_inputs(st, i, j, (alpha, beta, gamma), zp);
+                            if st.pass == Pass::Opaque && self.deferred {
+                                // 只记录着色输入 被后面的三角形覆盖时不会浪费着色
+                                tile.gbuffer[index] = Some(sample);
+                                tile.pending[index] = Some(tri_index);
+                                continue;
+                            }
+                            let color = self.shade(&sample, st);
+                            *shaded.insert((color, sample.view_pos.z))
+                        }
+                    };
+                    Self::write_color(
+                        tile,
+                        index,
+                        sub_index,
+                        st.pass,
+                        &self.blend,
+                        color,
+                        view_depth,
+                    );
                 }
             }
         }
     }
-}
 
-// helpers
-impl Rasterizer<'_> {}
+    // 插值出片元着色需要的所有输入
+    fn fragment_inputs(
+        &self,
+        st: &ScreenTriangle,
+        i: i32,
+        j: i32,
+        (alpha, beta, gamma): (f32, f32, f32),
+        depth: f32,
+    ) -> GSample {
+        let t = &st.triangle;
+        let color = interpolate(alpha, beta, gamma, &t.color, 1f32);
+        let normal = interpolate(alpha, beta, gamma, &t.normal, 1f32).normalize();
+        let tex_coords = interpolate(alpha, beta, gamma, &t.tex_coords, 1f32);
+        // 空间中的位置 而不是投影位置
+        let view_pos = interpolate(alpha, beta, gamma, &st.view_pos, 1f32);
+        let (tangent, bitangent) =
+            Self::tangent_frame(&normal, &interpolate(alpha, beta, gamma, &t.tangent, 1f32));
+        let (dx, dy) = Self::quad_tex_coords_derivatives(i, j, t);
+
+        let alpha_map = st.material.and_then(|m| m.texture(TextureUnit::Alpha));
+        let dissolve = st.material.map_or(1f32, |m| m.d);
+        let albedo = match self.diffuse_texture(st) {
+            Some(texture) => texture.sample(tex_coords, dx, dy) / 255f32,
+            None => color,
+        };
+        GSample {
+            albedo,
+            normal,
+            view_pos,
+            depth,
+            material_id: st.material_id,
+            specular: st.material.map_or(na::zero(), |m| m.ks),
+            ambient_occlusion: 1f32,
+            color,
+            tangent,
+            bitangent,
+            tex_coords,
+            tex_coords_dx: dx,
+            tex_coords_dy: dy,
+            alpha: dissolve
+                * alpha_map.map_or(1f32, |map| map.sample(tex_coords, dx, dy).x / 255f32),
+        }
+    }
+
+    // 材质上绑定了漫反射贴图时优先使用
+    fn diffuse_texture<'t>(&'t self, st: &ScreenTriangle<'t>) -> Option<&'t Texture> {
+        st.material
+            .and_then(|m| m.texture(TextureUnit::Diffuse))
+            .or(self.texture.as_ref())
+    }
+
+    fn shade(&self, sample: &GSample, st: &ScreenTriangle) -> Vector4f {
+        let mut payload = FragmentShaderPayload::new(
+            sample.color,
+            sample.normal,
+            sample.tex_coords,
+            self.diffuse_texture(st),
+        );
+        payload.view_pos = sample.view_pos;
+        payload.material = st.material;
+        payload.tangent = sample.tangent;
+        payload.bitangent = sample.bitangent;
+        payload.tex_coords_dx = sample.tex_coords_dx;
+        payload.tex_coords_dy = sample.tex_coords_dy;
+        payload.alpha = sample.alpha;
+        payload.ambient_occlusion = sample.ambient_occlusion;
+        payload.environment = self.environment.as_deref();
+        payload.ibl = self.ibl.as_deref();
+        payload.lights = &self.view_lights;
+        payload.view_to_world = self.view_to_world;
+        payload.eye_pos = self.eye_pos;
+        payload.fog = self.fog.as_ref();
+        match self.fragment_shader_rgba {
+            Some(shader) => shader(&payload),
+            None => to_vector4(self.fragment_shader.unwrap()(&payload), payload.alpha),
+        }
+    }
+
+    fn write_color(
+        tile: &mut Tile,
+        index: usize,
+        sub_index: usize,
+        pass: Pass,
+        blend: &Option<BlendState>,
+        color: Vector4f,
+        view_depth: f32,
+    ) {
+        let dst = tile.frame_buf[index][sub_index];
+        match pass {
+            Pass::Opaque => {
+                tile.frame_buf[index][sub_index] = match blend {
+                    None => color,
+                    Some(blend) => blend.blend(&color, &dst),
+                }
+            }
+            Pass::Blended => {
+                tile.frame_buf[index][sub_index] = BlendState::alpha_blending().blend(&color, &dst)
+            }
+            Pass::WeightedBlended => {
+                let w = oit_weight(color.w, view_depth);
+                let premultiplied = color.xyz() * color.w;
+                tile.accum[index] +=
+                    Vector4::new(premultiplied.x, premultiplied.y, premultiplied.z, color.w) * w;
+                tile.revealage[index] *= 1f32 - color.w;
+            }
+        }
+    }
+
+    // 延迟着色的光照阶段 每个本次绘制覆盖的像素只着色一次
+    fn resolve_deferred(&self, tile: &mut Tile, triangles: &[ScreenTriangle]) {
+        for index in 0..tile.pending.len() {
+            if let (Some(tri_index), Some(sample)) = (tile.pending[index], tile.gbuffer[index]) {
+                let color = self.shade(&sample, &triangles[tri_index]);
+                Self::write_color(
+                    tile,
+                    index,
+                    0,
+                    Pass::Opaque,
+                    &self.blend,
+                    color,
+                    sample.view_pos.z,
+                );
+            }
+        }
+    }
+
+    // Gram-Schmidt 正交化 副切线由法线和切线叉乘得到
+    fn tangent_frame(normal: &Vector3f, tangent: &Vector4f) -> (Vector3f, Vector3f) {
+        let t = tangent.xyz() - normal * normal.dot(&tangent.xyz());
+        if t.magnitude() < f32::EPSILON {
+            return (na::zero(), na::zero());
+        }
+        let t = t.normalize();
+        let sign = if tangent.w < 0f32 { -1f32 } else { 1f32 };
+        (t, normal.cross(&t) * sign)
+    }
+
+    // 和 GPU 一样以 2x2 像素块为单位求 uv 导数
+    // 块内不在三角形中的像素也按重心坐标外插
+    fn quad_tex_coords_derivatives(i: i32, j: i32, t: &Triangle) -> (Vector2f, Vector2f) {
+        let (qx, qy) = ((i & !1) as f32 + 0.5, (j & !1) as f32 + 0.5);
+        let uv_at = |x: f32, y: f32| {
+            let (alpha, beta, gamma) = Self::compute_barycentric2d(x, y, &t.v);
+            interpolate(alpha, beta, gamma, &t.tex_coords, 1f32)
+        };
+        let origin = uv_at(qx, qy);
+        (uv_at(qx + 1f32, qy) - origin, uv_at(qx, qy + 1f32) - origin)
+    }
+
+    pub fn rasterize_triangle(&mut self, t: &Triangle, view_pos: &[Vector3f; 3]) {
+        let st = ScreenTriangle {
+            triangle: t.clone(),
+            view_pos: *view_pos,
+            material: None,
+            material_id: 0,
+            pass: Pass::Opaque,
+            mesh_id: self.mesh_id,
+            instance_id: 0,
+            triangle_id: 0,
+        };
+        self.rasterize_tiled(&[st]);
+    }
+
+    // 把三角形按包围盒分到各个分块 每个分块内保持提交顺序
+    // 分块之间互不重叠 所以并行结果和串行完全一致
+    fn rasterize_tiled(&mut self, triangles: &[ScreenTriangle]) {
+        if !self.id_buf.is_empty() {
+            self.save_pick_triangles(triangles);
+        }
+        let tiles_x = (self.width + TILE_SIZE - 1) / TILE_SIZE;
+        let tiles_y = (self.height + TILE_SIZE - 1) / TILE_SIZE;
+        let mut bins: Vec<Vec<usize>> = vec![vec![]; (tiles_x * tiles_y) as usize];
+        for (index, st) in triangles.iter().enumerate() {
+            let (lower_bound, upper_bound) = Self::bounding_box(&st.triangle.v);
+            // 包围盒内已有的深度都比三角形近 整个三角形都不会通过深度测试
+            if self.stencil.is_none() {
+                let max_depth = self.hiz.max_depth(
+                    lower_bound.0.floor() as i32,
+                    lower_bound.1.floor() as i32,
+                    upper_bound.0.floor() as i32 + 1,
+                    upper_bound.1.floor() as i32 + 1,
+                );
+                if max_depth.map_or(false, |d| Self::depth_bounds(&st.triangle.v).0 >= d) {
+                    self.cull_stats.triangles += 1;
+                    continue;
+                }
+            }
+            let tx0 = 0i32.max(lower_bound.0 as i32) / TILE_SIZE;
+            let ty0 = 0i32.max(lower_bound.1 as i32) / TILE_SIZE;
+            let tx1 = (tiles_x - 1).min(upper_bound.0 as i32 / TILE_SIZE);
+            let ty1 = (tiles_y - 1).min(upper_bound.1 as i32 / TILE_SIZE);
+            for ty in ty0..=ty1 {
+                for tx in tx0..=tx1 {
+                    bins[(ty * tiles_x + tx) as usize].push(index);
+                }
+            }
+        }
+
+        let jobs: Vec<_> = bins
+            .iter()
+            .enumerate()
+            .filter(|(_, bin)| !bin.is_empty())
+            .map(|(bin_index, _)| bin_index as i32)
+            .collect();
+        // 不透明的三角形总是排在前面
+        let split = |tile_index: i32| {
+            let bin = &bins[tile_index as usize];
+            let opaque = bin
+                .iter()
+                .position(|&index| triangles[index].pass != Pass::Opaque)
+                .unwrap_or(bin.len());
+            bin.split_at(opaque)
+        };
+
+        if !self.deferred {
+            let done = self.run_tiles(&jobs, |tile_index| {
+                let mut tile = self.tile_at(tile_index, tiles_x);
+                for &index in &bins[tile_index as usize] {
+                    self.rasterize_in_tile(&mut tile, triangles, index);
+                }
+                tile.resolve_oit(self.msaa);
+                tile
+            });
+            done.into_iter().for_each(|tile| self.write_back_tile(tile));
+            return;
+        }
+
+        // 延迟着色 先把所有不透明的三角形写入 G-buffer
+        // 整个屏幕的 G-buffer 完成后才能做屏幕空间的效果 光照和反射之后再画半透明的三角形
+        let done = self.run_tiles(&jobs, |tile_index| {
+            let mut tile = self.tile_at(tile_index, tiles_x);
+            for &index in split(tile_index).0 {
+                self.rasterize_in_tile(&mut tile, triangles, index);
+            }
+            tile
+        });
+        done.into_iter().for_each(|tile| self.write_back_tile(tile));
+
+        if let Some(config) = &self.ssao {
+            let ao = ssao::ambient_occlusion(&self.gbuffer, &self.projection, config);
+            for (sample, ao) in self.gbuffer.samples_mut().iter_mut().zip(ao) {
+                if let Some(sample) = sample {
+                    sample.ambient_occlusion = ao;
+                }
+            }
+        }
+
+        let done = self.run_tiles(&jobs, |tile_index| {
+            let mut tile = self.tile_at(tile_index, tiles_x);
+            self.resolve_deferred(&mut tile, triangles);
+            tile
+        });
+        done.into_iter().for_each(|tile| self.write_back_tile(tile));
+
+        if let Some(config) = self.ssr {
+            self.apply_ssr(&config);
+        }
+        self.pending.iter_mut().for_each(|p| *p = None);
+
+        let jobs: Vec<_> = jobs
+            .into_iter()
+            .filter(|&tile_index| !split(tile_index).1.is_empty())
+            .collect();
+        let done = self.run_tiles(&jobs, |tile_index| {
+            let mut tile = self.tile_at(tile_index, tiles_x);
+            for &index in split(tile_index).1 {
+                self.rasterize_in_tile(&mut tile, triangles, index);
+            }
+            tile.resolve_oit(1);
+            tile
+        });
+        done.into_iter().for_each(|tile| self.write_back_tile(tile));
+    }
+
+    // 拾取时需要世界空间的顶点 观察矩阵在两次绘制之间可能改变 所以在这里变换
+    fn save_pick_triangles(&mut self, triangles: &[ScreenTriangle]) {
+        let view_inv = self.view.try_inverse().unwrap_or_else(Matrix4::identity);
+        for st in triangles {
+            let mut pt = PickTriangle {
+                position: Default::default(),
+                normal: Default::default(),
+                w: Default::default(),
+            };
+            for i in 0..3 {
+                let view_pos = to_vector4(st.view_pos[i], 1f32);
+                pt.position[i] = (view_inv * view_pos).xyz();
+                pt.w[i] = (self.projection * view_pos).w;
+                pt.normal[i] = self.view_to_world * st.triangle.normal[i];
+            }
+            self.pick_triangles.push(pt);
+        }
+    }
+
+    // 只给本次绘制的像素加上反射 反射的颜色全部取自加反射之前的 frame buffer
+    // 着色器已经混合了雾 反射乘上这个像素的透过率 相当于在雾之前加上反射
+    fn apply_ssr(&mut self, config: &Ssr) {
+        let pending = &self.pending;
+        let hits = ssr::trace(
+            &self.gbuffer,
+            &self.projection,
+            |index| pending[index].is_some(),
+            config,
+        );
+        let mut reflections = vec![];
+        for (index, hit) in hits.into_iter().enumerate() {
+            let (sample, (x, y)) = match (&self.gbuffer.samples()[index], hit) {
+                (Some(sample), Some(hit)) => (sample, hit),
+                _ => continue,
+            };
+            if sample.specular == Vector3f::zeros() {
+                continue;
+            }
+            // Schlick 近似 ks 作为垂直入射时的反射率
+            let cos = (-sample.view_pos.normalize()).dot(&sample.normal).max(0f32);
+            let fresnel = sample.specular
+                + (Vector3f::from_element(1f32) - sample.specular) * (1f32 - cos).powi(5);
+            let transmittance = match &self.fog {
+                Some(fog) => {
+                    let point = self.view_to_world * sample.view_pos + self.eye_pos;
+                    fog.transmittance(&self.eye_pos, &point)
+                }
+                None => 1f32,
+            };
+            if let Some(color) = self.frame_buf.get(self.get_index(x, y)) {
+                reflections.push((
+                    index,
+                    color[0].xyz().component_mul(&fresnel) * config.intensity * transmittance,
+                ));
+            }
+        }
+        for (index, reflection) in reflections {
+            let color = &mut self.frame_buf[index][0];
+            *color += to_vector4(reflection, 0f32);
+        }
+    }
+
+    // 按 jobs 中的分块下标执行 shade threads 大于 1 时多线程执行
+    fn run_tiles<F>(&self, jobs: &[i32], shade: F) -> Vec<Tile>
+    where
+        F: Fn(i32) -> Tile + Sync,
+    {
+        if self.threads <= 1 {
+            jobs.iter().map(|&tile_index| shade(tile_index)).collect()
+        } else {
+            let next = AtomicUsize::new(0);
+            let done = Mutex::new(Vec::with_capacity(jobs.len()));
+            thread::scope(|s| {
+                for _ in 0..self.threads.min(jobs.len()) {
+                    s.spawn(|| loop {
+                        let job = next.fetch_add(1, Ordering::Relaxed);
+                        if job >= jobs.len() {
+                            break;
+                        }
+                        let tile = shade(jobs[job]);
+                        done.lock().unwrap().push(tile);
+                    });
+                }
+            });
+            done.into_inner().unwrap()
+        }
+    }
+}
 
 // draw functions
 impl Rasterizer<'_> {
     pub fn draw_triangles(&mut self, triangle_list: &Vec<&Triangle>) {
-        let f1 = (50f32 - 0.1) / 2f32;
-        let f2 = (50f32 + 0.1) / 2f32;
+        let mut triangles = Vec::with_capacity(triangle_list.len());
+        self.transform_triangles(
+            triangle_list.iter().copied(),
+            &self.vertex_transform(&self.model),
+            None,
+            0,
+            &mut triangles,
+        );
+        self.rasterize_tiled(&triangles);
+    }
+
+    pub fn draw_mesh(&mut self, mesh: &Mesh) {
+        self.draw_meshes(std::slice::from_ref(mesh));
+    }
+
+    /// 按各网格自己的材质绘制 所有网格的三角形一起分块光栅化
+    pub fn draw_meshes(&mut self, meshes: &[Mesh]) {
+        let mut triangles = vec![];
+        let (model, transform) = (self.model, self.vertex_transform(&self.model));
+        for (index, mesh) in meshes.iter().enumerate() {
+            if !self.mesh_visible(mesh, &model) {
+                continue;
+            }
+            let material_id = self.material_id(mesh.material.as_deref());
+            let transform = VertexTransform {
+                mesh_id: self.mesh_id + index as u32,
+                ..transform
+            };
+            self.transform_triangles(
+                mesh.triangles.iter(),
+                &transform,
+                mesh.material.as_deref(),
+                material_id,
+                &mut triangles,
+            );
+        }
+        self.rasterize_sorted(triangles);
+    }
 
-        let mvp = self.projection * self.view * self.model;
+    /// 按场景图绘制 每个节点的模型矩阵为 set_model 设置的矩阵乘以节点的世界变换
+    pub fn draw_scene(&mut self, scene: &Scene) {
+        let mut triangles = vec![];
+        for (id, (node, world)) in scene
+            .nodes()
+            .iter()
+            .zip(scene.world_transforms())
+            .enumerate()
+        {
+            let mesh = match &node.mesh {
+                Some(mesh) => mesh,
+                None => continue,
+            };
+            if !self.mesh_visible(mesh, &(self.model * world)) {
+                continue;
+            }
+            let material = node.material();
+            let material_id = self.material_id(material);
+            let transform = VertexTransform {
+                mesh_id: id as u32,
+                ..self.vertex_transform(&(self.model * world))
+            };
+            self.transform_triangles(
+                mesh.triangles.iter(),
+                &transform,
+                material,
+                material_id,
+                &mut triangles,
+            );
+        }
+        self.rasterize_sorted(triangles);
+    }
+
+    // 开启遮挡剔除时用包围盒做遮挡查询 被剔除的网格计入统计
+    fn mesh_visible(&mut self, mesh: &Mesh, model: &Matrix4<f32>) -> bool {
+        if !self.occlusion_culling {
+            return true;
+        }
+        let visible = match mesh.bounds() {
+            Some((min, max)) => self.bounds_visible(model, &min, &max),
+            None => false,
+        };
+        if !visible {
+            self.cull_stats.meshes += 1;
+        }
+        visible
+    }
 
-        let mv = self.view * self.model;
+    fn rasterize_sorted(&mut self, mut triangles: Vec<ScreenTriangle>) {
+        // 不透明的先画 半透明的按远近排序后再画
+        triangles.sort_by(|a, b| {
+            let depth = |st: &ScreenTriangle| match st.pass {
+                Pass::Blended => st.view_pos.iter().map(|p| p.z).sum::<f32>(),
+                _ => f32::MIN,
+            };
+            (a.pass != Pass::Opaque)
+                .cmp(&(b.pass != Pass::Opaque))
+                .then(
+                    depth(a)
+                        .partial_cmp(&depth(b))
+                        .unwrap_or(std::cmp::Ordering::Equal),
+                )
+        });
+        self.rasterize_tiled(&triangles);
+    }
+
+    /// 深度还是清除值的像素用天空盒填充 在所有网格画完之后调用
+    pub fn draw_skybox(&mut self, skybox: &CubeMap) {
+        let inv_projection = match self.projection.try_inverse() {
+            Some(m) => m,
+            None => return,
+        };
+        let clear_depth = self.clear_values.depth;
+        let samples = self.sample_count();
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let index = self.get_index(x, y);
+                if index >= self.frame_buf.len()
+                    || self.depth_buf[index][..samples]
+                        .iter()
+                        .all(|&d| d != clear_depth)
+                {
+                    continue;
+                }
+                let ndc = Vector4::new(
+                    2f32 * (x as f32 + 0.5) / self.width as f32 - 1f32,
+                    2f32 * (y as f32 + 0.5) / self.height as f32 - 1f32,
+                    0f32,
+                    1f32,
+                );
+                let p = inv_projection * ndc;
+                let dir = if p.w.abs() > f32::EPSILON {
+                    p.xyz() / p.w
+                } else {
+                    p.xyz()
+                };
+                // 相机看向 -z
+                let dir = if dir.z > 0f32 { -dir } else { dir };
+                let color = to_vector4(skybox.sample(&(self.view_to_world * dir)) / 255f32, 1f32);
+                for sub_index in 0..samples {
+                    if self.depth_buf[index][sub_index] == clear_depth {
+                        self.frame_buf[index][sub_index] = color;
+                    }
+                }
+            }
+        }
+    }
+
+    fn vertex_transform(&self, model: &Matrix4<f32>) -> VertexTransform {
+        let mvp = self.projection * self.view * model;
+
+        let mv = self.view * model;
 
         #[cfg(feature = "show_print")]
         {
@@ -314,116 +1775,349 @@ impl Rasterizer<'_> {
             println!("mv is {:?}", mv);
         }
 
-        let inv_trans_vm = mv.try_inverse().expect("inverse fail").transpose();
-        for t in triangle_list {
-            let mut viewspace_pos: [Vector3f; 3] = Default::default();
-            viewspace_pos.copy_from_slice(
-                &t.v.iter()
-                    .map(|vertex| mv * vertex)
-                    .map(|v4| Vector3::from_column_slice(&v4.as_slice()[..3]))
-                    .collect::<Vec<_>>()
-                    .as_slice(),
-            );
-            let n: Vec<_> = t
-                .normal
-                .iter()
-                .map(|&nor| inv_trans_vm * to_vector4(nor, 0f32))
-                .map(|v4| Vector3::from_column_slice(&v4.as_slice()[..3]))
-                .collect();
+        // 法线用 mv 的逆转置变换 节点带非均匀缩放时也正确
+        // 缩放为 0 的实例或节点不可逆 它的三角形都退化了 法线用什么都不影响结果
+        let inv_trans_vm = mv
+            .try_inverse()
+            .unwrap_or_else(Matrix4::identity)
+            .transpose();
+        VertexTransform {
+            mvp,
+            mv,
+            inv_trans_vm,
+            mesh_id: self.mesh_id,
+            instance_id: 0,
+            tint: Vector3f::from_element(1f32),
+            vertex_colors: false,
+        }
+    }
 
-            // vertex to screen
-            let v: Vec<_> =
-                t.v.iter()
-                    .map(|vertex| mvp * vertex)
-                    .map(|vector| vector / vector.w)
-                    .map(|vertor| {
-                        Vector4::new(
-                            0.5 * self.width as f32 * (vertor.x + 1f32),
-                            0.5 * self.height as f32 * (vertor.y + 1f32),
-                            vertor.z * f1 + f2,
-                            vertor.w,
-                        )
-                    })
-                    .collect();
-            let mut triangle: Triangle = (*t).clone();
-            for i in 0..3 {
-                triangle.set_vertex(i, v[i]);
-                triangle.set_normal(i, n[i]);
-                triangle
-                    .set_color(i, 148f32, 121f32, 92f32)
-                    .expect("set color err");
+    fn pass(&self, material: Option<&Material>) -> Pass {
+        match material {
+            Some(m) if m.is_transparent() => match self.transparency {
+                Transparency::Sorted => Pass::Blended,
+                Transparency::WeightedBlended => Pass::WeightedBlended,
+            },
+            _ => Pass::Opaque,
+        }
+    }
+
+    fn transform_triangles<'t, 'm>(
+        &self,
+        triangle_list: impl IntoIterator<Item = &'t Triangle>,
+        transform: &VertexTransform,
+        material: Option<&'m Material>,
+        material_id: u32,
+        triangles: &mut Vec<ScreenTriangle<'m>>,
+    ) {
+        let displacement_map = material
+            .and_then(|m| m.texture(TextureUnit::Displacement))
+            .or(self.texture.as_ref());
+        let pass = self.pass(material);
+        for (triangle_id, t) in triangle_list.into_iter().enumerate() {
+            let triangle_id = triangle_id as u32;
+            match (&self.tessellation, displacement_map) {
+                (Some(config), map) => {
+                    let patch = tessellation::tessellate(
+                        t,
+                        &transform.mvp,
+                        self.width,
+                        self.height,
+                        config,
+                    );
+                    for mut sub in patch {
+                        if let Some(map) = map {
+                            tessellation::displace(&mut sub, map, config.displacement_scale);
+                        }
+                        triangles.extend(self.transform_triangle(
+                            &sub,
+                            transform,
+                            triangle_id,
+                            material,
+                            material_id,
+                            pass,
+                        ));
+                    }
+                }
+                (None, _) => triangles.extend(self.transform_triangle(
+                    t,
+                    transform,
+                    triangle_id,
+                    material,
+                    material_id,
+                    pass,
+                )),
             }
+        }
+    }
 
-            self.rasterize_triangle(&triangle, &viewspace_pos);
+    // 三个顶点都在同一个裁剪面外侧
+    fn outside_frustum(ndc: &[Vector4f]) -> bool {
+        (0..2).any(|k| ndc.iter().all(|v| v[k] < -1f32) || ndc.iter().all(|v| v[k] > 1f32))
+    }
+
+    // 顶点着色器作用在模型空间的位置上 之后变换到 NDC 和观察空间
+    fn transform_vertex(
+        &self,
+        position: &Vector3f,
+        normal: &Vector3f,
+        tangent: &Vector4f,
+        tex_coords: Vector2f,
+        color: Vector3f,
+        transform: &VertexTransform,
+    ) -> ClipVertex {
+        let position = match self.vertex_shader {
+            Some(shader) => shader(&VertexShaderPayload {
+                position: *position,
+                instance_id: transform.instance_id,
+            }),
+            None => *position,
+        };
+        let position = to_vector4(position, 1f32);
+        let clip = transform.mvp * position;
+        let normal = transform.inv_trans_vm * to_vector4(*normal, 0f32);
+        // 切线随表面一起变换 用 mv 而不是逆转置
+        let t = transform.mv * Vector4::new(tangent.x, tangent.y, tangent.z, 0f32);
+        ClipVertex {
+            ndc: clip / clip.w,
+            view_pos: (transform.mv * position).xyz(),
+            normal: normal.xyz(),
+            tangent: Vector4::new(t.x, t.y, t.z, tangent.w),
+            tex_coords,
+            color: color.component_mul(&transform.tint),
         }
     }
+
+    fn transform_triangle<'m>(
+        &self,
+        t: &Triangle,
+        transform: &VertexTransform,
+        triangle_id: u32,
+        material: Option<&'m Material>,
+        material_id: u32,
+        pass: Pass,
+    ) -> Option<ScreenTriangle<'m>> {
+        let color = |i: usize| match transform.vertex_colors {
+            true => t.color[i],
+            false => Vector3f::new(148f32, 121f32, 92f32) / 255f32,
+        };
+        let vertex = |i: usize| {
+            self.transform_vertex(
+                &t.v[i].xyz(),
+                &t.normal[i],
+                &t.tangent[i],
+                t.tex_coords[i],
+                color(i),
+                transform,
+            )
+        };
+        self.assemble(
+            [vertex(0), vertex(1), vertex(2)],
+            transform,
+            triangle_id,
+            material,
+            material_id,
+            pass,
+        )
+    }
+
+    // NDC 的 z 映射到屏幕空间深度的缩放和偏移
+    fn depth_range() -> (f32, f32) {
+        ((50f32 - 0.1) / 2f32, (50f32 + 0.1) / 2f32)
+    }
+
+    // 图元装配 视锥外的三角形直接剔除 其余变换到屏幕空间
+    fn assemble<'m>(
+        &self,
+        vertices: [ClipVertex; 3],
+        transform: &VertexTransform,
+        triangle_id: u32,
+        material: Option<&'m Material>,
+        material_id: u32,
+        pass: Pass,
+    ) -> Option<ScreenTriangle<'m>> {
+        let (f1, f2) = Self::depth_range();
+
+        let ndc: Vec<_> = vertices.iter().map(|v| v.ndc).collect();
+        if Self::outside_frustum(&ndc) {
+            return None;
+        }
+
+        let mut triangle = Triangle::new();
+        let mut view_pos: [Vector3f; 3] = Default::default();
+        for (i, vertex) in vertices.iter().enumerate() {
+            // vertex to screen
+            triangle.set_vertex(
+                i,
+                Vector4::new(
+                    0.5 * self.width as f32 * (vertex.ndc.x + 1f32),
+                    0.5 * self.height as f32 * (vertex.ndc.y + 1f32),
+                    vertex.ndc.z * f1 + f2,
+                    vertex.ndc.w,
+                ),
+            );
+            triangle.set_normal(i, vertex.normal);
+            triangle.set_tangent(i, vertex.tangent);
+            triangle.set_tex_coord(i, vertex.tex_coords);
+            triangle.color[i] = vertex.color;
+            view_pos[i] = vertex.view_pos;
+        }
+
+        Some(ScreenTriangle {
+            triangle,
+            view_pos,
+            material,
+            material_id,
+            pass,
+            mesh_id: transform.mesh_id,
+            instance_id: transform.instance_id as u32,
+            triangle_id,
+        })
+    }
 }
 
-// out of date
+// vertex and index buffers
 impl Rasterizer<'_> {
-    pub fn draw(
+    /// 按三角形列表的下标绘制 每个被引用的顶点在一次绘制中只变换一次
+    /// 开启曲面细分时按三角形展开后走 Mesh 的绘制路径
+    pub fn draw_indexed(
         &mut self,
-        pos_buf_id: PosBufID,
+        vert_buf_id: VertBufID,
         ind_buf_id: IndBufID,
-        col_buf_id: ColBufID,
         r#type: Primitive,
-    ) {
-        assert_eq!(r#type, Primitive::Triangle);
-
-        let buf = &self.pos_buf[&pos_buf_id.pos_id];
-        let ind = &self.ind_buf[&ind_buf_id.ind_id];
-        let color = &self.color_buf[&col_buf_id.col_id];
+        material: Option<&Material>,
+    ) -> VertexCacheStats {
+        self.draw_indexed_instances(
+            vert_buf_id,
+            ind_buf_id,
+            &[Instance::default()],
+            r#type,
+            material,
+        )
+    }
 
-        let f1 = (100f32 - 0.1) / 2f32;
-        let f2 = (100f32 + 0.1) / 2f32;
+    /// 一次绘制实例缓冲中的每个实例 实例的模型矩阵在 set_model 之前作用
+    /// 顶点缓存按实例分开 统计为所有实例之和
+    pub fn draw_instanced(
+        &mut self,
+        vert_buf_id: VertBufID,
+        ind_buf_id: IndBufID,
+        inst_buf_id: InstBufID,
+        r#type: Primitive,
+        material: Option<&Material>,
+    ) -> VertexCacheStats {
+        let instances = self.inst_buf[&inst_buf_id.inst_id].clone();
+        self.draw_indexed_instances(vert_buf_id, ind_buf_id, &instances, r#type, material)
+    }
 
-        let mvp = self.projection * self.view * self.model;
+    fn draw_indexed_instances(
+        &mut self,
+        vert_buf_id: VertBufID,
+        ind_buf_id: IndBufID,
+        instances: &[Instance],
+        r#type: Primitive,
+        material: Option<&Material>,
+    ) -> VertexCacheStats {
+        // 不透明的三角形攒够这么多就先光栅化 实例很多时不用一次保存所有三角形
+        const BATCH: usize = 1 << 16;
 
-        #[cfg(feature = "show_print")]
-        {
-            println!(
-                "mvp is :\n\nmodel: {:?}\n\nview: {:?}\n\nprojection: {:?}",
-                self.model, self.view, self.projection
+        assert_eq!(r#type, Primitive::Triangle);
+        let material_id = self.material_id(material);
+        let buffer = self.vert_buf[&vert_buf_id.vert_id].clone();
+        let indices = self.ind_buf[&ind_buf_id.ind_id].clone();
+        assert_eq!(indices.len() % 3, 0, "index buffer is not a triangle list");
+        if let Some(&i) = indices.iter().find(|&&i| i as usize >= buffer.len()) {
+            panic!(
+                "index {} out of range, vertex buffer has {}",
+                i,
+                buffer.len()
             );
-            println!("mvp~~~ is : {:?}", mvp);
-        }
-        let mut ts = vec![];
-        for i in ind {
-            let v: [Vector4<f32>; 3] = [
-                mvp * buf[i[0]].to_homogeneous(),
-                mvp * buf[i[1]].to_homogeneous(),
-                mvp * buf[i[2]].to_homogeneous(),
-            ];
-
-            #[cfg(feature = "show_print")]
-            println!("original v is {:?}", v);
-            let v: Vec<Vector4<f32>> = v
-                .iter()
-                .map(|vec| vec / vec.w)
-                .map(|vert| {
-                    Vector4::from_row_slice(&[
-                        0.5 * self.width as f32 * (vert.x + 1.0),
-                        0.5 * self.height as f32 * (vert.y + 1.0),
-                        vert.z * f1 + f2,
-                        1.0,
-                    ])
+        }
+
+        let pass = self.pass(material);
+        let mut stats = VertexCacheStats::default();
+        let mut triangles = Vec::with_capacity((indices.len() / 3).min(BATCH));
+        // 曲面细分需要完整的三角形 和实例无关 只展开一次
+        let list: Vec<_> = match self.tessellation {
+            Some(_) => indices
+                .chunks(3)
+                .map(|face| {
+                    let mut t = Triangle::new();
+                    for (j, &i) in face.iter().enumerate() {
+                        let i = i as usize;
+                        t.set_vertex(j, to_vector4(buffer.position(i), 1f32));
+                        t.set_normal(j, buffer.normal(i));
+                        t.set_tex_coord(j, buffer.tex_coords(i));
+                        t.set_tangent(j, buffer.tangent(i));
+                        t.color[j] = buffer.color(i);
+                    }
+                    t
                 })
-                .collect();
-            let mut t = super::triangle::Triangle::new();
-            (0..3).for_each(|index| {
-                t.set_vertex(
-                    index,
-                    to_vector4(Vector3::from_column_slice(&v[index].as_slice()[..3]), 1f32),
+                .collect(),
+            None => vec![],
+        };
+        // post-transform vertex cache 大小和顶点数相同 所以不会有被挤出的顶点
+        let mut cache: Vec<Option<ClipVertex>> = vec![];
+        for (instance_id, instance) in instances.iter().enumerate() {
+            let transform = VertexTransform {
+                instance_id,
+                tint: instance.tint,
+                vertex_colors: true,
+                ..self.vertex_transform(&(self.model * instance.model))
+            };
+            if self.tessellation.is_some() {
+                // 不经过缓存 每个下标各变换一次
+                stats.transformed += indices.len();
+                self.transform_triangles(
+                    list.iter(),
+                    &transform,
+                    material,
+                    material_id,
+                    &mut triangles,
                 );
-                let col = color[i[index]];
-                t.set_color(index, col.x, col.y, col.z)
-                    .expect("set wrong color");
-            });
-            ts.push(t);
+            } else {
+                cache.clear();
+                cache.resize(buffer.len(), None);
+                for (triangle_id, face) in indices.chunks(3).enumerate() {
+                    let mut vertices = [ClipVertex::default(); 3];
+                    for (vertex, &i) in vertices.iter_mut().zip(face) {
+                        let i = i as usize;
+                        *vertex = match cache[i] {
+                            Some(v) => {
+                                stats.hits += 1;
+                                v
+                            }
+                            None => {
+                                stats.transformed += 1;
+                                let v = self.transform_vertex(
+                                    &buffer.position(i),
+                                    &buffer.normal(i),
+                                    &buffer.tangent(i),
+                                    buffer.tex_coords(i),
+                                    buffer.color(i),
+                                    &transform,
+                                );
+                                cache[i] = Some(v);
+                                v
+                            }
+                        };
+                    }
+                    triangles.extend(self.assemble(
+                        vertices,
+                        &transform,
+                        triangle_id as u32,
+                        material,
+                        material_id,
+                        pass,
+                    ));
+                }
+            }
+            if pass == Pass::Opaque && triangles.len() >= BATCH {
+                self.rasterize_sorted(std::mem::take(&mut triangles));
+            }
         }
-        let view_pos = Default::default();
-        ts.iter()
-            .for_each(|t| self.rasterize_triangle(&t, &view_pos));
+        self.rasterize_sorted(triangles);
+        stats
     }
 
     // fn draw_line(&mut self, begin: Vector3<f32>, end: Vector3<f32>) {
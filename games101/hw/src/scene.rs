@@ -0,0 +1,135 @@
+use super::material::Material;
+use super::mesh::Mesh;
+use nalgebra::Matrix4;
+use std::sync::Arc;
+
+/// 节点在 Scene 中的下标
+pub type NodeId = usize;
+
+/// 场景图中的节点 transform 为相对父节点的变换
+#[derive(Clone)]
+pub struct Node {
+    pub name: String,
+    pub mesh: Option<Arc<Mesh>>,
+    /// 覆盖网格自带的材质 为 None 时使用网格的材质
+    pub material: Option<Arc<Material>>,
+    pub transform: Matrix4<f32>,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+}
+
+impl Node {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            mesh: None,
+            material: None,
+            transform: Matrix4::identity(),
+            parent: None,
+            children: vec![],
+        }
+    }
+
+    pub fn with_mesh(name: &str, mesh: Arc<Mesh>) -> Self {
+        Self {
+            mesh: Some(mesh),
+            ..Self::new(name)
+        }
+    }
+
+    pub fn parent(&self) -> Option<NodeId> {
+        self.parent
+    }
+
+    pub fn children(&self) -> &[NodeId] {
+        &self.children
+    }
+
+    /// 实际使用的材质
+    pub fn material(&self) -> Option<&Material> {
+        self.material
+            .as_deref()
+            .or_else(|| self.mesh.as_ref().and_then(|m| m.material.as_deref()))
+    }
+}
+
+/// 节点按加入的顺序存储 父节点总在子节点前面
+#[derive(Clone, Default)]
+pub struct Scene {
+    nodes: Vec<Node>,
+}
+
+impl Scene {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// 每个网格一个节点 都挂在同一个根节点下
+    pub fn from_meshes(meshes: Vec<Mesh>) -> Self {
+        let mut scene = Self::new();
+        let root = scene.add(Node::new("root"), None);
+        for mesh in meshes {
+            let name = mesh.name.clone();
+            scene.add(Node::with_mesh(&name, Arc::new(mesh)), Some(root));
+        }
+        scene
+    }
+
+    pub fn add(&mut self, mut node: Node, parent: Option<NodeId>) -> NodeId {
+        let id = self.nodes.len();
+        if let Some(parent) = parent {
+            assert!(parent < id, "parent node {} does not exist", parent);
+            self.nodes[parent].children.push(id);
+        }
+        node.parent = parent;
+        node.children.clear();
+        self.nodes.push(node);
+        id
+    }
+
+    pub fn node(&self, id: NodeId) -> &Node {
+        &self.nodes[id]
+    }
+
+    pub fn node_mut(&mut self, id: NodeId) -> &mut Node {
+        &mut self.nodes[id]
+    }
+
+    pub fn nodes(&self) -> &[Node] {
+        &self.nodes
+    }
+
+    pub fn find(&self, name: &str) -> Option<NodeId> {
+        self.nodes.iter().position(|n| n.name == name)
+    }
+
+    pub fn roots(&self) -> impl Iterator<Item = NodeId> + '_ {
+        (0..self.nodes.len()).filter(move |&id| self.nodes[id].parent.is_none())
+    }
+
+    pub fn set_transform(&mut self, id: NodeId, transform: Matrix4<f32>) {
+        self.nodes[id].transform = transform;
+    }
+
+    /// 节点到世界空间的变换 即从根节点开始所有 transform 的乘积
+    pub fn world_transform(&self, id: NodeId) -> Matrix4<f32> {
+        let node = &self.nodes[id];
+        match node.parent {
+            Some(parent) => self.world_transform(parent) * node.transform,
+            None => node.transform,
+        }
+    }
+
+    /// 所有节点的世界变换 下标和 nodes 一致
+    pub fn world_transforms(&self) -> Vec<Matrix4<f32>> {
+        let mut ret: Vec<Matrix4<f32>> = Vec::with_capacity(self.nodes.len());
+        for node in &self.nodes {
+            let world = match node.parent {
+                Some(parent) => ret[parent] * node.transform,
+                None => node.transform,
+            };
+            ret.push(world);
+        }
+        ret
+    }
+}
@@ -1,5 +1,10 @@
+use super::cube_map::CubeMap;
+use super::fog::Fog;
+use super::ibl::Ibl;
+use super::material::Material;
 use super::texture::Texture;
-use nalgebra::{Vector2, Vector3};
+use super::Light;
+use nalgebra::{Matrix3, Vector2, Vector3};
 
 type Vector2f = Vector2<f32>;
 type Vector3f = Vector3<f32>;
@@ -8,8 +13,31 @@ pub struct FragmentShaderPayload<'a> {
     pub view_pos: Vector3f,
     pub color: Vector3f,
     pub normal: Vector3f,
+    /// 观察空间下的切线和副切线 已经和法线正交化
+    pub tangent: Vector3f,
+    pub bitangent: Vector3f,
     pub tex_coords: Vector2f,
+    /// 纹理坐标在屏幕 x y 方向上的导数 按 2x2 像素块计算
+    pub tex_coords_dx: Vector2f,
+    pub tex_coords_dy: Vector2f,
+    /// 材质的 d 乘以 map_d 的采样值
+    pub alpha: f32,
+    /// 环境光可见度 开启 SSAO 时由 G-buffer 算出 否则为 1
+    pub ambient_occlusion: f32,
     pub texture: Option<&'a Texture>,
+    pub material: Option<&'a Material>,
+    /// 环境贴图 在世界空间中采样
+    pub environment: Option<&'a CubeMap>,
+    /// 由环境贴图预计算的 IBL 数据
+    pub ibl: Option<&'a Ibl>,
+    /// 把观察空间的方向变换到世界空间
+    pub view_to_world: Matrix3<f32>,
+    /// 相机在世界空间的位置 view_to_world * view_pos + eye_pos 为世界空间的位置
+    pub eye_pos: Vector3f,
+    /// 场景的雾 内置着色器通过 apply_fog 混合
+    pub fog: Option<&'a Fog>,
+    /// 观察空间中的光源 为空时着色器使用默认光源
+    pub lights: &'a [Light],
 }
 
 impl<'a> FragmentShaderPayload<'a> {
@@ -24,6 +52,9 @@ impl<'a> FragmentShaderPayload<'a> {
             normal,
             tex_coords,
             texture,
+            alpha: 1f32,
+            ambient_occlusion: 1f32,
+            view_to_world: Matrix3::identity(),
             ..Default::default()
         }
     }
@@ -32,4 +63,6 @@ impl<'a> FragmentShaderPayload<'a> {
 #[derive(Default)]
 pub struct VertexShaderPayload {
     pub position: Vector3f,
+    /// 实例化绘制时实例的下标 其他绘制为 0
+    pub instance_id: usize,
 }
@@ -0,0 +1,178 @@
+use super::gbuffer::GBuffer;
+use nalgebra::{Matrix4, Vector3, Vector4};
+
+type Vector3f = Vector3<f32>;
+
+// 旋转噪声平铺的边长 (像素) 模糊半径取它的一半正好能抹掉噪声的图案
+const NOISE_SIZE: i32 = 4;
+
+/// 屏幕空间环境光遮蔽的参数 距离都在观察空间中度量
+#[derive(Debug, Clone, Copy)]
+pub struct Ssao {
+    /// 半球采样核的半径
+    pub radius: f32,
+    /// 每个像素的采样数
+    pub samples: u32,
+    /// 遮蔽的强度 0 时没有效果
+    pub strength: f32,
+    /// 避免平面自遮挡的深度偏移
+    pub bias: f32,
+    /// 双边模糊的半径 (像素) 0 时不模糊
+    pub blur_radius: i32,
+}
+
+impl Default for Ssao {
+    fn default() -> Self {
+        Self {
+            radius: 0.5,
+            samples: 16,
+            strength: 1f32,
+            bias: 0.025,
+            blur_radius: NOISE_SIZE / 2,
+        }
+    }
+}
+
+// 整数哈希到 [0, 1) 保证每次渲染的结果相同
+fn random(n: u32) -> f32 {
+    let mut h = n.wrapping_mul(0x9e37_79b9) ^ 0x85eb_ca6b;
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x2c1b_3c6d);
+    h ^= h >> 12;
+    h = h.wrapping_mul(0x297a_2d39);
+    h ^= h >> 15;
+    (h >> 8) as f32 / (1u32 << 24) as f32
+}
+
+// 切线空间 +z 半球内的采样点 越靠后的采样离中心越远
+fn kernel(samples: u32) -> Vec<Vector3f> {
+    (0..samples)
+        .map(|i| {
+            let v = Vector3f::new(
+                random(3 * i) * 2f32 - 1f32,
+                random(3 * i + 1) * 2f32 - 1f32,
+                random(3 * i + 2),
+            );
+            let v = if v.magnitude() > f32::EPSILON {
+                v.normalize()
+            } else {
+                Vector3f::z()
+            };
+            let t = i as f32 / samples as f32;
+            v * random(i + 0x1000) * (0.1 + 0.9 * t * t)
+        })
+        .collect()
+}
+
+// 绕法线旋转采样核的随机向量 按 NOISE_SIZE 平铺
+fn noise(x: i32, y: i32) -> Vector3f {
+    let n = (y.rem_euclid(NOISE_SIZE) * NOISE_SIZE + x.rem_euclid(NOISE_SIZE)) as u32;
+    Vector3f::new(
+        random(n + 0x2000) * 2f32 - 1f32,
+        random(n + 0x3000) * 2f32 - 1f32,
+        0f32,
+    )
+}
+
+/// 根据 G-buffer 的观察空间位置和法线计算每个像素的环境光可见度
+/// 返回值和 G-buffer 像素一一对应 1 为完全不遮挡
+pub fn ambient_occlusion(gbuffer: &GBuffer, projection: &Matrix4<f32>, config: &Ssao) -> Vec<f32> {
+    let (width, height) = (gbuffer.width, gbuffer.height);
+    let samples = gbuffer.samples();
+    let kernel = kernel(config.samples.max(1));
+    let project = |p: &Vector3f| {
+        let clip = projection * Vector4::new(p.x, p.y, p.z, 1f32);
+        let (x, y) = (clip.x / clip.w, clip.y / clip.w);
+        (
+            (0.5 * width as f32 * (x + 1f32)).floor() as i32,
+            (0.5 * height as f32 * (y + 1f32)).floor() as i32,
+        )
+    };
+
+    let mut ao = vec![1f32; samples.len()];
+    for y in 0..height {
+        for x in 0..width {
+            let index = match gbuffer.index(x, y) {
+                Some(index) => index,
+                None => continue,
+            };
+            let s = match &samples[index] {
+                Some(s) => s,
+                None => continue,
+            };
+            let normal = s.normal;
+            let r = noise(x, y);
+            let tangent = r - normal * normal.dot(&r);
+            if tangent.magnitude() < f32::EPSILON {
+                continue;
+            }
+            let tangent = tangent.normalize();
+            let bitangent = normal.cross(&tangent);
+
+            let mut occlusion = 0f32;
+            for k in &kernel {
+                let q =
+                    s.view_pos + (tangent * k.x + bitangent * k.y + normal * k.z) * config.radius;
+                let (qx, qy) = project(&q);
+                let scene = match gbuffer.index(qx, qy).and_then(|i| samples[i].as_ref()) {
+                    Some(scene) => scene,
+                    None => continue,
+                };
+                // 观察空间中相机朝向 -z z 越大离相机越近
+                if scene.view_pos.z >= q.z + config.bias {
+                    let range = (config.radius / (s.view_pos.z - scene.view_pos.z).abs()).min(1f32);
+                    occlusion += range * range * (3f32 - 2f32 * range);
+                }
+            }
+            let visibility = 1f32 - config.strength * occlusion / kernel.len() as f32;
+            ao[index] = visibility.max(0f32).min(1f32);
+        }
+    }
+
+    if config.blur_radius > 0 {
+        blur(gbuffer, &ao, config.blur_radius)
+    } else {
+        ao
+    }
+}
+
+// 双边模糊 深度或法线差别大的邻居权重很小 边缘不会被抹开
+fn blur(gbuffer: &GBuffer, ao: &[f32], radius: i32) -> Vec<f32> {
+    let samples = gbuffer.samples();
+    let mut ret = ao.to_vec();
+    for y in 0..gbuffer.height {
+        for x in 0..gbuffer.width {
+            let index = match gbuffer.index(x, y) {
+                Some(index) => index,
+                None => continue,
+            };
+            let center = match &samples[index] {
+                Some(s) => s,
+                None => continue,
+            };
+            let (mut sum, mut weight_sum) = (0f32, 0f32);
+            for dy in -radius..=radius {
+                for dx in -radius..=radius {
+                    let neighbour = match gbuffer.index(x + dx, y + dy) {
+                        Some(i) => i,
+                        None => continue,
+                    };
+                    let s = match &samples[neighbour] {
+                        Some(s) => s,
+                        None => continue,
+                    };
+                    let spatial = (-((dx * dx + dy * dy) as f32) / (radius * radius) as f32).exp();
+                    let depth = (-(s.view_pos.z - center.view_pos.z).abs() * 10f32).exp();
+                    let normal = center.normal.dot(&s.normal).max(0f32).powi(8);
+                    let w = spatial * depth * normal;
+                    sum += ao[neighbour] * w;
+                    weight_sum += w;
+                }
+            }
+            if weight_sum > f32::EPSILON {
+                ret[index] = sum / weight_sum;
+            }
+        }
+    }
+    ret
+}
@@ -0,0 +1,172 @@
+use super::gbuffer::GBuffer;
+use nalgebra::{Matrix4, Vector3, Vector4};
+
+type Vector3f = Vector3<f32>;
+
+/// 屏幕空间反射的参数 距离都在观察空间中度量
+#[derive(Debug, Clone, Copy)]
+pub struct Ssr {
+    /// 最细一层的步长
+    pub step: f32,
+    pub max_steps: u32,
+    /// 光线穿到表面后面超过这个厚度时不算命中
+    pub thickness: f32,
+    /// hierarchical-Z 的层数 0 时逐步线性步进
+    pub hi_z_levels: u32,
+    /// 反射颜色的整体系数
+    pub intensity: f32,
+}
+
+impl Default for Ssr {
+    fn default() -> Self {
+        Self {
+            step: 0.02,
+            max_steps: 512,
+            thickness: 0.5,
+            hi_z_levels: 4,
+            intensity: 1f32,
+        }
+    }
+}
+
+// 观察空间深度的最小值金字塔 第 k 层的一个像素对应原图 2^k x 2^k 的区域
+struct DepthPyramid {
+    levels: Vec<(i32, i32, Vec<f32>)>,
+}
+
+impl DepthPyramid {
+    fn new(gbuffer: &GBuffer, levels: u32) -> Self {
+        let (width, height) = (gbuffer.width, gbuffer.height);
+        let mut base = vec![f32::MAX; (width * height) as usize];
+        for y in 0..height {
+            for x in 0..width {
+                if let Some(s) = gbuffer
+                    .index(x, y)
+                    .and_then(|i| gbuffer.samples()[i].as_ref())
+                {
+                    base[(y * width + x) as usize] = -s.view_pos.z;
+                }
+            }
+        }
+        let mut ret = Self {
+            levels: vec![(width, height, base)],
+        };
+        for _ in 0..levels {
+            let (w, h, prev) = ret.levels.last().unwrap();
+            let (nw, nh) = ((w + 1) / 2, (h + 1) / 2);
+            let mut data = Vec::with_capacity((nw * nh) as usize);
+            for y in 0..nh {
+                for x in 0..nw {
+                    let at = |x: i32, y: i32| {
+                        if x < *w && y < *h {
+                            prev[(y * w + x) as usize]
+                        } else {
+                            f32::MAX
+                        }
+                    };
+                    data.push(
+                        at(2 * x, 2 * y)
+                            .min(at(2 * x + 1, 2 * y))
+                            .min(at(2 * x, 2 * y + 1))
+                            .min(at(2 * x + 1, 2 * y + 1)),
+                    );
+                }
+            }
+            ret.levels.push((nw, nh, data));
+        }
+        ret
+    }
+
+    fn depth(&self, x: i32, y: i32, level: usize) -> f32 {
+        let (w, h, data) = &self.levels[level];
+        let (x, y) = (x >> level, y >> level);
+        if x < 0 || y < 0 || x >= *w || y >= *h {
+            f32::MAX
+        } else {
+            data[(y * w + x) as usize]
+        }
+    }
+}
+
+/// 对 G-buffer 中 pixels 为 true 的像素沿反射方向步进 命中时返回命中点的屏幕坐标
+/// 在 hi-z 的粗层上穿过深度时退回一步 在第 0 层重新走完这一步 第 0 层穿过深度才算命中
+pub fn trace(
+    gbuffer: &GBuffer,
+    projection: &Matrix4<f32>,
+    pixels: impl Fn(usize) -> bool,
+    config: &Ssr,
+) -> Vec<Option<(i32, i32)>> {
+    let (width, height) = (gbuffer.width, gbuffer.height);
+    let pyramid = DepthPyramid::new(gbuffer, config.hi_z_levels);
+    let max_level = config.hi_z_levels as i32;
+    let project = |p: &Vector3f| {
+        let clip = projection * Vector4::new(p.x, p.y, p.z, 1f32);
+        let (x, y) = (clip.x / clip.w, clip.y / clip.w);
+        (
+            (0.5 * width as f32 * (x + 1f32)).floor() as i32,
+            (0.5 * height as f32 * (y + 1f32)).floor() as i32,
+        )
+    };
+    let step_at = |level: i32| config.step * (1 << level.max(0)) as f32;
+
+    let mut ret = vec![None; gbuffer.samples().len()];
+    for y in 0..height {
+        for x in 0..width {
+            let index = match gbuffer.index(x, y) {
+                Some(index) if pixels(index) => index,
+                _ => continue,
+            };
+            let s = match &gbuffer.samples()[index] {
+                Some(s) => s,
+                None => continue,
+            };
+            let view_dir = s.view_pos.normalize();
+            let dir = view_dir - 2f32 * view_dir.dot(&s.normal) * s.normal;
+            // 起点沿法线偏移一点 避免打到自己
+            let origin = s.view_pos + s.normal * config.step;
+
+            let mut level = max_level;
+            let mut distance = 0f32;
+            let mut last = project(&origin);
+            // 细化时还要在第 0 层走的步数 走完之前不换更大的步长
+            let mut refine = 0;
+            for _ in 0..config.max_steps {
+                let next = distance + step_at(level);
+                let p = origin + dir * next;
+                // 走到相机后面
+                if p.z >= 0f32 {
+                    break;
+                }
+                let (px, py) = project(&p);
+                if px < 0 || py < 0 || px >= width || py >= height {
+                    break;
+                }
+                // 一步在屏幕上跨过的距离超过这一层的一个像素时会跳过中间的薄物体 换细一层
+                if level > 0 && (px - last.0).abs().max((py - last.1).abs()) > 1 << level {
+                    level -= 1;
+                    continue;
+                }
+                if pyramid.depth(px, py, level as usize) > -p.z {
+                    // 还在所有表面前面 可以换更大的步长
+                    distance = next;
+                    last = (px, py);
+                    if refine > 0 {
+                        refine -= 1;
+                    } else {
+                        level = (level + 1).min(max_level);
+                    }
+                } else if level > 0 {
+                    // 退回这一步 在第 0 层把它重新走一遍
+                    refine = 1 << level;
+                    level = 0;
+                } else {
+                    if -p.z - pyramid.depth(px, py, 0) < config.thickness {
+                        ret[index] = Some((px, py));
+                    }
+                    break;
+                }
+            }
+        }
+    }
+    ret
+}
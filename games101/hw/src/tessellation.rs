@@ -0,0 +1,179 @@
+use super::texture::Texture;
+use super::triangle::Triangle;
+use nalgebra::{Matrix4, Vector2, Vector3, Vector4};
+
+type Vector2f = Vector2<f32>;
+type Vector3f = Vector3<f32>;
+type Vector4f = Vector4<f32>;
+
+/// 光栅化前的曲面细分和位移设置
+#[derive(Debug, Clone, Copy)]
+pub struct Tessellation {
+    /// 细分后屏幕空间的目标边长 (像素)
+    pub target_edge_length: f32,
+    /// 每条边最多二分的次数
+    pub max_depth: u32,
+    /// 位移贴图亮度为 1 时顶点沿法线移动的距离 (模型空间)
+    pub displacement_scale: f32,
+}
+
+impl Default for Tessellation {
+    fn default() -> Self {
+        Self {
+            target_edge_length: 4f32,
+            max_depth: 6,
+            displacement_scale: 0.05,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct TessVertex {
+    position: Vector4f,
+    normal: Vector3f,
+    color: Vector3f,
+    tex_coords: Vector2f,
+    tangent: Vector4f,
+    depth: u32,
+}
+
+impl TessVertex {
+    fn from_triangle(t: &Triangle, i: usize) -> Self {
+        Self {
+            position: t.v[i],
+            normal: t.normal[i],
+            color: t.color[i],
+            tex_coords: t.tex_coords[i],
+            tangent: t.tangent[i],
+            depth: 0,
+        }
+    }
+
+    // (a + b) / 2 与参数顺序无关 共享边两侧得到完全相同的中点
+    fn midpoint(a: &Self, b: &Self) -> Self {
+        Self {
+            position: (a.position + b.position) / 2f32,
+            normal: ((a.normal + b.normal) / 2f32).normalize(),
+            color: (a.color + b.color) / 2f32,
+            tex_coords: (a.tex_coords + b.tex_coords) / 2f32,
+            tangent: (a.tangent + b.tangent) / 2f32,
+            depth: a.depth.max(b.depth) + 1,
+        }
+    }
+}
+
+fn to_triangle(vs: [&TessVertex; 3]) -> Triangle {
+    let mut t = Triangle::new();
+    for (i, v) in vs.iter().enumerate() {
+        t.v[i] = v.position;
+        t.normal[i] = v.normal;
+        t.color[i] = v.color;
+        t.tex_coords[i] = v.tex_coords;
+        t.tangent[i] = v.tangent;
+    }
+    t
+}
+
+struct Tessellator<'a> {
+    mvp: &'a Matrix4<f32>,
+    width: f32,
+    height: f32,
+    config: &'a Tessellation,
+    out: Vec<Triangle>,
+}
+
+impl Tessellator<'_> {
+    fn to_screen(&self, p: &Vector4f) -> Option<Vector2f> {
+        let clip = self.mvp * p;
+        if clip.w.abs() <= f32::EPSILON {
+            return None;
+        }
+        Some(Vector2f::new(
+            0.5 * self.width * (clip.x / clip.w + 1f32),
+            0.5 * self.height * (clip.y / clip.w + 1f32),
+        ))
+    }
+
+    // 是否二分只取决于边的两个端点 相邻三角形对共享边的判断一致 不会产生裂缝
+    fn should_split(&self, a: &TessVertex, b: &TessVertex) -> bool {
+        if a.depth.max(b.depth) >= self.config.max_depth {
+            return false;
+        }
+        match (self.to_screen(&a.position), self.to_screen(&b.position)) {
+            (Some(pa), Some(pb)) => (pa - pb).magnitude() > self.config.target_edge_length,
+            // 端点在相机平面上时无法投影 不细分
+            _ => false,
+        }
+    }
+
+    fn subdivide(&mut self, v: [TessVertex; 3]) {
+        let split = [
+            self.should_split(&v[0], &v[1]),
+            self.should_split(&v[1], &v[2]),
+            self.should_split(&v[2], &v[0]),
+        ];
+        let m = |i: usize| TessVertex::midpoint(&v[i], &v[(i + 1) % 3]);
+        match split {
+            [false, false, false] => self.out.push(to_triangle([&v[0], &v[1], &v[2]])),
+            [true, true, true] => {
+                let (m0, m1, m2) = (m(0), m(1), m(2));
+                self.subdivide([v[0], m0, m2]);
+                self.subdivide([m0, v[1], m1]);
+                self.subdivide([m2, m1, v[2]]);
+                self.subdivide([m0, m1, m2]);
+            }
+            _ => {
+                // 把需要二分的边转到第一条 剩下的情况按顶点顺序统一处理
+                let first = (0..3).find(|&i| split[i]).unwrap();
+                let first = if first == 0 && split[2] { 2 } else { first };
+                let r = |i: usize| v[(first + i) % 3];
+                let (a, b, c) = (r(0), r(1), r(2));
+                let mab = TessVertex::midpoint(&a, &b);
+                if split[(first + 1) % 3] {
+                    // ab 和 bc 两条边
+                    let mbc = TessVertex::midpoint(&b, &c);
+                    self.subdivide([a, mab, c]);
+                    self.subdivide([mab, b, mbc]);
+                    self.subdivide([mab, mbc, c]);
+                } else {
+                    // 只有 ab 一条边
+                    self.subdivide([a, mab, c]);
+                    self.subdivide([mab, b, c]);
+                }
+            }
+        }
+    }
+}
+
+/// 把模型空间的三角形细分到屏幕上的边长不超过目标值
+pub fn tessellate(
+    t: &Triangle,
+    mvp: &Matrix4<f32>,
+    width: i32,
+    height: i32,
+    config: &Tessellation,
+) -> Vec<Triangle> {
+    let mut tessellator = Tessellator {
+        mvp,
+        width: width as f32,
+        height: height as f32,
+        config,
+        out: vec![],
+    };
+    tessellator.subdivide([
+        TessVertex::from_triangle(t, 0),
+        TessVertex::from_triangle(t, 1),
+        TessVertex::from_triangle(t, 2),
+    ]);
+    tessellator.out
+}
+
+/// 按位移贴图的亮度把顶点沿法线方向移动
+pub fn displace(t: &mut Triangle, map: &Texture, scale: f32) {
+    for i in 0..3 {
+        let color = map.sample(t.tex_coords[i], nalgebra::zero(), nalgebra::zero());
+        let h = color.dot(&Vector3f::new(0.2126, 0.7152, 0.0722)) / 255f32;
+        let offset = t.normal[i].normalize() * h * scale;
+        t.v[i] += Vector4f::new(offset.x, offset.y, offset.z, 0f32);
+    }
+}
@@ -1,10 +1,163 @@
-use nalgebra::Vector3;
+use nalgebra::{Vector2, Vector3};
 use opencv::{core, imgcodecs, imgproc, prelude::*};
 
+type Vector2f = Vector2<f32>;
+type Vector3f = Vector3<f32>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterMode {
+    Nearest,
+    Bilinear,
+    /// 相邻两层 mipmap 的双线性结果再插值
+    Trilinear,
+    /// 沿屏幕足迹的长轴做多次三线性采样
+    Anisotropic,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WrapMode {
+    Repeat,
+    ClampToEdge,
+    MirroredRepeat,
+    /// 超出范围时返回 Sampler 的 border_color
+    ClampToBorder,
+}
+
+impl WrapMode {
+    // 把纹素坐标映射回 [0, size) 超出且为 border 模式时返回 None
+    fn apply(&self, x: i32, size: i32) -> Option<i32> {
+        match self {
+            WrapMode::Repeat => Some(x.rem_euclid(size)),
+            WrapMode::ClampToEdge => Some(x.max(0).min(size - 1)),
+            WrapMode::MirroredRepeat => {
+                let m = x.rem_euclid(2 * size);
+                Some(if m >= size { 2 * size - 1 - m } else { m })
+            }
+            WrapMode::ClampToBorder => {
+                if x >= 0 && x < size {
+                    Some(x)
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
+
+/// 采样器 描述纹理坐标的环绕方式和过滤方式
+#[derive(Debug, Clone, Copy)]
+pub struct Sampler {
+    pub wrap_u: WrapMode,
+    pub wrap_v: WrapMode,
+    pub border_color: Vector3f,
+    pub filter: FilterMode,
+    pub max_anisotropy: u32,
+}
+
+impl Default for Sampler {
+    fn default() -> Self {
+        Self {
+            wrap_u: WrapMode::Repeat,
+            wrap_v: WrapMode::Repeat,
+            border_color: nalgebra::zero(),
+            filter: FilterMode::Bilinear,
+            max_anisotropy: 8,
+        }
+    }
+}
+
+impl Sampler {
+    pub fn new(wrap: WrapMode, filter: FilterMode) -> Self {
+        Self {
+            wrap_u: wrap,
+            wrap_v: wrap,
+            filter,
+            ..Default::default()
+        }
+    }
+}
+
+// 纹素坐标转成整数前先截断 超大或无穷的 uv 加 1 之后也不会溢出
+// 超过 2^24 之后 f32 已经无法区分相邻的纹素
+const MAX_TEXEL_COORD: f32 = (1 << 24) as f32;
+
+fn texel_coord(x: f32) -> i32 {
+    x.max(-MAX_TEXEL_COORD).min(MAX_TEXEL_COORD) as i32
+}
+
+// mipmap 中的一层 按行存储
+struct MipLevel {
+    width: i32,
+    height: i32,
+    data: Vec<Vector3f>,
+}
+
+impl MipLevel {
+    fn texel(&self, x: i32, y: i32, sampler: &Sampler) -> Vector3f {
+        if self.data.is_empty() {
+            return sampler.border_color;
+        }
+        match (
+            sampler.wrap_u.apply(x, self.width),
+            sampler.wrap_v.apply(y, self.height),
+        ) {
+            (Some(x), Some(y)) => self.data[(y * self.width + x) as usize],
+            _ => sampler.border_color,
+        }
+    }
+
+    // 2x2 盒式滤波 奇数尺寸时向上取整 最后一列或一行和重复的边缘纹素平均
+    fn downsample(&self) -> Self {
+        let clamp = Sampler::new(WrapMode::ClampToEdge, FilterMode::Nearest);
+        let (width, height) = ((self.width + 1) / 2, (self.height + 1) / 2);
+        let mut data = Vec::with_capacity((width * height) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                let (sx, sy) = (x * 2, y * 2);
+                let sum = self.texel(sx, sy, &clamp)
+                    + self.texel(sx + 1, sy, &clamp)
+                    + self.texel(sx, sy + 1, &clamp)
+                    + self.texel(sx + 1, sy + 1, &clamp);
+                data.push(sum / 4f32);
+            }
+        }
+        Self {
+            width,
+            height,
+            data,
+        }
+    }
+
+    fn nearest(&self, u: f32, v: f32, sampler: &Sampler) -> Vector3f {
+        let x = texel_coord((u * self.width as f32).floor());
+        let y = texel_coord(((1f32 - v) * self.height as f32).floor());
+        self.texel(x, y, sampler)
+    }
+
+    // 以纹素中心为采样点的双线性插值
+    fn bilinear(&self, u: f32, v: f32, sampler: &Sampler) -> Vector3f {
+        let x = u * self.width as f32 - 0.5;
+        let y = (1f32 - v) * self.height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (s, t) = (x - x0, y - y0);
+        let (x0, y0) = (texel_coord(x0), texel_coord(y0));
+
+        let c0 = self
+            .texel(x0, y0, sampler)
+            .lerp(&self.texel(x0 + 1, y0, sampler), s);
+        let c1 = self
+            .texel(x0, y0 + 1, sampler)
+            .lerp(&self.texel(x0 + 1, y0 + 1, sampler), s);
+        c0.lerp(&c1, t)
+    }
+}
+
 pub struct Texture {
-    image_data: Mat,
+    // levels[0] 为原图 之后每层边长减半 直到 1x1
+    levels: Vec<MipLevel>,
     pub width: i32,
     pub height: i32,
+    pub sampler: Sampler,
 }
 
 impl Texture {
@@ -21,54 +174,153 @@ impl Texture {
 
         println!("texture size is {}, {}", width, height);
 
+        let mut pixels = Vec::with_capacity((width * height) as usize);
+        for v in 0..height {
+            for u in 0..width {
+                let color = image_data
+                    .at_2d::<core::Vec3b>(v, u)
+                    .expect("get color wrong");
+                pixels.push(Vector3::new(
+                    color[0] as f32,
+                    color[1] as f32,
+                    color[2] as f32,
+                ));
+            }
+        }
+
+        Self::from_pixels(width, height, pixels)
+    }
+
+    /// 用按行存储的像素构造纹理 第一行是图像顶部 颜色范围 [0, 255]
+    pub fn from_pixels(width: i32, height: i32, pixels: Vec<Vector3f>) -> Self {
+        assert_eq!(pixels.len(), (width * height) as usize);
+        let mut levels = vec![MipLevel {
+            width,
+            height,
+            data: pixels,
+        }];
+        while levels.last().map_or(false, |l| l.width > 1 || l.height > 1) {
+            let next = levels.last().unwrap().downsample();
+            levels.push(next);
+        }
+
         Self {
-            image_data,
+            levels,
             width,
             height,
+            sampler: Default::default(),
         }
     }
 
+    pub fn set_sampler(&mut self, sampler: Sampler) {
+        self.sampler = sampler;
+    }
+
+    pub fn set_filter(&mut self, filter: FilterMode) {
+        self.sampler.filter = filter;
+    }
+
+    pub fn set_max_anisotropy(&mut self, max_anisotropy: u32) {
+        self.sampler.max_anisotropy = max_anisotropy.max(1);
+    }
+
+    pub fn mip_levels(&self) -> usize {
+        self.levels.len()
+    }
+
     fn raw_get_color(&self, u: i32, v: i32) -> Vector3<f32> {
-        let u = if u < 0 { u + self.width } else { u };
-        let v = if v < 0 { v + self.height } else { v };
-        let color = self
-            .image_data
-            .at_2d::<core::Vec3b>(v, u)
-            .expect("get color wrong");
-        Vector3::new(color[0] as f32, color[1] as f32, color[2] as f32)
+        self.levels[0].texel(u, v, &self.sampler)
     }
 
+    /// 原图上最近的纹素 按纹理的采样器处理超出范围的坐标
     pub fn get_color(&self, u: f32, v: f32) -> Vector3<f32> {
-        let u = (u * self.width as f32) as i32;
-        let v = ((1f32 - v) * self.height as f32) as i32;
-        self.raw_get_color(u, v)
+        if !u.is_finite() || !v.is_finite() {
+            return self.sampler.border_color;
+        }
+        self.levels[0].nearest(u, v, &self.sampler)
     }
 
+    /// 原图上的双线性插值 按纹理的采样器处理超出范围的坐标
     pub fn get_color_bilinear(&self, u: f32, v: f32) -> Vector3<f32> {
-        let u = u * self.width as f32;
-        let v = (1f32 - v) * self.height as f32;
+        if !u.is_finite() || !v.is_finite() {
+            return self.sampler.border_color;
+        }
+        self.levels[0].bilinear(u, v, &self.sampler)
+    }
 
-        let uu = (u + 0.5) as i32 as f32;
-        let uv = (v + 0.5) as i32 as f32;
-        let lu = (u - 0.5) as i32 as f32;
-        let lv = (v - 0.5) as i32 as f32;
+    pub fn get_color_raw(&self, u: i32, v: i32) -> Vector3<f32> {
+        self.raw_get_color(u, v)
+    }
 
-        let s = (u - lu) / (uu - lu);
-        let t = (v - lv) / (uv - lv);
+    // 把 uv 导数换算成纹素单位的长度
+    fn footprint(&self, d: Vector2f) -> f32 {
+        Vector2f::new(d.x * self.width as f32, d.y * self.height as f32).magnitude()
+    }
 
-        let (lu, lv, uu, uv) = (lu as i32, lv as i32, uu as i32, uv as i32);
-        let c00 = self.raw_get_color(lu, lv);
-        let c01 = self.raw_get_color(uu, lv);
-        let c10 = self.raw_get_color(lu, uv);
-        let c11 = self.raw_get_color(uu, uv);
+    fn trilinear(&self, uv: Vector2f, lod: f32, sampler: &Sampler) -> Vector3f {
+        let lod = lod.max(0f32).min((self.levels.len() - 1) as f32);
+        let l0 = lod.floor() as usize;
+        let l1 = (l0 + 1).min(self.levels.len() - 1);
+        let c0 = self.levels[l0].bilinear(uv.x, uv.y, sampler);
+        let c1 = self.levels[l1].bilinear(uv.x, uv.y, sampler);
+        c0.lerp(&c1, lod - l0 as f32)
+    }
 
-        let c0 = c00.lerp(&c01, s);
-        let c1 = c10.lerp(&c11, s);
+    fn anisotropic(&self, uv: Vector2f, dx: Vector2f, dy: Vector2f, sampler: &Sampler) -> Vector3f {
+        let (len_x, len_y) = (self.footprint(dx), self.footprint(dy));
+        let (major, minor, axis) = if len_x >= len_y {
+            (len_x, len_y, dx)
+        } else {
+            (len_y, len_x, dy)
+        };
+        let n = (major / minor.max(f32::EPSILON))
+            .ceil()
+            .max(1f32)
+            .min(sampler.max_anisotropy.max(1) as f32);
+        let lod = (major / n).max(f32::EPSILON).log2();
 
-        c0.lerp(&c1, t)
+        let count = n as i32;
+        (0..count)
+            .map(|k| {
+                let offset = (k as f32 + 0.5) / n - 0.5;
+                self.trilinear(uv + axis * offset, lod, sampler)
+            })
+            .sum::<Vector3f>()
+            / n
     }
 
-    pub fn get_color_raw(&self, u: i32, v: i32) -> Vector3<f32> {
-        self.raw_get_color(u, v)
+    /// 直接指定 mipmap 层级的三线性采样 0 为原图
+    pub fn sample_lod(&self, uv: Vector2f, lod: f32) -> Vector3f {
+        if !uv.x.is_finite() || !uv.y.is_finite() {
+            return self.sampler.border_color;
+        }
+        self.trilinear(uv, lod, &self.sampler)
+    }
+
+    /// 按纹理自带的采样器采样 dx dy 为 uv 在屏幕 x y 方向上的导数
+    pub fn sample(&self, uv: Vector2f, dx: Vector2f, dy: Vector2f) -> Vector3f {
+        self.sample_with(&self.sampler, uv, dx, dy)
+    }
+
+    /// 用外部给定的采样器采样 同一张纹理可以配合不同的采样器使用
+    pub fn sample_with(
+        &self,
+        sampler: &Sampler,
+        uv: Vector2f,
+        dx: Vector2f,
+        dy: Vector2f,
+    ) -> Vector3f {
+        if !uv.x.is_finite() || !uv.y.is_finite() {
+            return sampler.border_color;
+        }
+        match sampler.filter {
+            FilterMode::Nearest => self.levels[0].nearest(uv.x, uv.y, sampler),
+            FilterMode::Bilinear => self.levels[0].bilinear(uv.x, uv.y, sampler),
+            FilterMode::Trilinear => {
+                let rho = self.footprint(dx).max(self.footprint(dy));
+                self.trilinear(uv, rho.max(f32::EPSILON).log2(), sampler)
+            }
+            FilterMode::Anisotropic => self.anisotropic(uv, dx, dy, sampler),
+        }
     }
 }
@@ -7,6 +7,8 @@ pub struct Triangle {
     pub color: [Vector3<f32>; 3],
     pub tex_coords: [Vector2<f32>; 3],
     pub normal: [Vector3<f32>; 3],
+    /// 切线 w 分量为副切线的方向
+    pub tangent: [Vector4<f32>; 3],
 }
 
 impl Triangle {
@@ -32,6 +34,10 @@ impl Triangle {
         self.normal[ind] = n;
     }
 
+    pub fn set_tangent(&mut self, ind: usize, t: Vector4<f32>) {
+        self.tangent[ind] = t;
+    }
+
     pub fn set_color(&mut self, ind: usize, r: f32, g: f32, b: f32) -> Result<(), String> {
         if r < 0.0 || r > 255.0 || g < 0.0 || g > 255.0 || b < 0.0 || b > 255.0 {
             Err("Invalid color values".to_owned())
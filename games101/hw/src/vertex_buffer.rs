@@ -0,0 +1,187 @@
+use super::obj_loader;
+use nalgebra::{Matrix4, Vector2, Vector3, Vector4};
+use std::collections::HashMap;
+
+type Vector2f = Vector2<f32>;
+type Vector3f = Vector3<f32>;
+type Vector4f = Vector4<f32>;
+
+/// 顶点属性 除了位置都是可选的
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Attribute {
+    Position,
+    Normal,
+    TexCoord,
+    /// w 分量为副切线的方向
+    Tangent,
+    /// 范围 [0, 1]
+    Color,
+}
+
+/// 按属性分开存储的顶点数据 每个属性的长度都和位置相同
+#[derive(Debug, Clone, Default)]
+pub struct VertexBuffer {
+    positions: Vec<Vector3f>,
+    normals: Option<Vec<Vector3f>>,
+    tex_coords: Option<Vec<Vector2f>>,
+    tangents: Option<Vec<Vector4f>>,
+    colors: Option<Vec<Vector3f>>,
+}
+
+// 没有颜色属性时使用的颜色 和 Mesh 的绘制路径一致
+fn default_color() -> Vector3f {
+    Vector3f::new(148f32, 121f32, 92f32) / 255f32
+}
+
+impl VertexBuffer {
+    pub fn new(positions: Vec<Vector3f>) -> Self {
+        Self {
+            positions,
+            ..Default::default()
+        }
+    }
+
+    /// obj 文件中的一个网格 loader 按面展开的顶点在这里合并
+    /// 所有属性都相同的顶点只保留一个 返回顶点和三角形列表的下标
+    pub fn from_obj_mesh(mesh: &obj_loader::Mesh) -> (Self, Vec<u32>) {
+        let mut vertices: Vec<&obj_loader::Vertex> = vec![];
+        let mut welded = HashMap::new();
+        let indices = mesh
+            .indices
+            .iter()
+            .map(|&i| {
+                let v = &mesh.vertices[i];
+                let key: Vec<u32> = v
+                    .position
+                    .iter()
+                    .chain(v.normal.iter())
+                    .chain(v.texture_coordinates.iter())
+                    .chain(v.tangent.iter())
+                    .map(|f| f.to_bits())
+                    .collect();
+                *welded.entry(key).or_insert_with(|| {
+                    vertices.push(v);
+                    vertices.len() as u32 - 1
+                })
+            })
+            .collect();
+        let buffer = Self::new(vertices.iter().map(|v| v.position).collect())
+            .with_normals(vertices.iter().map(|v| v.normal).collect())
+            .with_tex_coords(vertices.iter().map(|v| v.texture_coordinates).collect())
+            .with_tangents(vertices.iter().map(|v| v.tangent).collect());
+        (buffer, indices)
+    }
+
+    fn check_len(&self, attribute: Attribute, len: usize) {
+        assert_eq!(
+            len,
+            self.positions.len(),
+            "{:?} attribute has {} elements but there are {} positions",
+            attribute,
+            len,
+            self.positions.len()
+        );
+    }
+
+    pub fn with_normals(mut self, normals: Vec<Vector3f>) -> Self {
+        self.check_len(Attribute::Normal, normals.len());
+        self.normals = Some(normals);
+        self
+    }
+
+    pub fn with_tex_coords(mut self, tex_coords: Vec<Vector2f>) -> Self {
+        self.check_len(Attribute::TexCoord, tex_coords.len());
+        self.tex_coords = Some(tex_coords);
+        self
+    }
+
+    pub fn with_tangents(mut self, tangents: Vec<Vector4f>) -> Self {
+        self.check_len(Attribute::Tangent, tangents.len());
+        self.tangents = Some(tangents);
+        self
+    }
+
+    pub fn with_colors(mut self, colors: Vec<Vector3f>) -> Self {
+        self.check_len(Attribute::Color, colors.len());
+        self.colors = Some(colors);
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    pub fn has(&self, attribute: Attribute) -> bool {
+        match attribute {
+            Attribute::Position => true,
+            Attribute::Normal => self.normals.is_some(),
+            Attribute::TexCoord => self.tex_coords.is_some(),
+            Attribute::Tangent => self.tangents.is_some(),
+            Attribute::Color => self.colors.is_some(),
+        }
+    }
+
+    pub fn position(&self, index: usize) -> Vector3f {
+        self.positions[index]
+    }
+
+    pub fn normal(&self, index: usize) -> Vector3f {
+        self.normals.as_ref().map_or(nalgebra::zero(), |n| n[index])
+    }
+
+    pub fn tex_coords(&self, index: usize) -> Vector2f {
+        self.tex_coords
+            .as_ref()
+            .map_or(nalgebra::zero(), |t| t[index])
+    }
+
+    pub fn tangent(&self, index: usize) -> Vector4f {
+        self.tangents
+            .as_ref()
+            .map_or(nalgebra::zero(), |t| t[index])
+    }
+
+    pub fn color(&self, index: usize) -> Vector3f {
+        self.colors
+            .as_ref()
+            .map_or_else(default_color, |c| c[index])
+    }
+}
+
+/// 一次 draw_indexed 中顶点变换缓存的统计
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct VertexCacheStats {
+    /// 实际执行顶点变换的次数 等于被引用到的不同顶点数
+    /// 开启曲面细分时不经过缓存 为展开后三角形的顶点数 即下标数 不含细分新增的顶点
+    pub transformed: usize,
+    /// 直接使用缓存结果的次数 开启曲面细分时为 0
+    pub hits: usize,
+}
+
+/// 实例化绘制中每个实例的数据 实例的下标作为 instance_id 交给顶点着色器
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Instance {
+    /// 在 set_model 设置的矩阵之前作用
+    pub model: Matrix4<f32>,
+    /// 和顶点颜色逐分量相乘
+    pub tint: Vector3f,
+}
+
+impl Default for Instance {
+    fn default() -> Self {
+        Self {
+            model: Matrix4::identity(),
+            tint: Vector3f::from_element(1f32),
+        }
+    }
+}
+
+impl Instance {
+    pub fn new(model: Matrix4<f32>, tint: Vector3f) -> Self {
+        Self { model, tint }
+    }
+}
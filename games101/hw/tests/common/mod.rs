@@ -0,0 +1,38 @@
+//! 集成测试共用的随机数和光栅化器设置
+
+#![allow(dead_code)]
+
+use nalgebra::{Matrix4, Vector3};
+use opencv_learn::rasterizer::Rasterizer;
+use opencv_learn::shader::FragmentShaderPayload;
+
+// 简单的线性同余随机数 结果可以复现
+pub struct Lcg(pub u64);
+
+impl Lcg {
+    pub fn next(&mut self) -> f32 {
+        self.0 = self
+            .0
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (self.0 >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    // [-extent, extent)
+    pub fn signed(&mut self, extent: f32) -> f32 {
+        (self.next() * 2f32 - 1f32) * extent
+    }
+}
+
+pub fn white(_: &FragmentShaderPayload) -> Vector3<f32> {
+    Vector3::from_element(1f32)
+}
+
+// MVP 都是单位矩阵 顶点坐标直接就是 NDC
+pub fn identity_rasterizer<'a>(width: i32, height: i32) -> Rasterizer<'a> {
+    let mut r = Rasterizer::new(width, height);
+    r.set_model(&Matrix4::identity());
+    r.set_view(&Matrix4::identity());
+    r.set_projection(&Matrix4::identity());
+    r
+}
@@ -0,0 +1,98 @@
+//! 方向和立方体贴图面上坐标的互相转换 以及按方向采样取到对应的像素
+
+mod common;
+
+use common::Lcg;
+use nalgebra::Vector3;
+use opencv_learn::cube_map::{CubeFace, CubeMap};
+
+fn assert_parallel(a: &Vector3<f32>, b: &Vector3<f32>) {
+    assert!(
+        (a.normalize() - b.normalize()).norm() < 1e-5,
+        "{:?} and {:?}",
+        a,
+        b
+    );
+}
+
+#[test]
+fn direction_round_trip() {
+    let mut rng = Lcg(38);
+    for _ in 0..10000 {
+        let dir = Vector3::new(rng.signed(1f32), rng.signed(1f32), rng.signed(1f32));
+        if dir.norm() < 1e-3 {
+            continue;
+        }
+        let (face, s, t) = CubeFace::from_direction(&dir);
+        assert!((0f32..=1f32).contains(&s) && (0f32..=1f32).contains(&t));
+        assert_parallel(&face.direction(s, t), &dir);
+    }
+}
+
+#[test]
+fn face_coordinates_round_trip() {
+    let mut rng = Lcg(83);
+    for &face in CubeFace::all().iter() {
+        for _ in 0..1000 {
+            // 面的边界上方向同时属于两个面 只取内部
+            let (s, t) = (0.01 + 0.98 * rng.next(), 0.01 + 0.98 * rng.next());
+            let (f, s2, t2) = CubeFace::from_direction(&face.direction(s, t));
+            assert_eq!(f, face);
+            assert!((s - s2).abs() < 1e-5 && (t - t2).abs() < 1e-5);
+        }
+    }
+}
+
+#[test]
+fn opengl_major_axis_table() {
+    let axes = [
+        Vector3::x(),
+        -Vector3::x(),
+        Vector3::y(),
+        -Vector3::y(),
+        Vector3::z(),
+        -Vector3::z(),
+    ];
+    for (&face, axis) in CubeFace::all().iter().zip(axes.iter()) {
+        assert_eq!(CubeFace::from_direction(axis), (face, 0.5, 0.5));
+    }
+    // 每个面左上角的方向
+    let corners = [
+        Vector3::new(1f32, 1f32, 1f32),
+        Vector3::new(-1f32, 1f32, -1f32),
+        Vector3::new(-1f32, 1f32, -1f32),
+        Vector3::new(-1f32, -1f32, 1f32),
+        Vector3::new(-1f32, 1f32, 1f32),
+        Vector3::new(1f32, 1f32, -1f32),
+    ];
+    for (&face, corner) in CubeFace::all().iter().zip(corners.iter()) {
+        assert_eq!(face.direction(0f32, 0f32), *corner, "{:?}", face);
+    }
+}
+
+#[test]
+fn sample_hits_the_texel() {
+    let encode = |dir: &Vector3<f32>| dir.map(|c| (c + 1f32) * 127.5);
+    let size = 8;
+    let cube = CubeMap::from_fn(size, encode);
+    assert_eq!(cube.size(), size);
+    for &face in CubeFace::all().iter() {
+        for y in 0..size {
+            for x in 0..size {
+                let s = (x as f32 + 0.5) / size as f32;
+                let t = (y as f32 + 0.5) / size as f32;
+                let dir = face.direction(s, t).normalize();
+                let color = cube.sample(&(dir * 3f32));
+                assert!(
+                    (color - encode(&dir)).norm() < 1e-2,
+                    "{:?} ({}, {}): {:?}",
+                    face,
+                    x,
+                    y,
+                    color
+                );
+            }
+        }
+    }
+    assert_eq!(cube.sample(&Vector3::zeros()), Vector3::zeros());
+}
@@ -0,0 +1,200 @@
+//! 共享边的网格覆盖整个屏幕时 每个采样点恰好被画一次
+//! 关闭深度写入 模板测试总是通过并在每次写入时加一 模板值就是每个采样点的覆盖次数
+
+mod common;
+
+use common::{identity_rasterizer, white, Lcg};
+use nalgebra::Vector3;
+use opencv_learn::rasterizer::{Buffers, Primitive, StencilOp, StencilState};
+use opencv_learn::vertex_buffer::VertexBuffer;
+
+const SIZE: i32 = 64;
+
+// n x n 个格子的网格 顶点为屏幕坐标 (像素) 每个格子沿随机的对角线分成两个三角形
+// flip 时交替改变三角形的绕向
+fn grid(
+    n: usize,
+    position: impl Fn(usize, usize) -> (f32, f32),
+    seed: u64,
+    flip: bool,
+) -> (Vec<Vector3<f32>>, Vec<u32>) {
+    let mut rng = Lcg(seed);
+    let mut vertices = vec![];
+    for j in 0..=n {
+        for i in 0..=n {
+            let (x, y) = position(i, j);
+            // 屏幕坐标转成 NDC 使用单位矩阵作为 MVP
+            vertices.push(Vector3::new(
+                2f32 * x / SIZE as f32 - 1f32,
+                2f32 * y / SIZE as f32 - 1f32,
+                0f32,
+            ));
+        }
+    }
+    let mut indices = vec![];
+    let at = |i: usize, j: usize| (j * (n + 1) + i) as u32;
+    for j in 0..n {
+        for i in 0..n {
+            let (a, b, c, d) = (at(i, j), at(i + 1, j), at(i + 1, j + 1), at(i, j + 1));
+            let mut faces = if rng.next() < 0.5 {
+                [[a, b, c], [a, c, d]]
+            } else {
+                [[a, b, d], [b, c, d]]
+            };
+            if flip && (i + j) % 2 == 1 {
+                faces.iter_mut().for_each(|f| f.swap(1, 2));
+            }
+            indices.extend(faces.iter().flatten());
+        }
+    }
+    (vertices, indices)
+}
+
+// 每个采样点的覆盖次数 每个像素连续存放 msaa 个
+fn coverage(vertices: Vec<Vector3<f32>>, indices: Vec<u32>, msaa: usize) -> Vec<u8> {
+    let mut r = identity_rasterizer(SIZE, SIZE);
+    r.set_fragment_shader(&white);
+    r.set_msaa(msaa);
+    r.set_depth_write(false);
+    r.set_stencil(Some(StencilState {
+        pass: StencilOp::IncrementClamp,
+        ..Default::default()
+    }));
+    r.clear(Buffers::COLOR | Buffers::DEPTH | Buffers::STENCIL);
+
+    let vert_buf = r.load_vertices(VertexBuffer::new(vertices));
+    let ind_buf = r.load_indices(indices);
+    r.draw_indexed(vert_buf, ind_buf, Primitive::Triangle, None);
+    r.stencil_samples()
+}
+
+fn assert_covered_once(coverage: &[u8], msaa: usize) {
+    assert_eq!(coverage.len(), (SIZE * SIZE) as usize * msaa);
+    for (index, &count) in coverage.iter().enumerate() {
+        let pixel = (index / msaa) as i32;
+        assert!(
+            count == 1,
+            "sample {} of pixel ({}, {}) covered {} times",
+            index % msaa,
+            pixel % SIZE,
+            pixel / SIZE,
+            count
+        );
+    }
+}
+
+// 顶点都在像素中心 水平 竖直和对角线的边正好穿过采样点
+#[test]
+fn edges_through_pixel_centers() {
+    let step = 8f32;
+    for seed in 0..4 {
+        let (vertices, indices) = grid(
+            10,
+            |i, j| (i as f32 * step - 7.5, j as f32 * step - 7.5),
+            seed,
+            false,
+        );
+        assert_covered_once(&coverage(vertices, indices, 1), 1);
+    }
+}
+
+// 随机扰动顶点 边的斜率任意 顶点不在定点网格上 扰动不超过格子的 0.2 倍 格子保持是凸的
+#[test]
+fn jittered_grid() {
+    let n = 12;
+    let step = 80f32 / n as f32;
+    for seed in 0..8 {
+        let mut rng = Lcg(seed + 100);
+        let jitter: Vec<_> = (0..(n + 1) * (n + 1))
+            .map(|_| (rng.next() - 0.5, rng.next() - 0.5))
+            .collect();
+        let (vertices, indices) = grid(
+            n,
+            |i, j| {
+                let (dx, dy) = jitter[j * (n + 1) + i];
+                (
+                    (i as f32 + dx * 0.4) * step - 8f32,
+                    (j as f32 + dy * 0.4) * step - 8f32,
+                )
+            },
+            seed,
+            true,
+        );
+        assert_covered_once(&coverage(vertices, indices, 1), 1);
+    }
+}
+
+// 多重采样时每个采样点也只被覆盖一次
+#[test]
+fn jittered_grid_msaa() {
+    let n = 7;
+    let step = 80f32 / n as f32;
+    for &msaa in &[2, 4] {
+        let mut rng = Lcg(msaa as u64);
+        let jitter: Vec<_> = (0..(n + 1) * (n + 1))
+            .map(|_| (rng.next() - 0.5, rng.next() - 0.5))
+            .collect();
+        let (vertices, indices) = grid(
+            n,
+            |i, j| {
+                let (dx, dy) = jitter[j * (n + 1) + i];
+                (
+                    (i as f32 + dx * 0.4) * step - 8f32,
+                    (j as f32 + dy * 0.4) * step - 8f32,
+                )
+            },
+            msaa as u64,
+            true,
+        );
+        assert_covered_once(&coverage(vertices, indices, msaa), msaa);
+    }
+}
+
+// 很多三角形共享屏幕中心的一个顶点
+#[test]
+fn triangle_fan() {
+    let center = Vector3::zeros();
+    let segments = 37;
+    let mut vertices = vec![center];
+    for k in 0..segments {
+        let angle = std::f32::consts::PI * 2f32 * k as f32 / segments as f32;
+        // 半径足够大 扇形覆盖整个屏幕
+        vertices.push(Vector3::new(angle.cos() * 3f32, angle.sin() * 3f32, 0f32));
+    }
+    let indices = (0..segments)
+        .flat_map(|k| vec![0, k + 1, (k + 1) % segments + 1])
+        .collect();
+    assert_covered_once(&coverage(vertices, indices, 1), 1);
+}
+
+// 只盖住屏幕最左或最右一列像素的长条 每行恰好一个像素 不越界也不落到相邻的行
+#[test]
+fn edge_columns() {
+    for &msaa in &[1, 4] {
+        let mut columns = vec![];
+        for &column in &[0, SIZE - 1] {
+            let ndc = |x: i32| 2f32 * x as f32 / SIZE as f32 - 1f32;
+            let (x0, x1) = (ndc(column), ndc(column + 1));
+            let vertices = vec![
+                Vector3::new(x0, -1.5, 0f32),
+                Vector3::new(x1, -1.5, 0f32),
+                Vector3::new(x1, 1.5, 0f32),
+                Vector3::new(x0, 1.5, 0f32),
+            ];
+            let counts = coverage(vertices, vec![0, 1, 2, 0, 2, 3], msaa);
+            let samples = |pixel: i32| &counts[pixel as usize * msaa..(pixel as usize + 1) * msaa];
+            let covered: Vec<i32> = (0..SIZE * SIZE)
+                .filter(|&p| samples(p).iter().any(|&c| c > 0))
+                .collect();
+            assert_eq!(covered.len(), SIZE as usize, "column {}", column);
+            let x = covered[0] % SIZE;
+            assert!(x == 0 || x == SIZE - 1, "column {} drawn at {}", column, x);
+            for (row, &pixel) in covered.iter().enumerate() {
+                assert_eq!(pixel, row as i32 * SIZE + x, "column {}", column);
+                assert!(samples(pixel).iter().all(|&c| c == 1), "column {}", column);
+            }
+            columns.push(x);
+        }
+        assert_ne!(columns[0], columns[1]);
+    }
+}
@@ -0,0 +1,113 @@
+//! 各种雾在固定距离上的透过率 以及和颜色的混合
+
+use nalgebra::Vector3;
+use opencv_learn::fog::{Fog, FogMode};
+use opencv_learn::shader::FragmentShaderPayload;
+
+fn fog(mode: FogMode, density: f32) -> Fog {
+    Fog::new(mode, Vector3::new(0.5, 0.6, 0.7), density)
+}
+
+// 从原点沿 -z 看距离为 d 的点
+fn at(fog: &Fog, d: f32) -> f32 {
+    fog.transmittance(&Vector3::zeros(), &Vector3::new(0f32, 0f32, -d))
+}
+
+fn assert_close(actual: f32, expected: f32) {
+    assert!(
+        (actual - expected).abs() < 1e-6,
+        "{} != {}",
+        actual,
+        expected
+    );
+}
+
+#[test]
+fn linear() {
+    let linear = fog(
+        FogMode::Linear {
+            start: 2f32,
+            end: 10f32,
+        },
+        0f32,
+    );
+    for &(d, t) in &[
+        (0f32, 1f32),
+        (2f32, 1f32),
+        (6f32, 0.5),
+        (8f32, 0.25),
+        (10f32, 0f32),
+        (20f32, 0f32),
+    ] {
+        assert_close(at(&linear, d), t);
+    }
+    // start 和 end 相同时在 start 处突变
+    let step = fog(
+        FogMode::Linear {
+            start: 5f32,
+            end: 5f32,
+        },
+        0f32,
+    );
+    assert_close(at(&step, 4.9), 1f32);
+    assert_close(at(&step, 5.1), 0f32);
+}
+
+#[test]
+fn exponential() {
+    let exp = fog(FogMode::Exponential, 0.1);
+    assert_close(at(&exp, 0f32), 1f32);
+    assert_close(at(&exp, 10f32), (-1f32).exp());
+    assert_close(at(&exp, 30f32), (-3f32).exp());
+
+    let exp2 = fog(FogMode::ExponentialSquared, 0.1);
+    assert_close(at(&exp2, 0f32), 1f32);
+    assert_close(at(&exp2, 5f32), (-0.25f32).exp());
+    assert_close(at(&exp2, 10f32), (-1f32).exp());
+    assert_close(at(&exp2, 20f32), (-4f32).exp());
+}
+
+#[test]
+fn height() {
+    let height = |falloff| {
+        fog(
+            FogMode::Height {
+                base: 0f32,
+                falloff,
+            },
+            0.1,
+        )
+    };
+    // 在基准高度上水平看和指数雾相同
+    assert_close(at(&height(1f32), 10f32), (-1f32).exp());
+    // 没有衰减时和高度无关
+    let eye = Vector3::new(0f32, 3f32, 0f32);
+    let flat = height(0f32).transmittance(&eye, &Vector3::new(0f32, 3f32, -10f32));
+    assert_close(flat, (-1f32).exp());
+    // 在高度 3 处水平看 密度为 0.1 e^-3
+    let high = height(1f32).transmittance(&eye, &Vector3::new(0f32, 3f32, -10f32));
+    assert_close(high, (-(-3f32).exp()).exp());
+    // 从基准高度竖直向上看到 2 光学厚度为 0.1 (1 - e^-2)
+    let up = height(1f32).transmittance(&Vector3::zeros(), &Vector3::new(0f32, 2f32, 0f32));
+    assert_close(up, (-0.1 * (1f32 - (-2f32).exp())).exp());
+}
+
+#[test]
+fn blend_with_color() {
+    let exp = fog(FogMode::Exponential, 0.1);
+    let color = Vector3::new(1f32, 0f32, 0f32);
+    let eye = Vector3::new(0f32, 0f32, 10f32);
+    let point = Vector3::zeros();
+    let t = (-1f32).exp();
+    let expected = exp.color * (1f32 - t) + color * t;
+    assert!((exp.apply(color, &eye, &point) - expected).norm() < 1e-6);
+
+    // 着色器从观察空间的位置和相机位置得到世界空间的点
+    let mut payload = FragmentShaderPayload::new(color, Vector3::z(), Default::default(), None);
+    payload.eye_pos = eye;
+    payload.view_pos = Vector3::new(0f32, 0f32, -10f32);
+    payload.fog = Some(&exp);
+    assert!((opencv_learn::apply_fog(&payload, color) - expected).norm() < 1e-6);
+    payload.fog = None;
+    assert_eq!(opencv_learn::apply_fog(&payload, color), color);
+}
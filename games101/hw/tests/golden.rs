@@ -0,0 +1,254 @@
+//! 渲染 spot 模型和仓库中保存的图比较 使用 PSNR 和 SSIM 阈值
+//! tests/golden 中是每个着色器的参考图 crate 目录下的 output.png origin.png 等是作业保存的渲染结果
+//! 设置 GOLDEN_UPDATE=1 时用当前的渲染结果覆盖两者 参考图不存在又没有设置时测试失败
+//! 比较失败时渲染结果和差异图写在 CARGO_TARGET_TMPDIR/golden 中
+
+use nalgebra::Vector3;
+use opencv_learn::cli::Shader;
+use opencv_learn::texture::{FilterMode, Texture};
+use opencv_learn::{image, mesh, rasterizer, scene::Scene};
+use std::path::{Path, PathBuf};
+
+const SIZE: i32 = 256;
+const MIN_PSNR: f64 = 35.0;
+const MIN_SSIM: f64 = 0.98;
+const SPOT: &str = "models/spot/spot_triangulated_good.obj";
+
+fn manifest_path(relative: &str) -> String {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join(relative)
+        .to_string_lossy()
+        .into_owned()
+}
+
+// 和 main.rs 默认参数一样的相机和模型变换
+fn render(size: i32, model: &str, shader: Shader, texture: &str, filter: FilterMode) -> Vec<f32> {
+    let meshes = mesh::load_obj(&manifest_path(model)).expect("load model");
+    let scene = Scene::from_meshes(meshes);
+
+    let active_shader = shader.function();
+    let mut r = rasterizer::Rasterizer::new(size, size);
+    let mut texture = Texture::new(&manifest_path(texture));
+    texture.set_filter(filter);
+    r.set_texture(texture);
+    if shader == Shader::Displacement {
+        r.set_tessellation(Some(Default::default()));
+    }
+    r.set_fragment_shader(&active_shader);
+    r.set_vertex_shader(&opencv_learn::vertex_shader);
+
+    r.clear(rasterizer::Buffers::COLOR | rasterizer::Buffers::DEPTH);
+    r.set_model(&opencv_learn::get_model_matrix(140f32));
+    r.set_view(&opencv_learn::get_view_matrix(Vector3::new(
+        0f32, 0f32, 10f32,
+    )));
+    r.set_projection(&opencv_learn::get_projection_matrix(
+        45f32, 1f32, 0.1f32, 50f32,
+    ));
+    r.draw_scene(&scene);
+    // 和保存的 8 位图像一样取整
+    r.output_buffer().iter().map(|c| c.round()).collect()
+}
+
+fn psnr(a: &[f32], b: &[f32]) -> f64 {
+    let mse = a
+        .iter()
+        .zip(b)
+        .map(|(x, y)| (*x as f64 - *y as f64).powi(2))
+        .sum::<f64>()
+        / a.len() as f64;
+    if mse == 0.0 {
+        f64::INFINITY
+    } else {
+        10.0 * (255.0 * 255.0 / mse).log10()
+    }
+}
+
+// 亮度上 8x8 窗口 步长 4 的平均 SSIM
+fn ssim(a: &[f32], b: &[f32], width: i32, height: i32) -> f64 {
+    const WINDOW: i32 = 8;
+    const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+    const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+    let luma = |buf: &[f32]| -> Vec<f64> {
+        buf.chunks(3)
+            .map(|c| 0.299 * c[0] as f64 + 0.587 * c[1] as f64 + 0.114 * c[2] as f64)
+            .collect()
+    };
+    let (la, lb) = (luma(a), luma(b));
+
+    let (mut sum, mut count) = (0.0, 0);
+    for y0 in (0..=height - WINDOW).step_by(4) {
+        for x0 in (0..=width - WINDOW).step_by(4) {
+            let n = (WINDOW * WINDOW) as f64;
+            let pixels = || {
+                (y0..y0 + WINDOW)
+                    .flat_map(move |y| (x0..x0 + WINDOW).map(move |x| (y * width + x) as usize))
+            };
+            let mean_a = pixels().map(|i| la[i]).sum::<f64>() / n;
+            let mean_b = pixels().map(|i| lb[i]).sum::<f64>() / n;
+            let (mut var_a, mut var_b, mut cov) = (0.0, 0.0, 0.0);
+            for i in pixels() {
+                let (da, db) = (la[i] - mean_a, lb[i] - mean_b);
+                var_a += da * da;
+                var_b += db * db;
+                cov += da * db;
+            }
+            let (var_a, var_b, cov) = (var_a / (n - 1.0), var_b / (n - 1.0), cov / (n - 1.0));
+            sum += ((2.0 * mean_a * mean_b + C1) * (2.0 * cov + C2))
+                / ((mean_a * mean_a + mean_b * mean_b + C1) * (var_a + var_b + C2));
+            count += 1;
+        }
+    }
+    sum / count as f64
+}
+
+fn compare(name: &str, actual: &[f32], reference_path: &str) {
+    let (width, height, expected) = image::load(reference_path).expect("read reference image");
+    assert_eq!(
+        actual.len(),
+        (width * height * 3) as usize,
+        "reference image {} has a different size",
+        reference_path
+    );
+    let (psnr, ssim) = (
+        psnr(actual, &expected),
+        ssim(actual, &expected, width, height),
+    );
+    if psnr >= MIN_PSNR && ssim >= MIN_SSIM {
+        return;
+    }
+
+    let out_dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("golden");
+    std::fs::create_dir_all(&out_dir).expect("create output directory");
+    let out = |suffix: &str| {
+        out_dir
+            .join(format!("{}_{}.png", name, suffix))
+            .to_string_lossy()
+            .into_owned()
+    };
+    // 差异放大 4 倍以便看清
+    let mut diff: Vec<f32> = actual
+        .iter()
+        .zip(&expected)
+        .map(|(a, e)| ((a - e).abs() * 4f32).min(255f32))
+        .collect();
+    image::save(&out("actual"), width, height, &mut actual.to_vec()).expect("write actual image");
+    image::save(&out("diff"), width, height, &mut diff).expect("write diff image");
+    panic!(
+        "{} differs from {}: psnr {:.2} dB (min {}), ssim {:.4} (min {}), see {} and {}",
+        name,
+        reference_path,
+        psnr,
+        MIN_PSNR,
+        ssim,
+        MIN_SSIM,
+        out("actual"),
+        out("diff")
+    );
+}
+
+fn update_requested() -> bool {
+    std::env::var("GOLDEN_UPDATE").map_or(false, |v| v == "1")
+}
+
+// 按需覆盖参考图 否则和参考图比较
+fn check_against(name: &str, actual: &[f32], size: i32, reference_path: &str) {
+    if update_requested() {
+        if let Some(dir) = Path::new(reference_path).parent() {
+            std::fs::create_dir_all(dir).expect("create golden directory");
+        }
+        image::save(reference_path, size, size, &mut actual.to_vec())
+            .expect("write reference image");
+        println!("wrote reference image {}", reference_path);
+        return;
+    }
+    assert!(
+        Path::new(reference_path).exists(),
+        "reference image {} is missing, run with GOLDEN_UPDATE=1 to create it",
+        reference_path
+    );
+    compare(name, actual, reference_path);
+}
+
+fn check(shader: Shader) {
+    let texture = format!("models/spot/{}", shader.default_texture());
+    let actual = render(SIZE, SPOT, shader, &texture, FilterMode::Bilinear);
+    let reference_path = manifest_path(&format!("tests/golden/{}.png", shader.name()));
+    check_against(shader.name(), &actual, SIZE, &reference_path);
+}
+
+fn check_legacy(file: &str, model: &str, shader: Shader, texture: &str, filter: FilterMode) {
+    const LEGACY_SIZE: i32 = 700;
+    let actual = render(LEGACY_SIZE, model, shader, texture, filter);
+    let name = Path::new(file).file_stem().unwrap().to_string_lossy();
+    check_against(&name, &actual, LEGACY_SIZE, &manifest_path(file));
+}
+
+#[test]
+fn normal_shader() {
+    check(Shader::Normal);
+}
+
+#[test]
+fn phong_shader() {
+    check(Shader::Phong);
+}
+
+#[test]
+fn texture_shader() {
+    check(Shader::Texture);
+}
+
+#[test]
+fn bump_shader() {
+    check(Shader::Bump);
+}
+
+#[test]
+fn displacement_shader() {
+    check(Shader::Displacement);
+}
+
+#[test]
+fn legacy_phong_rock() {
+    check_legacy(
+        "output.png",
+        "models/rock/rock.obj",
+        Shader::Phong,
+        "models/spot/hmap.jpg",
+        FilterMode::Bilinear,
+    );
+}
+
+#[test]
+fn legacy_texture() {
+    check_legacy(
+        "origin.png",
+        SPOT,
+        Shader::Texture,
+        "models/spot/spot_texture.png",
+        FilterMode::Bilinear,
+    );
+}
+
+#[test]
+fn legacy_low_texture_nearest() {
+    check_legacy(
+        "low_origin.png",
+        SPOT,
+        Shader::Texture,
+        "models/spot/spot_texture_low.png",
+        FilterMode::Nearest,
+    );
+}
+
+#[test]
+fn legacy_low_texture_bilinear() {
+    check_legacy(
+        "low_bilinear.png",
+        SPOT,
+        Shader::Texture,
+        "models/spot/spot_texture_low.png",
+        FilterMode::Bilinear,
+    );
+}
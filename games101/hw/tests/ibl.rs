@@ -0,0 +1,84 @@
+//! GGX 的归一化 BRDF 查找表的能量 以及常量环境光的预计算结果
+
+use nalgebra::Vector3;
+use opencv_learn::cube_map::CubeMap;
+use opencv_learn::ibl::{distribution_ggx, geometry_smith, BrdfLut, Ibl};
+
+// 半球上 ∫ D(h) (n·h) dω = 2π ∫ D(μ) μ dμ 投影后的微表面面积为 1
+#[test]
+fn ggx_is_normalized() {
+    let steps = 200000;
+    for &roughness in &[0.2, 0.5, 0.8, 1f32] {
+        let sum: f64 = (0..steps)
+            .map(|i| {
+                let mu = (i as f32 + 0.5) / steps as f32;
+                (distribution_ggx(mu, roughness) * mu) as f64
+            })
+            .sum();
+        let integral = 2f64 * std::f64::consts::PI * sum / steps as f64;
+        assert!(
+            (integral - 1f64).abs() < 1e-3,
+            "{}: {}",
+            roughness,
+            integral
+        );
+    }
+}
+
+#[test]
+fn smith_geometry() {
+    for &roughness in &[0f32, 0.5, 1f32] {
+        assert!((geometry_smith(1f32, 1f32, roughness) - 1f32).abs() < 1e-6);
+        assert_eq!(geometry_smith(0f32, 1f32, roughness), 0f32);
+    }
+    // 越粗糙遮蔽越多
+    assert!(geometry_smith(0.5, 0.5, 0.2) > geometry_smith(0.5, 0.5, 0.9));
+}
+
+#[test]
+fn brdf_lut() {
+    let lut = BrdfLut::new(16, 256);
+    for j in 0..=10 {
+        for i in 0..=10 {
+            let (n_dot_v, roughness) = (i as f32 / 10f32, j as f32 / 10f32);
+            let ab = lut.lookup(n_dot_v, roughness);
+            // F0 = 1 时的反照率 A + B 不超过 1
+            assert!(ab.x >= 0f32 && ab.y >= 0f32, "{:?}", ab);
+            assert!(
+                ab.x + ab.y <= 1.001,
+                "({}, {}): {:?}",
+                n_dot_v,
+                roughness,
+                ab
+            );
+        }
+    }
+    // 正对着光滑表面时几乎没有菲涅耳和遮蔽的损失
+    let smooth = lut.lookup(1f32, 0f32);
+    assert!(smooth.x > 0.95 && smooth.y < 0.02, "{:?}", smooth);
+    // 掠射角时菲涅耳项变大 光滑表面的反照率仍然接近 1
+    let grazing = lut.lookup(0.05, 0f32);
+    assert!(
+        grazing.y > 0.5 && grazing.x + grazing.y > 0.9,
+        "{:?}",
+        grazing
+    );
+    // 粗糙表面掠射时多次散射的能量没有计入 反照率明显小于 1
+    let rough = lut.lookup(0.05, 1f32);
+    assert!(rough.x + rough.y < 0.7, "{:?}", rough);
+}
+
+#[test]
+fn constant_environment() {
+    let color = Vector3::new(200f32, 100f32, 50f32);
+    let environment = CubeMap::from_fn(16, |_| color);
+    let ibl = Ibl::with_settings(&environment, 16, 3, 32);
+    let close = |c: Vector3<f32>| (c - color).norm() < 1e-2 * color.norm();
+    for dir in &[Vector3::x(), -Vector3::y(), Vector3::new(1f32, 2f32, -3f32)] {
+        assert!(close(ibl.irradiance(dir)), "{:?}", ibl.irradiance(dir));
+        for &roughness in &[0f32, 0.3, 1f32] {
+            let c = ibl.prefiltered(dir, roughness);
+            assert!(close(c), "{:?} at {}: {:?}", dir, roughness, c);
+        }
+    }
+}
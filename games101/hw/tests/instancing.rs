@@ -0,0 +1,89 @@
+//! 实例化绘制时实例的下标和颜色进入顶点和片元阶段 开启曲面细分时也一样
+
+mod common;
+
+use common::identity_rasterizer;
+use nalgebra::{Matrix4, Vector3};
+use opencv_learn::rasterizer::{Buffers, Primitive};
+use opencv_learn::shader::{FragmentShaderPayload, VertexShaderPayload};
+use opencv_learn::vertex_buffer::{Instance, VertexBuffer};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// 顶点着色器见过的实例 每个实例一位
+static VERTEX_INSTANCES: AtomicUsize = AtomicUsize::new(0);
+
+fn record_instance(payload: &VertexShaderPayload) -> Vector3<f32> {
+    VERTEX_INSTANCES.fetch_or(1 << payload.instance_id, Ordering::Relaxed);
+    payload.position
+}
+
+fn vertex_color(payload: &FragmentShaderPayload) -> Vector3<f32> {
+    payload.color
+}
+
+#[test]
+fn tint_and_instance_id() {
+    let color = Vector3::new(0.5, 1f32, 0.25);
+    let tints = [
+        Vector3::new(1f32, 0.5, 0f32),
+        Vector3::new(0.25, 1f32, 1f32),
+        Vector3::new(1f32, 1f32, 0.5),
+    ];
+    // 屏幕左边三分之一的矩形 每个实例向右平移三分之一
+    let third = 2f32 / 3f32;
+    let positions = vec![
+        Vector3::new(-1f32, -1f32, 0f32),
+        Vector3::new(-1f32 + third, -1f32, 0f32),
+        Vector3::new(-1f32 + third, 1f32, 0f32),
+        Vector3::new(-1f32, 1f32, 0f32),
+    ];
+    for &tessellation in &[false, true] {
+        VERTEX_INSTANCES.store(0, Ordering::Relaxed);
+        let mut r = identity_rasterizer(60, 20);
+        r.set_vertex_shader(&record_instance);
+        r.set_fragment_shader(&vertex_color);
+        r.set_id_buffer(true);
+        if tessellation {
+            r.set_tessellation(Some(Default::default()));
+        }
+        r.clear(Buffers::COLOR | Buffers::DEPTH);
+
+        let vertices = VertexBuffer::new(positions.clone()).with_colors(vec![color; 4]);
+        let vert_buf = r.load_vertices(vertices);
+        let ind_buf = r.load_indices(vec![0, 1, 2, 0, 2, 3]);
+        let instances = tints
+            .iter()
+            .enumerate()
+            .map(|(i, &tint)| Instance {
+                model: Matrix4::new_translation(&Vector3::new(third * i as f32, 0f32, 0f32)),
+                tint,
+            })
+            .collect();
+        let inst_buf = r.load_instances(instances);
+        r.draw_instanced(vert_buf, ind_buf, inst_buf, Primitive::Triangle, None);
+
+        assert_eq!(
+            VERTEX_INSTANCES.load(Ordering::Relaxed),
+            0b111,
+            "tessellation {}",
+            tessellation
+        );
+        let frame = r.frame_buffer();
+        let mut covered = [0; 3];
+        for (pixel, sample) in r.id_buffer().iter().enumerate() {
+            let id = sample.expect("every pixel is covered").instance_id as usize;
+            covered[id] += 1;
+            let expected = color.component_mul(&tints[id]);
+            let actual = Vector3::from_column_slice(&frame[pixel * 3..pixel * 3 + 3]);
+            assert!(
+                (actual - expected).magnitude() < 1e-5,
+                "instance {} has color {:?}, expected {:?}, tessellation {}",
+                id,
+                actual,
+                expected,
+                tessellation
+            );
+        }
+        assert_eq!(covered, [400; 3], "tessellation {}", tessellation);
+    }
+}
@@ -0,0 +1,121 @@
+//! 层次 Z 剔除和遮挡查询都是保守的 开关剔除不改变画面 被挡住的网格确实被跳过
+
+use nalgebra::{Matrix4, Vector3, Vector4};
+use opencv_learn::hiz::CullStats;
+use opencv_learn::mesh::Mesh;
+use opencv_learn::rasterizer::{Buffers, Rasterizer};
+use opencv_learn::shader::FragmentShaderPayload;
+use opencv_learn::triangle::Triangle;
+
+const SIZE: i32 = 96;
+
+fn view_position(payload: &FragmentShaderPayload) -> Vector3<f32> {
+    payload.view_pos.map(|v| v.abs() / 5f32)
+}
+
+// z 平面上边长 2 * half 的正方形 分成 n x n 个格子
+fn quad(z: f32, half: f32, n: usize) -> Mesh {
+    let mut mesh = Mesh::default();
+    let at = |i: usize| -half + 2f32 * half * i as f32 / n as f32;
+    for j in 0..n {
+        for i in 0..n {
+            let corners = [
+                (at(i), at(j)),
+                (at(i + 1), at(j)),
+                (at(i + 1), at(j + 1)),
+                (at(i), at(j + 1)),
+            ];
+            for face in &[[0, 1, 2], [0, 2, 3]] {
+                let mut t = Triangle::new();
+                for (k, &c) in face.iter().enumerate() {
+                    let (x, y) = corners[c];
+                    t.set_vertex(k, Vector4::new(x, y, z, 1f32));
+                    t.set_normal(k, Vector3::z());
+                }
+                mesh.triangles.push(t);
+            }
+        }
+    }
+    mesh
+}
+
+fn rasterizer() -> Rasterizer<'static> {
+    let mut r = Rasterizer::new(SIZE, SIZE);
+    r.set_model(&Matrix4::identity());
+    r.set_view(&opencv_learn::get_view_matrix(Vector3::new(
+        0f32, 0f32, 5f32,
+    )));
+    r.set_projection(&opencv_learn::get_projection_matrix(
+        45f32, 1f32, 0.1, 50f32,
+    ));
+    r.set_fragment_shader(&view_position);
+    r.clear(Buffers::COLOR | Buffers::DEPTH);
+    r
+}
+
+// 先画挡住整个屏幕的平面 再画它后面的网格和它前面的小平面
+fn render(occlusion_culling: bool, msaa: usize) -> (Vec<f32>, CullStats) {
+    let mut r = rasterizer();
+    r.set_msaa(msaa);
+    r.set_occlusion_culling(occlusion_culling);
+    r.draw_meshes(&[quad(1f32, 3f32, 1)]);
+    r.draw_meshes(&[quad(-1f32, 1f32, 8), quad(2f32, 0.5, 2)]);
+    (r.frame_buffer(), r.cull_stats())
+}
+
+#[test]
+fn culling_keeps_the_image() {
+    for &msaa in &[1, 4] {
+        let (without, stats_without) = render(false, msaa);
+        let (with, stats_with) = render(true, msaa);
+        assert!(with == without, "occlusion culling changed the image");
+
+        // 没有开启遮挡剔除时 被挡住的 128 个三角形在分块前被层次 Z 剔除
+        assert_eq!(stats_without.meshes, 0);
+        assert!(stats_without.triangles >= 128, "{:?}", stats_without);
+        // 开启后整个网格被跳过 前面的小平面照常绘制
+        assert_eq!(stats_with.meshes, 1, "{:?}", stats_with);
+        assert!(stats_with.triangles < stats_without.triangles);
+    }
+}
+
+#[test]
+fn query_against_drawn_depth() {
+    let mut r = rasterizer();
+    let behind = (
+        Vector3::new(-1f32, -1f32, -2f32),
+        Vector3::new(1f32, 1f32, -1f32),
+    );
+    // 还没有画东西时什么都挡不住
+    assert!(r.occlusion_query(&behind.0, &behind.1));
+
+    r.draw_meshes(&[quad(1f32, 3f32, 1)]);
+    assert!(!r.occlusion_query(&behind.0, &behind.1));
+    // 在遮挡物前面 或者穿过遮挡物
+    assert!(r.occlusion_query(
+        &Vector3::new(-0.5, -0.5, 1.5),
+        &Vector3::new(0.5, 0.5, 2f32)
+    ));
+    assert!(r.occlusion_query(
+        &Vector3::new(-0.5, -0.5, -1f32),
+        &Vector3::new(0.5, 0.5, 2f32)
+    ));
+    // 屏幕外
+    assert!(!r.occlusion_query(
+        &Vector3::new(50f32, 50f32, 1.5),
+        &Vector3::new(51f32, 51f32, 2f32)
+    ));
+    // 包围盒在相机后面 或者包含相机
+    assert!(!r.occlusion_query(
+        &Vector3::new(-1f32, -1f32, 6f32),
+        &Vector3::new(1f32, 1f32, 7f32)
+    ));
+    assert!(r.occlusion_query(
+        &Vector3::new(-1f32, -1f32, 4f32),
+        &Vector3::new(1f32, 1f32, 6f32)
+    ));
+
+    // 清除深度之后又能看到
+    r.clear(Buffers::DEPTH);
+    assert!(r.occlusion_query(&behind.0, &behind.1));
+}
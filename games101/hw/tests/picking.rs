@@ -0,0 +1,101 @@
+//! 透视投影下拾取到的位置落在像素中心的视线和三角形的交点上
+
+use nalgebra::{Matrix4, Vector3, Vector4};
+use opencv_learn::mesh::Mesh;
+use opencv_learn::rasterizer::{Buffers, Rasterizer};
+use opencv_learn::triangle::Triangle;
+
+mod common;
+
+const SIZE: i32 = 64;
+
+fn mesh(triangles: &[[Vector3<f32>; 3]]) -> Mesh {
+    let mut mesh = Mesh::default();
+    for vertices in triangles {
+        let mut t = Triangle::new();
+        for (k, v) in vertices.iter().enumerate() {
+            t.set_vertex(k, Vector4::new(v.x, v.y, v.z, 1f32));
+            t.set_normal(k, Vector3::z());
+        }
+        mesh.triangles.push(t);
+    }
+    mesh
+}
+
+// 像素中心的视线和平面的交点 pick 的 x 和 get_index 一样是镜像的
+fn ray_hit(mvp: &Matrix4<f32>, x: i32, y: i32, plane: &[Vector3<f32>; 3]) -> Vector3<f32> {
+    let inv = mvp.try_inverse().unwrap();
+    let ndc_x = 2f32 * ((SIZE - 1 - x) as f32 + 0.5) / SIZE as f32 - 1f32;
+    let ndc_y = 2f32 * (y as f32 + 0.5) / SIZE as f32 - 1f32;
+    let unproject = |z: f32| {
+        let p = inv * Vector4::new(ndc_x, ndc_y, z, 1f32);
+        p.xyz() / p.w
+    };
+    let (a, b) = (unproject(-0.5), unproject(0.5));
+    let n = (plane[1] - plane[0]).cross(&(plane[2] - plane[0]));
+    let s = n.dot(&(plane[0] - a)) / n.dot(&(b - a));
+    a + (b - a) * s
+}
+
+#[test]
+fn pick_on_a_tilted_quad() {
+    let view = opencv_learn::get_view_matrix(Vector3::new(0f32, 0f32, 5f32));
+    let projection = opencv_learn::get_projection_matrix(45f32, 1f32, 0.1, 50f32);
+    let mut r = Rasterizer::new(SIZE, SIZE);
+    r.set_model(&Matrix4::identity());
+    r.set_view(&view);
+    r.set_projection(&projection);
+    r.set_fragment_shader(&common::white);
+    r.set_id_buffer(true);
+    r.set_mesh_id(7);
+    r.clear(Buffers::COLOR | Buffers::DEPTH);
+
+    // 从 z = 3 斜着伸到 z = -12 的正方形 深度变化大 屏幕空间插值会明显偏离
+    let corners = [
+        Vector3::new(-2f32, -1.5, 3f32),
+        Vector3::new(2f32, -1.5, 3f32),
+        Vector3::new(2f32, 1.5, -12f32),
+        Vector3::new(-2f32, 1.5, -12f32),
+    ];
+    let behind = [
+        Vector3::new(-1f32, -1f32, -30f32),
+        Vector3::new(1f32, -1f32, -30f32),
+        Vector3::new(0f32, 1f32, -30f32),
+    ];
+    let quad = [
+        [corners[0], corners[1], corners[2]],
+        [corners[0], corners[2], corners[3]],
+    ];
+    r.draw_meshes(&[mesh(&[behind]), mesh(&quad)]);
+
+    let mvp = projection * view;
+    let mut picked = [0; 2];
+    for y in 0..SIZE {
+        for x in 0..SIZE {
+            let hit = match r.pick(x, y) {
+                Some(hit) if hit.mesh_id == 8 => hit,
+                _ => continue,
+            };
+            assert_eq!(hit.instance_id, 0);
+            let triangle = &quad[hit.triangle_id as usize];
+            picked[hit.triangle_id as usize] += 1;
+
+            let expected = ray_hit(&mvp, x, y, triangle);
+            assert!(
+                (hit.position - expected).norm() < 1e-2,
+                "({}, {}) picked {:?}, expected {:?}",
+                x,
+                y,
+                hit.position,
+                expected
+            );
+            let b = hit.barycentric;
+            let position = triangle[0] * b.x + triangle[1] * b.y + triangle[2] * b.z;
+            assert!((position - hit.position).norm() < 1e-4);
+            assert!((hit.normal - Vector3::z()).norm() < 1e-6);
+        }
+    }
+    assert!(picked[0] > 50 && picked[1] > 50, "{:?}", picked);
+    assert_eq!(r.pick(-1, 0), None);
+    assert_eq!(r.pick(0, SIZE), None);
+}
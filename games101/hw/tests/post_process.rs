@@ -0,0 +1,165 @@
+//! 后处理链中各步在已知输入上的结果
+
+use nalgebra::Vector3;
+use opencv_learn::post_process::{Geometry, Outline, PostEffect, PostProcess, ToneMapping};
+
+// 单个像素 三个通道分别为 values
+fn apply(effects: Vec<PostEffect>, values: [f32; 3]) -> Vec<f32> {
+    PostProcess::new(effects).apply(1, 1, &values)
+}
+
+fn assert_close(actual: &[f32], expected: &[f32]) {
+    assert_eq!(actual.len(), expected.len());
+    for (a, e) in actual.iter().zip(expected) {
+        assert!((a - e).abs() < 1e-5, "{:?} != {:?}", actual, expected);
+    }
+}
+
+#[test]
+fn empty_chain_clamps() {
+    assert_close(&apply(vec![], [2f32, -1f32, 0.25]), &[1f32, 0f32, 0.25]);
+}
+
+#[test]
+fn exposure() {
+    let ev = |ev| vec![PostEffect::Exposure(ev)];
+    assert_close(&apply(ev(1f32), [0.25, 0.1, 0f32]), &[0.5, 0.2, 0f32]);
+    assert_close(&apply(ev(-2f32), [1f32, 2f32, 4f32]), &[0.25, 0.5, 1f32]);
+}
+
+#[test]
+fn reinhard() {
+    let reinhard = || vec![PostEffect::ToneMap(ToneMapping::Reinhard)];
+    assert_close(&apply(reinhard(), [1f32, 3f32, 0f32]), &[0.5, 0.75, 0f32]);
+    // 负值当作 0
+    assert_close(&apply(reinhard(), [-1f32, 9f32, 0.25]), &[0f32, 0.9, 0.2]);
+}
+
+#[test]
+fn aces() {
+    let aces = || vec![PostEffect::ToneMap(ToneMapping::Aces)];
+    // x (2.51 x + 0.03) / (x (2.43 x + 0.59) + 0.14)
+    assert_close(
+        &apply(aces(), [0f32, 1f32, 0.18]),
+        &[0f32, 2.54 / 3.16, 0.086724 / 0.324932],
+    );
+    // 很亮时趋向 2.51 / 2.43 输出截断到 1
+    assert_close(&apply(aces(), [1e6, 1e6, 1e6]), &[1f32, 1f32, 1f32]);
+}
+
+#[test]
+fn tone_maps_are_monotonic() {
+    for &mapping in &[ToneMapping::Reinhard, ToneMapping::Aces] {
+        let mut last = -1f32;
+        for i in 0..1000 {
+            let x = i as f32 / 100f32;
+            let y = apply(vec![PostEffect::ToneMap(mapping)], [x, x, x])[0];
+            assert!(y >= last, "{:?} at {}: {} < {}", mapping, x, y, last);
+            last = y;
+        }
+    }
+}
+
+#[test]
+fn srgb_encode() {
+    let srgb = || vec![PostEffect::SrgbEncode];
+    assert_close(
+        &apply(srgb(), [0f32, 0.5, 1f32]),
+        &[0f32, 1.055 * 0.5f32.powf(1f32 / 2.4) - 0.055, 1f32],
+    );
+    // 线性段 以及两段在 0.0031308 处连续
+    assert_close(
+        &apply(srgb(), [0.002, 0.0031308, 2f32]),
+        &[0.02584, 0.04045, 1f32],
+    );
+    let below = apply(srgb(), [0.0031307, 0f32, 0f32])[0];
+    let above = apply(srgb(), [0.0031309, 0f32, 0f32])[0];
+    assert!((above - below).abs() < 1e-5, "{} {}", below, above);
+}
+
+#[test]
+fn standard_chain() {
+    // 单个像素时 FXAA 不改变颜色 结果是 ACES 后再编码
+    let aces = 2.54 / 3.16f32;
+    let expected = 1.055 * aces.powf(1f32 / 2.4) - 0.055;
+    let out = PostProcess::standard().apply(1, 1, &[1f32, 1f32, 1f32]);
+    assert_close(&out, &[expected, expected, expected]);
+}
+
+// 白色图像上描黑边 返回每个像素的灰度 按行存储
+fn outline(width: i32, depth: &[f32], normal: &[Vector3<f32>], line: f32) -> Vec<f32> {
+    let height = depth.len() as i32 / width;
+    let chain = PostProcess::new(vec![PostEffect::Outline(Outline {
+        width: line,
+        ..Default::default()
+    })]);
+    let white = vec![1f32; depth.len() * 3];
+    let geometry = Geometry { depth, normal };
+    let out = chain.apply_with(width, height, &white, Some(&geometry));
+    out.chunks(3).map(|c| c[0]).collect()
+}
+
+fn row(values: &[f32], width: usize, y: usize) -> &[f32] {
+    &values[y * width..(y + 1) * width]
+}
+
+#[test]
+fn outline_at_depth_step() {
+    // 左边近 右边远 轮廓画在近处一侧
+    let depth: Vec<f32> = (0..32)
+        .map(|i| if i % 8 < 4 { 1f32 } else { 5f32 })
+        .collect();
+    let normal = vec![Vector3::z(); 32];
+    let thin = outline(8, &depth, &normal, 1f32);
+    let thick = outline(8, &depth, &normal, 2f32);
+    for y in 0..4 {
+        assert_eq!(row(&thin, 8, y), &[1f32, 1., 1., 0., 1., 1., 1., 1.]);
+        assert_eq!(row(&thick, 8, y), &[1f32, 1., 0.5, 0., 0.5, 1., 1., 1.]);
+    }
+    // 没有几何信息时跳过描边
+    let chain = PostProcess::new(vec![PostEffect::Outline(Default::default())]);
+    assert!(chain.apply(8, 4, &[1f32; 96]).iter().all(|&c| c == 1f32));
+}
+
+#[test]
+fn outline_at_crease() {
+    // 深度相同 法线夹角 90 度 只标记右边的像素
+    let depth = vec![2f32; 32];
+    let normal: Vec<_> = (0..32)
+        .map(|i| {
+            if i % 8 < 4 {
+                Vector3::z()
+            } else {
+                Vector3::x()
+            }
+        })
+        .collect();
+    let out = outline(8, &depth, &normal, 1f32);
+    for y in 0..4 {
+        assert_eq!(row(&out, 8, y), &[1f32, 1., 1., 1., 0., 1., 1., 1.]);
+    }
+}
+
+#[test]
+fn outline_around_an_object() {
+    // 8x8 的背景中间是 4x4 的物体 背景的深度是清除值 法线为 0
+    let inside = |i: usize| (2..6).contains(&(i % 8)) && (2..6).contains(&(i / 8));
+    let depth: Vec<f32> = (0..64)
+        .map(|i| if inside(i) { 1f32 } else { f32::MAX })
+        .collect();
+    let normal: Vec<_> = (0..64)
+        .map(|i| {
+            if inside(i) {
+                Vector3::z()
+            } else {
+                Vector3::zeros()
+            }
+        })
+        .collect();
+    let out = outline(8, &depth, &normal, 1f32);
+    for (i, &c) in out.iter().enumerate() {
+        let (x, y) = (i % 8, i / 8);
+        let border = inside(i) && (x == 2 || x == 5 || y == 2 || y == 5);
+        assert_eq!(c, if border { 0f32 } else { 1f32 }, "({}, {})", x, y);
+    }
+}
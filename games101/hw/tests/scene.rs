@@ -0,0 +1,112 @@
+//! 场景图的世界变换 以及节点带非均匀缩放时法线的变换
+
+mod common;
+
+use common::{identity_rasterizer, white};
+use nalgebra::{Matrix4, Vector3, Vector4};
+use opencv_learn::mesh::Mesh;
+use opencv_learn::rasterizer::Buffers;
+use opencv_learn::scene::{Node, Scene};
+use opencv_learn::triangle::Triangle;
+use std::f32::consts::FRAC_PI_2;
+use std::sync::Arc;
+
+fn translation(x: f32, y: f32, z: f32) -> Matrix4<f32> {
+    Matrix4::new_translation(&Vector3::new(x, y, z))
+}
+
+fn scale(x: f32, y: f32, z: f32) -> Matrix4<f32> {
+    Matrix4::new_nonuniform_scaling(&Vector3::new(x, y, z))
+}
+
+fn rotation_z(angle: f32) -> Matrix4<f32> {
+    Matrix4::new_rotation(Vector3::z() * angle)
+}
+
+#[test]
+fn nested_world_transforms() {
+    let mut scene = Scene::new();
+    let root = scene.add(Node::new("root"), None);
+    let arm = scene.add(Node::new("arm"), Some(root));
+    let other = scene.add(Node::new("other"), None);
+    let hand = scene.add(Node::new("hand"), Some(arm));
+    let sibling = scene.add(Node::new("sibling"), Some(root));
+    scene.set_transform(root, translation(1f32, 0f32, 0f32));
+    scene.set_transform(arm, rotation_z(FRAC_PI_2));
+    scene.set_transform(
+        hand,
+        scale(2f32, 1f32, 1f32) * translation(0f32, 0f32, 3f32),
+    );
+    scene.set_transform(other, scale(5f32, 5f32, 5f32));
+    scene.set_transform(sibling, translation(0f32, 2f32, 0f32));
+
+    assert_eq!(scene.node(hand).parent(), Some(arm));
+    assert_eq!(scene.node(root).children(), &[arm, sibling]);
+    assert_eq!(scene.roots().collect::<Vec<_>>(), vec![root, other]);
+    assert_eq!(scene.find("hand"), Some(hand));
+
+    let worlds = scene.world_transforms();
+    for id in 0..scene.nodes().len() {
+        assert_eq!(worlds[id], scene.world_transform(id), "node {}", id);
+    }
+    // 先在手的空间里变换 再依次变换到手臂和根节点的空间
+    let at = |id: usize, p: Vector3<f32>| (worlds[id] * Vector4::new(p.x, p.y, p.z, 1f32)).xyz();
+    let close = |a: Vector3<f32>, b: Vector3<f32>| (a - b).norm() < 1e-5;
+    // (1, 0, 0) -> (2, 0, 3) -> (0, 2, 3) -> (1, 2, 3)
+    assert!(close(
+        at(hand, Vector3::x()),
+        Vector3::new(1f32, 2f32, 3f32)
+    ));
+    assert!(close(
+        at(sibling, Vector3::zeros()),
+        Vector3::new(1f32, 2f32, 0f32)
+    ));
+    assert!(close(
+        at(other, Vector3::x()),
+        Vector3::new(5f32, 0f32, 0f32)
+    ));
+}
+
+#[test]
+fn normals_under_non_uniform_scale() {
+    // x + z = 0 平面上的三角形 法线 (1, 0, 1) / √2
+    let normal = Vector3::new(1f32, 0f32, 1f32).normalize();
+    let mut t = Triangle::new();
+    let vertices = [
+        Vector3::new(0.1, -0.5, -0.1),
+        Vector3::new(0.1, 0.5, -0.1),
+        Vector3::new(-0.1, 0f32, 0.1),
+    ];
+    for (i, v) in vertices.iter().enumerate() {
+        t.set_vertex(i, Vector4::new(v.x, v.y, v.z, 1f32));
+        t.set_normal(i, normal);
+    }
+    let mut mesh = Mesh::default();
+    mesh.triangles.push(t);
+
+    let mut scene = Scene::new();
+    let root = scene.add(Node::new("root"), None);
+    let arm = scene.add(Node::new("arm"), Some(root));
+    let leaf = scene.add(Node::with_mesh("leaf", Arc::new(mesh)), Some(arm));
+    scene.set_transform(root, translation(0.1, 0.1, 0f32));
+    scene.set_transform(arm, rotation_z(FRAC_PI_2));
+    scene.set_transform(leaf, scale(4f32, 1f32, 1f32));
+
+    let mut r = identity_rasterizer(32, 32);
+    r.set_fragment_shader(&white);
+    r.set_normal_buffer(true);
+    r.clear(Buffers::COLOR | Buffers::DEPTH);
+    r.draw_scene(&scene);
+
+    // 逆转置为 R S^-1 先把 x 分量除以 4 再绕 z 轴转 90 度
+    let expected = Vector3::new(0f32, 0.25, 1f32).normalize();
+    let covered: Vec<_> = r
+        .normal_buffer()
+        .iter()
+        .filter(|n| n.norm() > 0f32)
+        .collect();
+    assert!(covered.len() > 20, "{} pixels", covered.len());
+    for n in covered {
+        assert!((n - expected).norm() < 1e-5, "{:?}", n);
+    }
+}
@@ -0,0 +1,152 @@
+//! 屏幕空间反射 hi-z 步进不会跳过细的物体 反射在雾之前合成
+
+use nalgebra::{Vector3, Vector4};
+use opencv_learn::fog::{Fog, FogMode};
+use opencv_learn::material::Material;
+use opencv_learn::mesh::Mesh;
+use opencv_learn::rasterizer::{Buffers, Rasterizer};
+use opencv_learn::shader::FragmentShaderPayload;
+use opencv_learn::ssr::Ssr;
+use opencv_learn::triangle::Triangle;
+use std::sync::Arc;
+
+const SIZE: i32 = 96;
+
+// 网格的顶点颜色不参与着色 颜色取自材质的 kd
+fn fogged_color(payload: &FragmentShaderPayload) -> Vector3<f32> {
+    let color = payload.material.map_or(Vector3::zeros(), |m| m.kd);
+    opencv_learn::apply_fog(payload, color)
+}
+
+// 分成 n x n 个格子 片元的观察空间位置在屏幕空间插值 格子小时误差才小
+fn quad(corners: [Vector3<f32>; 4], n: usize, material: Material) -> Mesh {
+    let mut mesh = Mesh {
+        material: Some(Arc::new(material)),
+        ..Default::default()
+    };
+    let normal = (corners[1] - corners[0])
+        .cross(&(corners[3] - corners[0]))
+        .normalize();
+    let at = |i: usize, j: usize| {
+        let (s, t) = (i as f32 / n as f32, j as f32 / n as f32);
+        corners[0] + (corners[1] - corners[0]) * s + (corners[3] - corners[0]) * t
+    };
+    for j in 0..n {
+        for i in 0..n {
+            let cell = [at(i, j), at(i + 1, j), at(i + 1, j + 1), at(i, j + 1)];
+            for face in &[[0, 1, 2], [0, 2, 3]] {
+                let mut t = Triangle::new();
+                for (k, &c) in face.iter().enumerate() {
+                    let v = cell[c];
+                    t.set_vertex(k, Vector4::new(v.x, v.y, v.z, 1f32));
+                    t.set_normal(k, normal);
+                }
+                mesh.triangles.push(t);
+            }
+        }
+    }
+    mesh
+}
+
+// 黑色的镜面地板 远处横着一根细的白色横杆
+fn scene() -> Vec<Mesh> {
+    let mut mirror = Material::default();
+    mirror.ks = Vector3::from_element(1f32);
+    let mut white = Material::default();
+    white.kd = Vector3::from_element(1f32);
+    let floor = quad(
+        [
+            Vector3::new(-3f32, -1f32, 0f32),
+            Vector3::new(3f32, -1f32, 0f32),
+            Vector3::new(3f32, -1f32, -10f32),
+            Vector3::new(-3f32, -1f32, -10f32),
+        ],
+        32,
+        mirror,
+    );
+    let bar = quad(
+        [
+            Vector3::new(-3f32, -0.7, -4f32),
+            Vector3::new(3f32, -0.7, -4f32),
+            Vector3::new(3f32, -0.6, -4f32),
+            Vector3::new(-3f32, -0.6, -4f32),
+        ],
+        1,
+        white,
+    );
+    vec![floor, bar]
+}
+
+fn render(ssr: Option<Ssr>, fog: Option<Fog>) -> Vec<f32> {
+    let mut r = Rasterizer::new(SIZE, SIZE);
+    r.set_model(&nalgebra::Matrix4::identity());
+    r.set_view(&opencv_learn::get_view_matrix(Vector3::new(
+        0f32, 0f32, 3f32,
+    )));
+    r.set_projection(&opencv_learn::get_projection_matrix(
+        45f32, 1f32, 0.1, 50f32,
+    ));
+    r.set_fragment_shader(&fogged_color);
+    r.set_deferred(true);
+    r.set_ssr(ssr);
+    r.set_fog(fog);
+    r.clear(Buffers::COLOR | Buffers::DEPTH);
+    r.draw_meshes(&scene());
+    r.frame_buffer()
+}
+
+// 加上反射后变亮的像素
+fn reflected(ssr: Ssr, fog: Option<Fog>) -> Vec<bool> {
+    let without = render(None, fog);
+    let with = render(Some(ssr), fog);
+    with.chunks(3)
+        .zip(without.chunks(3))
+        .map(|(a, b)| a[0] > b[0] + 1e-3)
+        .collect()
+}
+
+// 步长较大时粗层的一步在屏幕上跨过横杆 需要退回细化才能找到
+#[test]
+fn hi_z_finds_thin_geometry() {
+    let linear = reflected(
+        Ssr {
+            step: 0.25,
+            hi_z_levels: 0,
+            max_steps: 4096,
+            ..Default::default()
+        },
+        None,
+    );
+    let hi_z = reflected(
+        Ssr {
+            step: 0.25,
+            ..Default::default()
+        },
+        None,
+    );
+    let count = |v: &[bool]| v.iter().filter(|&&b| b).count();
+    assert!(count(&linear) > 20, "{} reflected pixels", count(&linear));
+    let both = linear.iter().zip(&hi_z).filter(|(a, b)| **a && **b).count();
+    assert!(
+        both * 10 >= count(&linear) * 9,
+        "hi-z found {} of {} reflected pixels",
+        both,
+        count(&linear)
+    );
+}
+
+#[test]
+fn reflections_are_fogged() {
+    let fog = Fog::new(
+        FogMode::Linear {
+            start: 0f32,
+            end: 1f32,
+        },
+        Vector3::from_element(0.5),
+        0f32,
+    );
+    // 整个场景都在雾里 加上的反射也应该完全被雾挡住
+    let without = render(None, Some(fog));
+    let with = render(Some(Default::default()), Some(fog));
+    assert!(without.iter().zip(&with).all(|(a, b)| (a - b).abs() < 1e-6));
+}
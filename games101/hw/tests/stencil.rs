@@ -0,0 +1,173 @@
+//! 模板测试的比较函数和三种情况下的更新操作 和 OpenGL 的定义一致
+
+mod common;
+
+use common::{identity_rasterizer, white};
+use nalgebra::Vector4;
+use opencv_learn::rasterizer::{
+    Buffers, ClearValues, CompareFunc, Rasterizer, StencilOp, StencilState,
+};
+use opencv_learn::triangle::Triangle;
+
+const SIZE: i32 = 8;
+
+// NDC 中 x 在 [x0, x1] 之间的矩形 z 越小越近
+fn rect(x0: f32, x1: f32, z: f32) -> Vec<Triangle> {
+    let corners = [(x0, -1f32), (x1, -1f32), (x1, 1f32), (x0, 1f32)];
+    [[0, 1, 2], [0, 2, 3]]
+        .iter()
+        .map(|face| {
+            let mut t = Triangle::new();
+            for (i, &c) in face.iter().enumerate() {
+                let (x, y) = corners[c];
+                t.set_vertex(i, Vector4::new(x, y, z, 1f32));
+            }
+            t
+        })
+        .collect()
+}
+
+fn rasterizer(clear_stencil: u8) -> Rasterizer<'static> {
+    let mut r = identity_rasterizer(SIZE, SIZE);
+    r.set_fragment_shader(&white);
+    r.set_clear_values(ClearValues {
+        stencil: clear_stencil,
+        ..Default::default()
+    });
+    r.clear(Buffers::COLOR | Buffers::DEPTH | Buffers::STENCIL);
+    r
+}
+
+fn draw(r: &mut Rasterizer, triangles: &[Triangle]) {
+    r.draw_triangles(&triangles.iter().collect());
+}
+
+// 整个屏幕都是同一个值时返回它
+fn uniform(values: &[u8]) -> u8 {
+    assert!(values.iter().all(|&v| v == values[0]), "{:?}", values);
+    values[0]
+}
+
+#[test]
+fn stencil_ops() {
+    use StencilOp::*;
+    let cases = [
+        (5, Keep, 5),
+        (5, Zero, 0),
+        (5, Replace, 9),
+        (5, IncrementClamp, 6),
+        (255, IncrementClamp, 255),
+        (5, DecrementClamp, 4),
+        (0, DecrementClamp, 0),
+        (5, Invert, 250),
+        (255, IncrementWrap, 0),
+        (0, DecrementWrap, 255),
+    ];
+    for &(clear, op, expected) in cases.iter() {
+        let mut r = rasterizer(clear);
+        r.set_stencil(Some(StencilState {
+            reference: 9,
+            pass: op,
+            ..Default::default()
+        }));
+        draw(&mut r, &rect(-1f32, 1f32, 0f32));
+        assert_eq!(
+            uniform(&r.stencil_buffer()),
+            expected,
+            "{:?} on {}",
+            op,
+            clear
+        );
+    }
+}
+
+#[test]
+fn compare_funcs() {
+    use CompareFunc::*;
+    // 模板值为 4 参考值分别为 3 4 5 时是否通过
+    let cases = [
+        (Never, [false, false, false]),
+        (Less, [true, false, false]),
+        (LessEqual, [true, true, false]),
+        (Greater, [false, false, true]),
+        (GreaterEqual, [false, true, true]),
+        (Equal, [false, true, false]),
+        (NotEqual, [true, false, true]),
+        (Always, [true, true, true]),
+    ];
+    for &(func, passes) in cases.iter() {
+        for (&reference, &pass) in [3u8, 4, 5].iter().zip(passes.iter()) {
+            let mut r = rasterizer(4);
+            r.set_stencil(Some(StencilState {
+                func,
+                reference,
+                fail: StencilOp::Zero,
+                pass: StencilOp::IncrementClamp,
+                ..Default::default()
+            }));
+            draw(&mut r, &rect(-1f32, 1f32, 0f32));
+            let what = format!("{:?} with reference {}", func, reference);
+            assert_eq!(
+                uniform(&r.stencil_buffer()),
+                if pass { 5 } else { 0 },
+                "{}",
+                what
+            );
+            // 模板测试失败的片元不写颜色
+            let color = r.frame_buffer();
+            assert!(
+                color.iter().all(|&c| c == if pass { 1f32 } else { 0f32 }),
+                "{}",
+                what
+            );
+        }
+    }
+}
+
+#[test]
+fn masks() {
+    let mut r = rasterizer(0b1010_0110);
+    r.set_stencil(Some(StencilState {
+        func: CompareFunc::Equal,
+        reference: 0b0101_0110,
+        read_mask: 0x0f,
+        write_mask: 0xf0,
+        pass: StencilOp::Invert,
+        ..Default::default()
+    }));
+    draw(&mut r, &rect(-1f32, 1f32, 0f32));
+    // 只比较低 4 位 只改写高 4 位
+    assert_eq!(uniform(&r.stencil_buffer()), 0b0101_0110);
+}
+
+#[test]
+fn depth_fail() {
+    let mut r = rasterizer(0);
+    // 先在左半边画一个近处的矩形 不用模板
+    draw(&mut r, &rect(-1f32, 0f32, -0.5));
+    r.set_stencil(Some(StencilState {
+        reference: 7,
+        fail: StencilOp::Zero,
+        depth_fail: StencilOp::Replace,
+        pass: StencilOp::IncrementClamp,
+        ..Default::default()
+    }));
+    // 远处的矩形在左半边深度测试失败 右半边通过
+    draw(&mut r, &rect(-1f32, 1f32, 0.5));
+    let stencil = r.stencil_buffer();
+    let count = |v: u8| stencil.iter().filter(|&&s| s == v).count();
+    let half = (SIZE * SIZE / 2) as usize;
+    assert_eq!((count(7), count(1)), (half, half), "{:?}", stencil);
+
+    // 模板测试失败时不管深度 左右两边都清零
+    r.set_stencil(Some(StencilState {
+        func: CompareFunc::Never,
+        fail: StencilOp::Zero,
+        depth_fail: StencilOp::Replace,
+        pass: StencilOp::Replace,
+        reference: 3,
+        ..Default::default()
+    }));
+    draw(&mut r, &rect(-1f32, 1f32, 0.5));
+    assert_eq!(uniform(&r.stencil_buffer()), 0);
+}
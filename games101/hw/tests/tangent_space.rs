@@ -0,0 +1,68 @@
+//! 没有切线的片元用法线推出 TBN 法线沿任意方向都不会得到 NaN
+
+use nalgebra::{Vector2, Vector3};
+use opencv_learn::shader::FragmentShaderPayload;
+use opencv_learn::texture::Texture;
+use opencv_learn::{
+    bump_fragment_shader, default_lights, displacement_fragment_shader, normal_map_fragment_shader,
+    phone_fragment_shader,
+};
+
+fn checker() -> Texture {
+    let pixels = (0..16)
+        .map(|i| Vector3::from_element(((i + i / 4) % 2) as f32 * 255f32))
+        .collect();
+    Texture::from_pixels(4, 4, pixels)
+}
+
+#[test]
+fn normals_along_every_axis() {
+    let texture = checker();
+    let shaders: [(&str, fn(&FragmentShaderPayload) -> Vector3<f32>); 3] = [
+        ("bump", bump_fragment_shader),
+        ("displacement", displacement_fragment_shader),
+        ("normal_map", normal_map_fragment_shader),
+    ];
+    let normals = [
+        Vector3::x(),
+        -Vector3::x(),
+        Vector3::y(),
+        -Vector3::y(),
+        Vector3::z(),
+        -Vector3::z(),
+    ];
+    for normal in normals.iter() {
+        let mut payload = FragmentShaderPayload::new(
+            Vector3::from_element(0.5),
+            *normal,
+            Vector2::new(0.3, 0.6),
+            Some(&texture),
+        );
+        payload.view_pos = Vector3::new(0f32, 0f32, -2f32);
+        for (name, shader) in shaders.iter() {
+            let color = shader(&payload);
+            assert!(
+                color.iter().all(|c| c.is_finite()),
+                "{} with normal {:?}: {:?}",
+                name,
+                normal,
+                color
+            );
+        }
+    }
+}
+
+#[test]
+fn empty_lights_use_the_defaults() {
+    let lights = default_lights();
+    let mut payload = FragmentShaderPayload::new(
+        Vector3::new(0.8, 0.4, 0.2),
+        Vector3::new(0.3, 0.8, 0.5).normalize(),
+        Vector2::zeros(),
+        None,
+    );
+    payload.view_pos = Vector3::new(0.5, -0.5, -2f32);
+    let implicit = phone_fragment_shader(&payload);
+    payload.lights = &lights;
+    assert_eq!(implicit, phone_fragment_shader(&payload));
+}
@@ -0,0 +1,73 @@
+//! 共享一条边的两个三角形细分程度不同 共享边上的顶点仍然逐位相同 不会产生裂缝
+
+use nalgebra::{Vector3, Vector4};
+use opencv_learn::tessellation::{tessellate, Tessellation};
+use opencv_learn::triangle::Triangle;
+
+const SIZE: i32 = 256;
+
+fn triangle(vertices: [Vector3<f32>; 3]) -> Triangle {
+    let mut t = Triangle::new();
+    for (i, v) in vertices.iter().enumerate() {
+        t.set_vertex(i, Vector4::new(v.x, v.y, v.z, 1f32));
+        t.set_normal(i, Vector3::z());
+    }
+    t
+}
+
+// 细分结果中落在 a b 这条线段上的边的端点 按位保存
+fn edge_vertices(triangles: &[Triangle], a: Vector3<f32>, b: Vector3<f32>) -> Vec<[u32; 4]> {
+    let on_edge = |p: &Vector4<f32>| {
+        let p = p.xyz();
+        (p - a).cross(&(b - a)).norm() < 1e-4 * (b - a).norm_squared()
+    };
+    let mut ret = vec![];
+    for t in triangles {
+        for i in 0..3 {
+            let (p, q) = (&t.v[i], &t.v[(i + 1) % 3]);
+            if on_edge(p) && on_edge(q) {
+                ret.push(p.map(f32::to_bits).into());
+                ret.push(q.map(f32::to_bits).into());
+            }
+        }
+    }
+    ret.sort();
+    ret.dedup();
+    ret
+}
+
+#[test]
+fn shared_edge_matches_bit_for_bit() {
+    let view = opencv_learn::get_view_matrix(Vector3::new(0f32, 0f32, 5f32));
+    let projection = opencv_learn::get_projection_matrix(45f32, 1f32, 0.1, 50f32);
+    let mvp = projection * view;
+    let config = Tessellation {
+        target_edge_length: 6f32,
+        ..Default::default()
+    };
+
+    // 共享边从近处斜着伸向远处 一侧的三角形很大 另一侧很窄
+    let (a, b) = (
+        Vector3::new(-1f32, -1f32, 2f32),
+        Vector3::new(0.7, 1.3, -4f32),
+    );
+    let wide = triangle([a, b, Vector3::new(-3f32, 2f32, -1f32)]);
+    let narrow = triangle([b, a, Vector3::new(0.2, -0.3, -0.5)]);
+    let wide = tessellate(&wide, &mvp, SIZE, SIZE, &config);
+    let narrow = tessellate(&narrow, &mvp, SIZE, SIZE, &config);
+    assert!(
+        wide.len() > 2 * narrow.len(),
+        "{} and {} triangles",
+        wide.len(),
+        narrow.len()
+    );
+
+    let shared = edge_vertices(&wide, a, b);
+    // 共享边本身也被细分了 两端之外还有中点
+    assert!(
+        shared.len() > 4,
+        "{} vertices on the shared edge",
+        shared.len()
+    );
+    assert_eq!(shared, edge_vertices(&narrow, a, b));
+}
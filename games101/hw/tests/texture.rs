@@ -0,0 +1,98 @@
+//! 纹理采样在任意 uv 和所有采样器设置下都不会 panic 结果在纹理的颜色范围内
+
+use nalgebra::{Vector2, Vector3};
+use opencv_learn::texture::{FilterMode, Sampler, Texture, WrapMode};
+
+const WRAPS: [WrapMode; 4] = [
+    WrapMode::Repeat,
+    WrapMode::ClampToEdge,
+    WrapMode::MirroredRepeat,
+    WrapMode::ClampToBorder,
+];
+const FILTERS: [FilterMode; 4] = [
+    FilterMode::Nearest,
+    FilterMode::Bilinear,
+    FilterMode::Trilinear,
+    FilterMode::Anisotropic,
+];
+const COORDS: [f32; 4] = [1.0, -0.5, 1e9, f32::NAN];
+
+// 宽高都是奇数 颜色在 [0, 255] 之间
+fn gradient(width: i32, height: i32) -> Texture {
+    let pixels = (0..width * height)
+        .map(|i| {
+            let (x, y) = (i % width, i / width);
+            Vector3::new(
+                255f32 * x as f32 / (width - 1) as f32,
+                255f32 * y as f32 / (height - 1) as f32,
+                ((x + y) % 2) as f32 * 255f32,
+            )
+        })
+        .collect();
+    Texture::from_pixels(width, height, pixels)
+}
+
+fn assert_in_range(color: Vector3<f32>, what: &str) {
+    assert!(
+        color.iter().all(|c| *c >= 0f32 && *c <= 255f32),
+        "{}: {:?}",
+        what,
+        color
+    );
+}
+
+#[test]
+fn any_uv_with_every_sampler() {
+    let mut texture = gradient(5, 3);
+    let derivatives = [
+        (Vector2::zeros(), Vector2::zeros()),
+        (Vector2::new(0.6, 0f32), Vector2::new(0f32, 0.05)),
+    ];
+    for &wrap in &WRAPS {
+        for &filter in &FILTERS {
+            let sampler = Sampler {
+                border_color: Vector3::new(10f32, 20f32, 30f32),
+                max_anisotropy: 4,
+                ..Sampler::new(wrap, filter)
+            };
+            texture.set_sampler(sampler);
+            for &u in &COORDS {
+                for &v in &COORDS {
+                    let what = format!("{:?} {:?} uv ({}, {})", wrap, filter, u, v);
+                    let uv = Vector2::new(u, v);
+                    for (dx, dy) in &derivatives {
+                        assert_in_range(texture.sample_with(&sampler, uv, *dx, *dy), &what);
+                    }
+                    for &lod in &[0f32, 1.5, 100f32] {
+                        assert_in_range(texture.sample_lod(uv, lod), &what);
+                    }
+                    assert_in_range(texture.get_color(u, v), &what);
+                    assert_in_range(texture.get_color_bilinear(u, v), &what);
+                }
+            }
+            for &(x, y) in &[(5, 3), (-1, -4), (i32::MAX, i32::MIN)] {
+                let what = format!("{:?} texel ({}, {})", wrap, x, y);
+                assert_in_range(texture.get_color_raw(x, y), &what);
+            }
+        }
+    }
+}
+
+// 奇数尺寸的 mipmap 向上取整 最后一列也参与平均
+#[test]
+fn odd_mip_levels_keep_the_last_column() {
+    let pixels = vec![
+        Vector3::zeros(),
+        Vector3::zeros(),
+        Vector3::new(90f32, 90f32, 90f32),
+    ];
+    let texture = Texture::from_pixels(3, 1, pixels);
+    // 3x1 2x1 1x1
+    assert_eq!(texture.mip_levels(), 3);
+    let top = texture.sample_lod(Vector2::new(0.5, 0.5), 2f32);
+    assert!(
+        (top - Vector3::new(45f32, 45f32, 45f32)).magnitude() < 1e-4,
+        "{:?}",
+        top
+    );
+}
@@ -0,0 +1,121 @@
+//! 分块并行光栅化的结果和线程数无关 和单线程逐个提交三角形完全相同
+
+mod common;
+
+use common::{identity_rasterizer, Lcg};
+use nalgebra::{Vector3, Vector4};
+use opencv_learn::rasterizer::{BlendState, Buffers, Rasterizer};
+use opencv_learn::shader::FragmentShaderPayload;
+use opencv_learn::triangle::Triangle;
+
+// 宽高都不是分块大小的整数倍
+const WIDTH: i32 = 150;
+const HEIGHT: i32 = 100;
+
+fn vertex_color(payload: &FragmentShaderPayload) -> Vector3<f32> {
+    payload.color
+}
+
+fn half_transparent(payload: &FragmentShaderPayload) -> Vector4<f32> {
+    Vector4::new(payload.color.x, payload.color.y, payload.color.z, 0.5)
+}
+
+// 互相重叠的随机三角形 有些超出屏幕 深度有重复的值
+fn random_triangles(count: usize, seed: u64) -> Vec<Triangle> {
+    let mut rng = Lcg(seed);
+    (0..count)
+        .map(|_| {
+            let mut t = Triangle::new();
+            let (cx, cy) = (rng.signed(1.1), rng.signed(1.1));
+            let z = (rng.signed(4f32)).round() / 4f32;
+            for j in 0..3 {
+                t.set_vertex(
+                    j,
+                    Vector4::new(cx + rng.signed(0.5), cy + rng.signed(0.5), z, 1f32),
+                );
+                t.set_color(
+                    j,
+                    rng.next() * 255f32,
+                    rng.next() * 255f32,
+                    rng.next() * 255f32,
+                )
+                .unwrap();
+            }
+            t
+        })
+        .collect()
+}
+
+fn render(threads: usize, setup: impl Fn(&mut Rasterizer)) -> Vec<f32> {
+    let mut r = identity_rasterizer(WIDTH, HEIGHT);
+    r.set_fragment_shader(&vertex_color);
+    r.set_threads(threads);
+    setup(&mut r);
+    r.clear(Buffers::COLOR | Buffers::DEPTH);
+
+    let triangles = random_triangles(300, 7);
+    r.draw_triangles(&triangles.iter().collect());
+    r.frame_buffer()
+}
+
+fn assert_same_for_all_thread_counts(setup: impl Fn(&mut Rasterizer)) {
+    let serial = render(1, &setup);
+    assert!(serial.iter().any(|&c| c > 0f32), "nothing was drawn");
+    for &threads in &[2, 3, 8] {
+        assert!(
+            render(threads, &setup) == serial,
+            "{} threads differ from one thread",
+            threads
+        );
+    }
+}
+
+#[test]
+fn depth_tested() {
+    assert_same_for_all_thread_counts(|_| {});
+}
+
+#[test]
+fn depth_tested_msaa() {
+    assert_same_for_all_thread_counts(|r| r.set_msaa(4));
+}
+
+// 混合的结果依赖提交顺序
+#[test]
+fn alpha_blended() {
+    assert_same_for_all_thread_counts(|r| {
+        r.set_fragment_shader_rgba(&half_transparent);
+        r.set_blend(Some(BlendState::alpha_blending()));
+        r.set_depth_write(false);
+    });
+}
+
+// rasterize_triangle 每次提交一个屏幕空间的三角形
+#[test]
+fn single_triangles() {
+    let render = |threads: usize| {
+        let mut r = Rasterizer::new(WIDTH, HEIGHT);
+        r.set_fragment_shader(&vertex_color);
+        r.set_threads(threads);
+        r.clear(Buffers::COLOR | Buffers::DEPTH);
+        let mut rng = Lcg(11);
+        for _ in 0..100 {
+            let mut t = Triangle::new();
+            let (cx, cy) = (rng.next() * WIDTH as f32, rng.next() * HEIGHT as f32);
+            let z = rng.next().round();
+            for j in 0..3 {
+                t.set_vertex(
+                    j,
+                    Vector4::new(cx + rng.signed(40f32), cy + rng.signed(40f32), z, 1f32),
+                );
+                t.set_color(j, rng.next() * 255f32, rng.next() * 255f32, 0f32)
+                    .unwrap();
+            }
+            r.rasterize_triangle(&t, &[Vector3::zeros(); 3]);
+        }
+        r.frame_buffer()
+    };
+    let serial = render(1);
+    assert!(serial.iter().any(|&c| c > 0f32), "nothing was drawn");
+    assert!(render(8) == serial, "8 threads differ from one thread");
+}
@@ -0,0 +1,64 @@
+//! 焊接过的网格中共享的顶点在一次绘制中只变换一次
+
+mod common;
+
+use common::{identity_rasterizer, white};
+use nalgebra::Vector3;
+use opencv_learn::rasterizer::{Buffers, Primitive, Rasterizer};
+use opencv_learn::vertex_buffer::{Instance, VertexBuffer};
+
+// n x n 个格子的网格 相邻格子共享顶点 (n + 1)^2 个顶点 6n^2 个下标
+fn welded_grid(n: u32) -> (Vec<Vector3<f32>>, Vec<u32>) {
+    let mut vertices = vec![];
+    for j in 0..=n {
+        for i in 0..=n {
+            vertices.push(Vector3::new(
+                2f32 * i as f32 / n as f32 - 1f32,
+                2f32 * j as f32 / n as f32 - 1f32,
+                0f32,
+            ));
+        }
+    }
+    let at = |i: u32, j: u32| j * (n + 1) + i;
+    let mut indices = vec![];
+    for j in 0..n {
+        for i in 0..n {
+            let (a, b, c, d) = (at(i, j), at(i + 1, j), at(i + 1, j + 1), at(i, j + 1));
+            indices.extend(&[a, b, c, a, c, d]);
+        }
+    }
+    (vertices, indices)
+}
+
+fn rasterizer() -> Rasterizer<'static> {
+    let mut r = identity_rasterizer(32, 32);
+    r.set_fragment_shader(&white);
+    r.clear(Buffers::COLOR | Buffers::DEPTH);
+    r
+}
+
+#[test]
+fn shared_vertices_transformed_once() {
+    let (vertices, indices) = welded_grid(8);
+    let (vertex_count, index_count) = (vertices.len(), indices.len());
+    let mut r = rasterizer();
+    let vert_buf = r.load_vertices(VertexBuffer::new(vertices));
+    let ind_buf = r.load_indices(indices);
+    let stats = r.draw_indexed(vert_buf, ind_buf, Primitive::Triangle, None);
+    assert_eq!(stats.transformed, vertex_count);
+    assert_eq!(stats.hits, index_count - vertex_count);
+}
+
+// 每个实例重新变换一遍 统计为所有实例之和
+#[test]
+fn instanced_cache_per_instance() {
+    let (vertices, indices) = welded_grid(5);
+    let (vertex_count, index_count) = (vertices.len(), indices.len());
+    let mut r = rasterizer();
+    let vert_buf = r.load_vertices(VertexBuffer::new(vertices));
+    let ind_buf = r.load_indices(indices);
+    let inst_buf = r.load_instances(vec![Instance::default(); 3]);
+    let stats = r.draw_instanced(vert_buf, ind_buf, inst_buf, Primitive::Triangle, None);
+    assert_eq!(stats.transformed, 3 * vertex_count);
+    assert_eq!(stats.hits, 3 * (index_count - vertex_count));
+}